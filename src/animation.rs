@@ -0,0 +1,283 @@
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use duct::cmd;
+use serde::{Deserialize, Serialize};
+use xcap::image::{
+    RgbaImage,
+    imageops::{self, FilterType},
+};
+
+use crate::{
+    config::{AnimationConfig, ScreenshotConfig},
+    crop,
+    encode::EncodeError,
+    process::{Process, find_executable},
+};
+
+/// Halvings of the frame size tried to get under the file size cap
+const MAX_SHRINKS: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnimationFormat {
+    #[default]
+    Webp,
+    Gif,
+    WebmVp9,
+    WebmAv1,
+}
+
+impl AnimationFormat {
+    pub const ALL: [AnimationFormat; 4] = [
+        AnimationFormat::Webp,
+        AnimationFormat::Gif,
+        AnimationFormat::WebmVp9,
+        AnimationFormat::WebmAv1,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Webp => "webp",
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::WebmVp9 | AnimationFormat::WebmAv1 => "webm",
+        }
+    }
+
+    /// Make sure ffmpeg is installed, so a recording isn't lost when it's saved
+    pub fn check_tool(&self) -> Result<(), EncodeError> {
+        match find_executable("ffmpeg") {
+            Some(_) => Ok(()),
+            None => Err(EncodeError::MissingTool("ffmpeg")),
+        }
+    }
+
+    /// ffmpeg output options of the format
+    fn codec_args(&self) -> Vec<&'static str> {
+        match self {
+            AnimationFormat::Webp => {
+                vec![
+                    "-c:v",
+                    "libwebp_anim",
+                    "-lossless",
+                    "0",
+                    "-quality",
+                    "75",
+                    "-loop",
+                    "0",
+                ]
+            }
+            // A palette generated from the frames looks much better than the default one
+            AnimationFormat::Gif => vec![
+                "-filter_complex",
+                "split[a][b];[a]palettegen[p];[b][p]paletteuse",
+                "-loop",
+                "0",
+            ],
+            AnimationFormat::WebmVp9 => vec![
+                "-c:v",
+                "libvpx-vp9",
+                "-b:v",
+                "0",
+                "-crf",
+                "40",
+                "-pix_fmt",
+                "yuv420p",
+            ],
+            AnimationFormat::WebmAv1 => {
+                vec!["-c:v", "libsvtav1", "-crf", "45", "-pix_fmt", "yuv420p"]
+            }
+        }
+    }
+}
+
+impl Display for AnimationFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AnimationFormat::Webp => "Animated WebP",
+            AnimationFormat::Gif => "GIF",
+            AnimationFormat::WebmVp9 => "WebM (VP9)",
+            AnimationFormat::WebmAv1 => "WebM (AV1)",
+        })
+    }
+}
+
+/// Samples the window at the configured frame rate during a recording, cropped and scaled down
+/// as they're taken to keep memory in check.
+pub struct AnimationGrabber {
+    frames: Arc<Mutex<Vec<RgbaImage>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AnimationGrabber {
+    pub fn start(
+        process: &Process,
+        config: &AnimationConfig,
+        screenshot: &ScreenshotConfig,
+    ) -> AnimationGrabber {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let interval = Duration::from_secs_f64(1.0 / config.fps.max(1) as f64);
+        let max_frames = (config.max_secs * config.fps) as usize;
+
+        let thread = std::thread::spawn({
+            let process = process.clone();
+            let screenshot = screenshot.clone();
+            let max_width = config.max_width;
+            let frames = frames.clone();
+            let stop = stop.clone();
+            move || {
+                let mut size = None;
+                let mut next = Instant::now();
+                // Past the duration cap, the rest of the recording isn't animated
+                while !stop.load(Ordering::Relaxed) && frames.lock().unwrap().len() < max_frames {
+                    if Instant::now() < next {
+                        std::thread::sleep(Duration::from_millis(5));
+                        continue;
+                    }
+                    next += interval;
+                    let frame = match process.capture_image() {
+                        Ok(frame) => crop::apply(frame, process.game(), &screenshot),
+                        Err(e) => {
+                            eprintln!("Failed to capture an animation frame: {}", e);
+                            continue;
+                        }
+                    };
+                    // Every frame takes the size of the first, in case the window is resized
+                    let (width, height) =
+                        *size.get_or_insert_with(|| fit(frame.dimensions(), max_width));
+                    let frame = imageops::resize(&frame, width, height, FilterType::Triangle);
+                    frames.lock().unwrap().push(frame);
+                }
+            }
+        });
+
+        AnimationGrabber {
+            frames,
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop sampling and return the frames
+    pub fn finish(mut self) -> Vec<RgbaImage> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        std::mem::take(&mut *self.frames.lock().unwrap())
+    }
+}
+
+impl Drop for AnimationGrabber {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Scale a size down to a maximum width, rounded to even dimensions as video codecs need
+fn fit((width, height): (u32, u32), max_width: u32) -> (u32, u32) {
+    let scale = (max_width as f64 / width as f64).min(1.0);
+    let even = |v: f64| ((v as u32) & !1).max(2);
+    (even(width as f64 * scale), even(height as f64 * scale))
+}
+
+/// Encode the frames with ffmpeg, halving their size until the file fits the size cap
+pub fn encode(
+    mut frames: Vec<RgbaImage>,
+    path: &Path,
+    config: &AnimationConfig,
+) -> Result<(), EncodeError> {
+    let max_bytes = u64::from(config.max_size_kb) * 1024;
+    let mut shrinks = 0;
+    loop {
+        encode_once(&frames, path, config)?;
+        let size = std::fs::metadata(path)?.len();
+        if size <= max_bytes {
+            return Ok(());
+        }
+        let Some((width, height)) = frames.first().map(|f| f.dimensions()) else {
+            return Ok(());
+        };
+        if shrinks == MAX_SHRINKS || width <= 64 {
+            eprintln!(
+                "Animation {} is {} KiB, over the {} KiB cap",
+                path.display(),
+                size / 1024,
+                config.max_size_kb
+            );
+            return Ok(());
+        }
+        let (width, height) = fit((width, height), width / 2);
+        frames = frames
+            .iter()
+            .map(|f| imageops::resize(f, width, height, FilterType::Triangle))
+            .collect();
+        shrinks += 1;
+    }
+}
+
+fn encode_once(
+    frames: &[RgbaImage],
+    path: &Path,
+    config: &AnimationConfig,
+) -> Result<(), EncodeError> {
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+    let (width, height) = first.dimensions();
+    let raw: Vec<u8> = frames.iter().flat_map(|f| f.as_raw()).copied().collect();
+
+    let mut args: Vec<OsString> = [
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-y",
+        "-f",
+        "rawvideo",
+        "-pix_fmt",
+        "rgba",
+        "-s",
+        &format!("{}x{}", width, height),
+        "-r",
+        &config.fps.to_string(),
+        "-i",
+        "-",
+        "-an",
+    ]
+    .into_iter()
+    .map(OsString::from)
+    .collect();
+    args.extend(config.format.codec_args().into_iter().map(OsString::from));
+    args.push(path.into());
+
+    let tool = "ffmpeg";
+    let output = cmd(tool, args)
+        .stdin_bytes(raw)
+        .stdout_null()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => EncodeError::MissingTool(tool),
+            _ => EncodeError::IO(e),
+        })?;
+
+    if !output.status.success() {
+        return Err(EncodeError::Failed {
+            tool,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use thiserror::Error;
+
+use crate::{clipboard::html_escape, config::AnkiConfig, record::RecordedData};
+
+/// AnkiConnect API version the requests are written for
+const API_VERSION: u32 = 6;
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Search for the last added note, whose newest note has the largest ID since IDs are creation
+/// timestamps. Older notes aren't listed so large collections stay fast to search.
+const LAST_ADDED_QUERY: &str = "added:30";
+
+#[derive(Error, Debug)]
+pub enum AnkiError {
+    #[error("Failed to reach AnkiConnect at {url}, is Anki running? ({source})")]
+    Connect { url: String, source: ureq::Error },
+    #[error("AnkiConnect `{action}` failed: {message}")]
+    Api {
+        action: &'static str,
+        message: String,
+    },
+    #[error("No note matches {0:?}")]
+    NoNote(String),
+}
+
+/// Note a record is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteTarget {
+    /// The most recently added note, if it was added in the last 30 days
+    LastAdded,
+    Id(u64),
+}
+
+/// Client of the AnkiConnect add-on's HTTP JSON API
+pub struct AnkiClient {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl AnkiClient {
+    pub fn new(url: &str) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(TIMEOUT))
+            .build()
+            .into();
+        Self {
+            url: url.to_string(),
+            agent,
+        }
+    }
+
+    fn invoke<T: DeserializeOwned>(
+        &self,
+        action: &'static str,
+        params: Value,
+    ) -> Result<T, AnkiError> {
+        let request = json!({
+            "action": action,
+            "version": API_VERSION,
+            "params": params,
+        });
+        let mut response: Value = self
+            .agent
+            .post(&self.url)
+            .send_json(&request)
+            .and_then(|mut r| r.body_mut().read_json())
+            .map_err(|source| AnkiError::Connect {
+                url: self.url.clone(),
+                source,
+            })?;
+
+        let api_error = |message: String| AnkiError::Api { action, message };
+        match response.get("error") {
+            Some(Value::Null) => {}
+            Some(Value::String(e)) => return Err(api_error(e.clone())),
+            Some(e) => return Err(api_error(e.to_string())),
+            None => return Err(api_error("Response has no error field".to_string())),
+        }
+        serde_json::from_value(response["result"].take())
+            .map_err(|e| api_error(format!("Unexpected result: {}", e)))
+    }
+
+    /// IDs of the notes matching a search query
+    pub fn find_notes(&self, query: &str) -> Result<Vec<u64>, AnkiError> {
+        self.invoke("findNotes", json!({ "query": query }))
+    }
+
+    /// Copy a file into Anki's media folder, returning its name there
+    pub fn store_media_file(&self, path: &Path) -> Result<String, AnkiError> {
+        let filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.invoke(
+            "storeMediaFile",
+            json!({ "filename": filename, "path": path }),
+        )
+    }
+
+    pub fn update_note_fields(
+        &self,
+        note: u64,
+        fields: &BTreeMap<String, String>,
+    ) -> Result<(), AnkiError> {
+        self.invoke(
+            "updateNoteFields",
+            json!({ "note": { "id": note, "fields": fields } }),
+        )
+    }
+
+    /// Open the card browser on a search query
+    pub fn gui_browse(&self, query: &str) -> Result<Vec<u64>, AnkiError> {
+        self.invoke("guiBrowse", json!({ "query": query }))
+    }
+}
+
+/// What of a record is put in a note, so it can be exported without holding the record
+#[derive(Debug, Clone, Default)]
+pub struct NoteMedia {
+    pub audio_path: PathBuf,
+    pub screenshot_path: PathBuf,
+    pub animation_path: Option<PathBuf>,
+    pub sentence: Option<String>,
+}
+
+impl From<&RecordedData> for NoteMedia {
+    fn from(data: &RecordedData) -> Self {
+        Self {
+            audio_path: data.audio_path.clone(),
+            screenshot_path: data.screenshot_path.clone(),
+            animation_path: data.animation_path.clone(),
+            sentence: data.metadata.sentence.clone(),
+        }
+    }
+}
+
+/// Attach the record's audio, screenshot and sentence to a note according to the field mapping,
+/// returning the ID of the updated note. Blocks until AnkiConnect answers.
+pub fn export(config: &AnkiConfig, data: &NoteMedia, target: NoteTarget) -> Result<u64, AnkiError> {
+    let client = AnkiClient::new(&config.url);
+    let note = match target {
+        NoteTarget::Id(id) => id,
+        NoteTarget::LastAdded => client
+            .find_notes(LAST_ADDED_QUERY)?
+            .into_iter()
+            .max()
+            .ok_or_else(|| AnkiError::NoNote(LAST_ADDED_QUERY.to_string()))?,
+    };
+
+    // Several kinds of content may be mapped to the same field
+    let mut fields: BTreeMap<String, String> = BTreeMap::new();
+    let mut append = |field: &str, content: String| {
+        if !field.is_empty() {
+            fields
+                .entry(field.to_string())
+                .or_default()
+                .push_str(&content);
+        }
+    };
+    if !config.audio_field.is_empty() {
+        let audio = client.store_media_file(&data.audio_path)?;
+        append(&config.audio_field, format!("[sound:{}]", audio));
+    }
+    // The screenshot is missing if the window couldn't be captured
+    if !config.picture_field.is_empty() && data.screenshot_path.exists() {
+        let picture = client.store_media_file(&data.screenshot_path)?;
+        append(&config.picture_field, format!("<img src=\"{}\">", picture));
+    }
+    if !config.animation_field.is_empty()
+        && let Some(path) = &data.animation_path
+    {
+        let animation = client.store_media_file(path)?;
+        // Anki plays videos like sounds
+        let content = if path.extension().is_some_and(|e| e == "webm") {
+            format!("[sound:{}]", animation)
+        } else {
+            format!("<img src=\"{}\">", animation)
+        };
+        append(&config.animation_field, content);
+    }
+    if let Some(sentence) = &data.sentence {
+        append(&config.sentence_field, html_escape(sentence));
+    }
+    client.update_note_fields(note, &fields)?;
+
+    if config.open_browser {
+        client.gui_browse(&format!("nid:{}", note))?;
+    }
+    Ok(note)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    /// AnkiConnect stand-in answering each action with a scripted response and keeping the
+    /// requests it got
+    struct MockAnki {
+        url: String,
+        requests: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl MockAnki {
+        fn start(respond: fn(&str, &Value) -> Value) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let received = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else { return };
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    // Requests may share a kept-alive connection
+                    while let Some(request) = read_request(&mut reader) {
+                        let action = request["action"].as_str().unwrap_or_default().to_string();
+                        let body = respond(&action, &request["params"]).to_string();
+                        received.lock().unwrap().push(request);
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        if stream.write_all(response.as_bytes()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+            Self { url, requests }
+        }
+
+        fn config(&self) -> AnkiConfig {
+            AnkiConfig {
+                url: self.url.clone(),
+                audio_field: "Audio".to_string(),
+                picture_field: "Picture".to_string(),
+                animation_field: String::new(),
+                sentence_field: "Sentence".to_string(),
+                open_browser: false,
+            }
+        }
+
+        fn actions(&self) -> Vec<String> {
+            let requests = self.requests.lock().unwrap();
+            requests
+                .iter()
+                .map(|r| r["action"].as_str().unwrap_or_default().to_string())
+                .collect()
+        }
+    }
+
+    fn read_request(reader: &mut impl BufRead) -> Option<Value> {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                length = value.trim().parse().ok()?;
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+
+    fn media() -> NoteMedia {
+        NoteMedia {
+            audio_path: PathBuf::from("/records/1_audio.mp3"),
+            // Missing, like when the window couldn't be captured
+            screenshot_path: PathBuf::from("/records/1_screenshot.png"),
+            animation_path: None,
+            sentence: Some("<b>Hello</b>".to_string()),
+        }
+    }
+
+    #[test]
+    fn export_fills_the_last_added_note() {
+        let anki = MockAnki::start(|action, params| match action {
+            "findNotes" => json!({ "result": [3, 7, 5], "error": null }),
+            "storeMediaFile" => json!({ "result": params["filename"], "error": null }),
+            _ => json!({ "result": null, "error": null }),
+        });
+        let note = export(&anki.config(), &media(), NoteTarget::LastAdded).unwrap();
+        assert_eq!(note, 7);
+        assert_eq!(
+            anki.actions(),
+            ["findNotes", "storeMediaFile", "updateNoteFields"]
+        );
+
+        let requests = anki.requests.lock().unwrap();
+        assert_eq!(requests[0]["params"]["query"], LAST_ADDED_QUERY);
+        let update = &requests[2]["params"]["note"];
+        assert_eq!(update["id"], 7);
+        assert_eq!(
+            update["fields"],
+            json!({
+                "Audio": "[sound:1_audio.mp3]",
+                "Sentence": "&lt;b&gt;Hello&lt;/b&gt;",
+            })
+        );
+    }
+
+    #[test]
+    fn export_reports_api_errors() {
+        let anki = MockAnki::start(|action, _| match action {
+            "storeMediaFile" => json!({ "result": null, "error": "file not found" }),
+            _ => json!({ "result": null, "error": null }),
+        });
+        let res = export(&anki.config(), &media(), NoteTarget::Id(42));
+        assert!(matches!(
+            res,
+            Err(AnkiError::Api { action: "storeMediaFile", ref message }) if message == "file not found"
+        ));
+        assert_eq!(anki.actions(), ["storeMediaFile"]);
+    }
+
+    #[test]
+    fn export_without_recent_notes_fails() {
+        let anki = MockAnki::start(|_, _| json!({ "result": [], "error": null }));
+        let res = export(&anki.config(), &media(), NoteTarget::LastAdded);
+        assert!(matches!(res, Err(AnkiError::NoNote(_))));
+    }
+
+    #[test]
+    fn unreachable_anki_is_a_connect_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let res = AnkiClient::new(&url).find_notes("added:1");
+        assert!(matches!(res, Err(AnkiError::Connect { .. })));
+    }
+}
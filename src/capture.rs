@@ -0,0 +1,400 @@
+#[cfg(test)]
+mod fake;
+mod mix;
+#[cfg(feature = "pipewire")]
+mod native;
+mod node;
+mod shell;
+
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    config::AudioConfig,
+    process::{Process, is_in_tree},
+};
+#[cfg(feature = "pipewire")]
+pub use native::{PipeWireSource, format_pod};
+pub use node::{StreamNode, stream_nodes};
+pub use shell::ShellSource;
+
+use mix::StreamMix;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureBackend {
+    /// Capture PCM in-process through PipeWire
+    #[default]
+    PipeWire,
+    /// `pw-record` subprocess
+    Shell,
+}
+
+impl CaptureBackend {
+    pub const ALL: [CaptureBackend; 2] = [CaptureBackend::PipeWire, CaptureBackend::Shell];
+}
+
+impl Display for CaptureBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CaptureBackend::PipeWire => "PipeWire (native)",
+            CaptureBackend::Shell => "pw-record (shell)",
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[cfg(feature = "pipewire")]
+    #[error("PipeWire error: {0}")]
+    PipeWire(#[from] pipewire::Error),
+    #[error("Capture stream failed: {0}")]
+    Stream(String),
+    #[error("Capture source disconnected")]
+    Disconnected,
+    #[error("Capture thread panicked")]
+    Panicked,
+    #[error("IO error on capture: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// What to record audio from, always a mix of application output streams. Streams are
+/// captured directly rather than through the sink's monitor, so the cues this app plays on its
+/// own stream are never recorded.
+#[derive(Debug, Clone)]
+pub enum CaptureTarget {
+    /// Every application's output stream, except this app's
+    Desktop,
+    /// Every output stream of the game's process and its children, following the game when it's
+    /// restarted
+    Process(Process),
+}
+
+/// Find the audio output streams of the process `pid` and its children.
+pub fn process_streams(pid: u32) -> Result<Vec<StreamNode>, CaptureError> {
+    let mut nodes = stream_nodes()?;
+    nodes.retain(|node| is_in_tree(node.pid, pid));
+    Ok(nodes)
+}
+
+/// Find the audio output streams of every application except this one (and the `pw-play` it
+/// may play cues through).
+pub fn desktop_streams() -> Result<Vec<StreamNode>, CaptureError> {
+    let own = std::process::id();
+    let mut nodes = stream_nodes()?;
+    nodes.retain(|node| !is_in_tree(node.pid, own));
+    Ok(nodes)
+}
+
+/// Layout of interleaved 32-bit float PCM samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl PcmFormat {
+    pub const STEREO_48K: PcmFormat = PcmFormat {
+        sample_rate: 48000,
+        channels: 2,
+    };
+
+    /// Number of interleaved samples in `duration` of audio
+    pub fn samples_in(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.sample_rate as f64) as usize * self.channels as usize
+    }
+}
+
+/// A source of interleaved f32 PCM, decoupled from PipeWire so any sample producer can feed a
+/// [`PcmStream`].
+pub trait PcmSource: Send {
+    fn format(&self) -> PcmFormat;
+
+    /// Wait up to `timeout` for the next chunk of samples, `Ok(None)` if nothing arrived in time.
+    fn read(&mut self, timeout: Duration) -> Result<Option<Vec<f32>>, CaptureError>;
+}
+
+impl<S: PcmSource + ?Sized> PcmSource for Box<S> {
+    fn format(&self) -> PcmFormat {
+        (**self).format()
+    }
+
+    fn read(&mut self, timeout: Duration) -> Result<Option<Vec<f32>>, CaptureError> {
+        (**self).read(timeout)
+    }
+}
+
+/// Start capturing `target` using the configured backend, falling back to `pw-record` if the
+/// native backend fails to start.
+pub fn start_capture(
+    target: &CaptureTarget,
+    audio: &AudioConfig,
+) -> Result<Box<dyn PcmSource>, CaptureError> {
+    let audio = audio.clone();
+    let open = move |node: &StreamNode| start_backend(node, &audio);
+    // Games often play voice, BGM and SE on separate streams, and recreate them at will
+    Ok(match target {
+        CaptureTarget::Desktop => Box::new(StreamMix::new(
+            PcmFormat::STEREO_48K,
+            desktop_streams,
+            open,
+        )?),
+        CaptureTarget::Process(process) => {
+            let process = process.clone();
+            Box::new(StreamMix::new(
+                PcmFormat::STEREO_48K,
+                move || match process.live_pid() {
+                    Some(pid) => process_streams(pid),
+                    None => Ok(Vec::new()),
+                },
+                open,
+            )?)
+        }
+    })
+}
+
+fn start_backend(
+    target: &StreamNode,
+    audio: &AudioConfig,
+) -> Result<Box<dyn PcmSource>, CaptureError> {
+    if audio.backend == CaptureBackend::PipeWire {
+        match connect_native(target) {
+            Ok(source) => return Ok(source),
+            Err(e) => eprintln!(
+                "Native PipeWire capture failed, falling back to pw-record: {}",
+                e
+            ),
+        }
+    }
+    Ok(Box::new(ShellSource::start(target)?))
+}
+
+#[cfg(feature = "pipewire")]
+fn connect_native(target: &StreamNode) -> Result<Box<dyn PcmSource>, CaptureError> {
+    Ok(Box::new(PipeWireSource::connect(target)?))
+}
+
+#[cfg(not(feature = "pipewire"))]
+fn connect_native(_target: &StreamNode) -> Result<Box<dyn PcmSource>, CaptureError> {
+    Err(CaptureError::Stream(
+        "built without native PipeWire support".to_string(),
+    ))
+}
+
+/// Reads a [`PcmSource`] on a background thread into memory, optionally keeping only the most
+/// recent samples.
+pub struct PcmStream {
+    format: PcmFormat,
+    buffer: Arc<Mutex<VecDeque<f32>>>,
+
+    stop: Arc<AtomicBool>,
+    level: Arc<AtomicU32>,
+    thread: Option<JoinHandle<Result<(), CaptureError>>>,
+}
+
+impl PcmStream {
+    /// Start reading `source`, keeping at most `max_duration` of audio if given.
+    pub fn start(mut source: impl PcmSource + 'static, max_duration: Option<Duration>) -> Self {
+        let format = source.format();
+        let capacity = max_duration.map(|d| format.samples_in(d));
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let level = Arc::new(AtomicU32::new(0));
+
+        let thread = std::thread::spawn({
+            let buffer = buffer.clone();
+            let stop = stop.clone();
+            let level = level.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let Some(chunk) = source.read(Duration::from_millis(100))? else {
+                        level.store(0f32.to_bits(), Ordering::Relaxed);
+                        continue;
+                    };
+                    level.store(peak(&chunk).to_bits(), Ordering::Relaxed);
+
+                    let mut buffer = buffer.lock().unwrap();
+                    buffer.extend(chunk);
+                    if let Some(capacity) = capacity
+                        && buffer.len() > capacity
+                    {
+                        let excess = buffer.len() - capacity;
+                        buffer.drain(..excess);
+                    }
+                }
+                Ok(())
+            }
+        });
+
+        PcmStream {
+            format,
+            buffer,
+            stop,
+            level,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn format(&self) -> PcmFormat {
+        self.format
+    }
+
+    /// Peak level (0.0 to 1.0) of the most recently captured audio
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Copy of the samples captured so far
+    pub fn snapshot(&self) -> Vec<f32> {
+        self.buffer.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Return the error which stopped the stream, if it has stopped.
+    pub fn check(&mut self) -> Result<(), CaptureError> {
+        match self.thread.take_if(|thread| thread.is_finished()) {
+            Some(thread) => thread.join().map_err(|_| CaptureError::Panicked)?,
+            None => Ok(()),
+        }
+    }
+
+    /// Stop reading and return all buffered samples, along with the error which stopped the
+    /// stream early if it did. The samples captured before an error are kept.
+    pub fn stop(mut self) -> (Vec<f32>, Result<(), CaptureError>) {
+        self.stop.store(true, Ordering::Relaxed);
+        let res = match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or(Err(CaptureError::Panicked)),
+            None => Ok(()),
+        };
+        (
+            std::mem::take(&mut *self.buffer.lock().unwrap()).into(),
+            res,
+        )
+    }
+}
+
+impl Drop for PcmStream {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Peak absolute amplitude of the samples
+pub fn peak(samples: &[f32]) -> f32 {
+    samples
+        .iter()
+        .fold(0.0f32, |max, s| max.max(s.abs()))
+        .min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{fake::FakeSource, *};
+
+    const MONO_10: PcmFormat = PcmFormat {
+        sample_rate: 10,
+        channels: 1,
+    };
+
+    /// Wait for the reader thread to buffer `len` samples
+    fn wait_for(stream: &PcmStream, len: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while stream.snapshot().len() < len {
+            assert!(
+                Instant::now() < deadline,
+                "stream never buffered {len} samples"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn stream_keeps_every_chunk_in_order() {
+        let source = FakeSource::new(PcmFormat::STEREO_48K)
+            .chunk([0.1, 0.2])
+            .chunk([0.3, 0.4, 0.5, 0.6])
+            .chunk([0.7, 0.8]);
+        let stream = PcmStream::start(source, None);
+        assert_eq!(stream.format(), PcmFormat::STEREO_48K);
+        wait_for(&stream, 8);
+        let (samples, res) = stream.stop();
+        assert_eq!(samples, [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8]);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn stream_keeps_only_the_latest_max_duration() {
+        let source = (0..5).fold(FakeSource::new(MONO_10), |source, i| {
+            source.chunk((0..5).map(|j| (i * 5 + j) as f32).collect::<Vec<_>>())
+        });
+        let stream = PcmStream::start(source, Some(Duration::from_secs(1)));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while stream.snapshot().last() != Some(&24.0) {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let expected: Vec<f32> = (15..25).map(|s| s as f32).collect();
+        assert_eq!(stream.snapshot(), expected);
+        assert_eq!(stream.stop().0, expected);
+    }
+
+    #[test]
+    fn stream_reports_source_errors() {
+        let source = FakeSource::new(MONO_10)
+            .chunk([0.5])
+            .error(CaptureError::Disconnected);
+        let mut stream = PcmStream::start(source, None);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match stream.check() {
+                Err(CaptureError::Disconnected) => break,
+                Err(e) => panic!("unexpected error {e}"),
+                Ok(()) => assert!(Instant::now() < deadline, "error never reported"),
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(stream.snapshot(), [0.5]);
+    }
+
+    #[test]
+    fn stream_stop_keeps_samples_before_an_error() {
+        let source = FakeSource::new(MONO_10)
+            .chunk([0.1, 0.2])
+            .error(CaptureError::Disconnected);
+        let stream = PcmStream::start(source, None);
+        wait_for(&stream, 2);
+        // Give the reader time to hit the error
+        std::thread::sleep(Duration::from_millis(50));
+        let (samples, res) = stream.stop();
+        assert_eq!(samples, [0.1, 0.2]);
+        assert!(matches!(res, Err(CaptureError::Disconnected)));
+    }
+
+    #[test]
+    fn peak_is_the_largest_absolute_sample_capped_at_one() {
+        assert_eq!(peak(&[]), 0.0);
+        assert_eq!(peak(&[0.1, -0.6, 0.4]), 0.6);
+        assert_eq!(peak(&[2.0]), 1.0);
+    }
+
+    #[test]
+    fn samples_in_counts_every_channel() {
+        assert_eq!(
+            PcmFormat::STEREO_48K.samples_in(Duration::from_millis(10)),
+            960
+        );
+        assert_eq!(MONO_10.samples_in(Duration::from_millis(250)), 2);
+    }
+}
@@ -0,0 +1,45 @@
+use std::{collections::VecDeque, time::Duration};
+
+use super::{CaptureError, PcmFormat, PcmSource};
+
+/// Test source replaying scripted chunks and errors, then staying silent
+pub struct FakeSource {
+    format: PcmFormat,
+    chunks: VecDeque<Result<Vec<f32>, CaptureError>>,
+}
+
+impl FakeSource {
+    pub fn new(format: PcmFormat) -> Self {
+        FakeSource {
+            format,
+            chunks: VecDeque::new(),
+        }
+    }
+
+    pub fn chunk(mut self, samples: impl Into<Vec<f32>>) -> Self {
+        self.chunks.push_back(Ok(samples.into()));
+        self
+    }
+
+    pub fn error(mut self, error: CaptureError) -> Self {
+        self.chunks.push_back(Err(error));
+        self
+    }
+}
+
+impl PcmSource for FakeSource {
+    fn format(&self) -> PcmFormat {
+        self.format
+    }
+
+    fn read(&mut self, timeout: Duration) -> Result<Option<Vec<f32>>, CaptureError> {
+        match self.chunks.pop_front() {
+            Some(chunk) => chunk.map(Some),
+            None => {
+                // Like a real source waiting for audio, without spinning the reader
+                std::thread::sleep(timeout.min(Duration::from_millis(1)));
+                Ok(None)
+            }
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::{CaptureError, PcmFormat, PcmSource, StreamNode};
+
+/// How often the streams are listed again to pick up new ones and drop removed ones
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(1);
+/// How far a stream may fall behind the others before it's considered idle and padded with
+/// silence
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+type ListStreams = Box<dyn FnMut() -> Result<Vec<StreamNode>, CaptureError> + Send>;
+type OpenStream = Box<dyn FnMut(&StreamNode) -> Result<Box<dyn PcmSource>, CaptureError> + Send>;
+
+/// Captures several streams at once and mixes them into one, such as the separate voice, BGM
+/// and SE streams of a game. Streams are listed again every second and whenever one fails, so
+/// streams the game recreates keep being recorded.
+pub struct StreamMix {
+    format: PcmFormat,
+    list: ListStreams,
+    open: OpenStream,
+    sources: Vec<(StreamNode, Box<dyn PcmSource>)>,
+    mixer: Mixer,
+    next_resolve: Instant,
+    /// When silence was last produced while no stream was captured
+    idle_since: Instant,
+}
+
+impl StreamMix {
+    /// Mix the streams returned by `list`, each captured through `open` in `format`.
+    pub fn new(
+        format: PcmFormat,
+        list: impl FnMut() -> Result<Vec<StreamNode>, CaptureError> + Send + 'static,
+        open: impl FnMut(&StreamNode) -> Result<Box<dyn PcmSource>, CaptureError> + Send + 'static,
+    ) -> Result<StreamMix, CaptureError> {
+        let mut mix = StreamMix {
+            format,
+            list: Box::new(list),
+            open: Box::new(open),
+            sources: Vec::new(),
+            mixer: Mixer::new(format, MAX_LAG),
+            next_resolve: Instant::now(),
+            idle_since: Instant::now(),
+        };
+        // Fail early if the streams can't be listed at all
+        mix.resolve()?;
+        Ok(mix)
+    }
+
+    /// Start capturing new streams and stop capturing removed ones
+    fn resolve(&mut self) -> Result<(), CaptureError> {
+        self.next_resolve = Instant::now() + RESOLVE_INTERVAL;
+        let nodes = (self.list)()?;
+
+        let mixer = &mut self.mixer;
+        self.sources.retain(|(node, _)| {
+            let present = nodes.contains(node);
+            if !present {
+                mixer.close(node.id);
+            }
+            present
+        });
+        for node in nodes {
+            if self.sources.iter().any(|(n, _)| *n == node) {
+                continue;
+            }
+            match (self.open)(&node) {
+                Ok(source) if source.format() != self.format => {
+                    eprintln!(
+                        "Skipping audio stream {} with unexpected format {:?}",
+                        node.name,
+                        source.format()
+                    );
+                }
+                Ok(source) => {
+                    if self.sources.is_empty() {
+                        self.flush_silence();
+                    }
+                    self.mixer.open(node.id);
+                    self.sources.push((node, source));
+                }
+                Err(e) => eprintln!("Failed to capture audio stream {}: {}", node.name, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue the silence since the last stream went away, keeping the timeline continuous
+    fn flush_silence(&mut self) {
+        let now = Instant::now();
+        let silence = self.format.samples_in(now - self.idle_since);
+        self.mixer.silence(silence);
+        self.idle_since = now;
+    }
+}
+
+impl PcmSource for StreamMix {
+    fn format(&self) -> PcmFormat {
+        self.format
+    }
+
+    fn read(&mut self, timeout: Duration) -> Result<Option<Vec<f32>>, CaptureError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Keep the streams already captured if listing fails, they may still be fine
+            if Instant::now() >= self.next_resolve
+                && let Err(e) = self.resolve()
+            {
+                eprintln!("Failed to list audio streams: {}", e);
+            }
+
+            let mut failed = false;
+            let mixer = &mut self.mixer;
+            self.sources.retain_mut(|(node, source)| {
+                loop {
+                    match source.read(Duration::ZERO) {
+                        Ok(Some(chunk)) => mixer.push(node.id, &chunk),
+                        Ok(None) => return true,
+                        Err(e) => {
+                            eprintln!("Audio stream {} stopped: {}", node.name, e);
+                            mixer.close(node.id);
+                            failed = true;
+                            return false;
+                        }
+                    }
+                }
+            });
+            if failed {
+                // The stream may have been recreated already
+                self.next_resolve = Instant::now();
+            }
+            if self.sources.is_empty() {
+                self.flush_silence();
+            } else {
+                self.idle_since = Instant::now();
+            }
+            if let Some(mixed) = self.mixer.mix() {
+                return Ok(Some(mixed));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep((deadline - now).min(Duration::from_millis(5)));
+        }
+    }
+}
+
+/// Sums the samples of several streams, waiting for every open stream to provide them unless
+/// one lags too far behind.
+struct Mixer {
+    channels: usize,
+    max_lag: usize,
+    queues: Vec<Queue>,
+}
+
+struct Queue {
+    id: u32,
+    samples: VecDeque<f32>,
+    /// Closed queues are drained but never waited for
+    open: bool,
+}
+
+impl Mixer {
+    fn new(format: PcmFormat, max_lag: Duration) -> Self {
+        Mixer {
+            channels: format.channels.max(1) as usize,
+            max_lag: format.samples_in(max_lag),
+            queues: Vec::new(),
+        }
+    }
+
+    fn open(&mut self, id: u32) {
+        self.queues.push(Queue {
+            id,
+            samples: VecDeque::new(),
+            open: true,
+        });
+    }
+
+    fn close(&mut self, id: u32) {
+        for queue in self.queues.iter_mut().filter(|q| q.id == id) {
+            queue.open = false;
+        }
+    }
+
+    fn push(&mut self, id: u32, samples: &[f32]) {
+        if let Some(queue) = self.queues.iter_mut().find(|q| q.id == id && q.open) {
+            queue.samples.extend(samples);
+        }
+    }
+
+    /// Queue silence to be mixed in, as a closed stream of its own
+    fn silence(&mut self, len: usize) {
+        const SILENCE: u32 = u32::MAX;
+        let len = len - len % self.channels;
+        if len == 0 {
+            return;
+        }
+        match self.queues.iter_mut().find(|q| q.id == SILENCE) {
+            Some(queue) => queue.samples.resize(queue.samples.len() + len, 0.0),
+            None => self.queues.push(Queue {
+                id: SILENCE,
+                samples: VecDeque::from(vec![0.0; len]),
+                open: false,
+            }),
+        }
+    }
+
+    /// Mix the samples every open stream has provided, or everything queued once a stream falls
+    /// too far behind
+    fn mix(&mut self) -> Option<Vec<f32>> {
+        let longest = self.queues.iter().map(|q| q.samples.len()).max()?;
+        let ready = if longest > self.max_lag {
+            longest
+        } else {
+            self.queues
+                .iter()
+                .filter(|q| q.open)
+                .map(|q| q.samples.len())
+                .min()
+                .unwrap_or(longest)
+        };
+        let ready = ready - ready % self.channels;
+        if ready == 0 {
+            return None;
+        }
+
+        let mut mixed = vec![0.0f32; ready];
+        for queue in &mut self.queues {
+            let n = queue.samples.len().min(ready);
+            for (m, s) in mixed.iter_mut().zip(queue.samples.drain(..n)) {
+                *m += s;
+            }
+        }
+        self.queues.retain(|q| q.open || !q.samples.is_empty());
+        for m in &mut mixed {
+            *m = m.clamp(-1.0, 1.0);
+        }
+        Some(mixed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{super::fake::FakeSource, *};
+
+    const MONO_1K: PcmFormat = PcmFormat {
+        sample_rate: 1000,
+        channels: 1,
+    };
+
+    fn node(id: u32) -> StreamNode {
+        StreamNode {
+            id,
+            serial: id.to_string(),
+            pid: 1,
+            name: format!("stream {id}"),
+        }
+    }
+
+    #[test]
+    fn mixer_waits_for_every_open_stream() {
+        let mut mixer = Mixer::new(MONO_1K, Duration::from_millis(100));
+        mixer.open(1);
+        mixer.open(2);
+        mixer.push(1, &[0.1, 0.2, 0.3]);
+        assert_eq!(mixer.mix(), None);
+        mixer.push(2, &[0.1, 0.1]);
+        assert_eq!(mixer.mix(), Some(vec![0.2, 0.3]));
+        mixer.push(2, &[0.5]);
+        assert_eq!(mixer.mix(), Some(vec![0.8]));
+    }
+
+    #[test]
+    fn mixer_pads_a_lagging_stream_with_silence() {
+        let mut mixer = Mixer::new(MONO_1K, Duration::from_millis(3));
+        mixer.open(1);
+        mixer.open(2);
+        mixer.push(1, &[0.1, 0.2, 0.3]);
+        assert_eq!(mixer.mix(), None);
+        mixer.push(1, &[0.4]);
+        assert_eq!(mixer.mix(), Some(vec![0.1, 0.2, 0.3, 0.4]));
+    }
+
+    #[test]
+    fn mixer_drains_closed_streams_and_clips() {
+        let mut mixer = Mixer::new(MONO_1K, Duration::from_millis(100));
+        mixer.open(1);
+        mixer.open(2);
+        mixer.push(1, &[0.9, 0.9]);
+        mixer.push(2, &[0.5, 0.5, 0.5]);
+        mixer.close(2);
+        assert_eq!(mixer.mix(), Some(vec![1.0, 1.0]));
+        // The open stream is waited for again once the closed one is drained
+        assert_eq!(mixer.mix(), None);
+        mixer.push(1, &[0.1]);
+        assert_eq!(mixer.mix(), Some(vec![0.6]));
+        mixer.push(1, &[0.1]);
+        assert_eq!(mixer.mix(), Some(vec![0.1]));
+    }
+
+    #[test]
+    fn mixer_appends_silence() {
+        let mut mixer = Mixer::new(MONO_1K, Duration::from_millis(100));
+        mixer.silence(2);
+        mixer.silence(3);
+        assert_eq!(mixer.mix(), Some(vec![0.0; 5]));
+    }
+
+    #[test]
+    fn mixer_keeps_whole_frames() {
+        let stereo = PcmFormat {
+            sample_rate: 1000,
+            channels: 2,
+        };
+        let mut mixer = Mixer::new(stereo, Duration::from_millis(100));
+        mixer.open(1);
+        mixer.push(1, &[0.1, 0.2, 0.3]);
+        assert_eq!(mixer.mix(), Some(vec![0.1, 0.2]));
+    }
+
+    /// Read until `len` samples were mixed
+    fn read_samples(mix: &mut StreamMix, len: usize) -> Vec<f32> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut samples = Vec::new();
+        while samples.len() < len {
+            assert!(Instant::now() < deadline, "only mixed {samples:?}");
+            samples.extend(
+                mix.read(Duration::from_millis(10))
+                    .unwrap()
+                    .unwrap_or_default(),
+            );
+        }
+        samples
+    }
+
+    #[test]
+    fn mixes_every_listed_stream() {
+        let mut mix = StreamMix::new(
+            MONO_1K,
+            || Ok(vec![node(1), node(2)]),
+            |node| {
+                let samples = vec![node.id as f32 / 10.0; 4];
+                Ok(Box::new(FakeSource::new(MONO_1K).chunk(samples)) as Box<dyn PcmSource>)
+            },
+        )
+        .unwrap();
+        assert_eq!(mix.sources.len(), 2);
+        let samples = read_samples(&mut mix, 4);
+        assert!(
+            samples.iter().all(|s| (s - 0.3).abs() < 1e-6),
+            "{samples:?}"
+        );
+    }
+
+    #[test]
+    fn follows_recreated_streams() {
+        // The game replaces its stream after the first listing
+        let listings = Arc::new(Mutex::new(0));
+        let mut mix = StreamMix::new(
+            MONO_1K,
+            {
+                let listings = listings.clone();
+                move || {
+                    let mut listings = listings.lock().unwrap();
+                    *listings += 1;
+                    Ok(vec![node(if *listings == 1 { 1 } else { 2 })])
+                }
+            },
+            |node| {
+                let source = match node.id {
+                    // The first stream fails after some audio
+                    1 => FakeSource::new(MONO_1K)
+                        .chunk([0.5; 2])
+                        .error(CaptureError::Disconnected),
+                    _ => FakeSource::new(MONO_1K).chunk([0.25; 2]),
+                };
+                Ok(Box::new(source) as Box<dyn PcmSource>)
+            },
+        )
+        .unwrap();
+        assert_eq!(read_samples(&mut mix, 2), [0.5; 2]);
+        let samples = read_samples(&mut mix, 2);
+        assert!(samples.contains(&0.25), "{samples:?}");
+        assert_eq!(*listings.lock().unwrap(), 2);
+        assert_eq!(mix.sources[0].0, node(2));
+    }
+
+    #[test]
+    fn produces_silence_without_streams() {
+        let mut mix = StreamMix::new(
+            MONO_1K,
+            || Ok(Vec::new()),
+            |_| Err(CaptureError::Disconnected),
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let samples = read_samples(&mut mix, 10);
+        assert!(samples.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn fails_if_streams_cant_be_listed() {
+        let mix = StreamMix::new(
+            MONO_1K,
+            || Err(CaptureError::Disconnected),
+            |_| Err(CaptureError::Disconnected),
+        );
+        assert!(matches!(mix, Err(CaptureError::Disconnected)));
+    }
+}
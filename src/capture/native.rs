@@ -0,0 +1,174 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use pipewire::{
+    self as pw,
+    properties::properties,
+    spa::{self, pod::Pod},
+    stream::{Stream, StreamFlags, StreamState},
+};
+
+use super::{CaptureError, PcmFormat, PcmSource, StreamNode};
+
+type Chunk = Result<Vec<f32>, CaptureError>;
+
+/// Captures an application's output stream through a PipeWire stream running on its own thread
+pub struct PipeWireSource {
+    receiver: mpsc::Receiver<Chunk>,
+    quit: pw::channel::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PipeWireSource {
+    const FORMAT: PcmFormat = PcmFormat::STEREO_48K;
+
+    pub fn connect(target: &StreamNode) -> Result<PipeWireSource, CaptureError> {
+        let target = target.clone();
+        let (sender, receiver) = mpsc::channel();
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let (quit, quit_receiver) = pw::channel::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("pipewire-capture".into())
+            .spawn(move || {
+                let res = run_stream(target, sender, quit_receiver, || {
+                    let _ = ready_sender.send(Ok(()));
+                });
+                if let Err(e) = res {
+                    let _ = ready_sender.send(Err(e));
+                }
+            })?;
+
+        // Wait until the stream is set up, so connection errors are reported to the caller
+        ready_receiver
+            .recv()
+            .map_err(|_| CaptureError::Disconnected)??;
+
+        Ok(PipeWireSource {
+            receiver,
+            quit,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl PcmSource for PipeWireSource {
+    fn format(&self) -> PcmFormat {
+        Self::FORMAT
+    }
+
+    fn read(&mut self, timeout: Duration) -> Result<Option<Vec<f32>>, CaptureError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(chunk) => chunk.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(CaptureError::Disconnected),
+        }
+    }
+}
+
+impl Drop for PipeWireSource {
+    fn drop(&mut self) {
+        let _ = self.quit.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Serialized `EnumFormat` param asking for interleaved f32 PCM in `format`
+pub fn format_pod(format: PcmFormat) -> Result<Vec<u8>, CaptureError> {
+    let mut audio_info = spa::param::audio::AudioInfoRaw::new();
+    audio_info.set_format(spa::param::audio::AudioFormat::F32LE);
+    audio_info.set_rate(format.sample_rate);
+    audio_info.set_channels(format.channels as u32);
+    let obj = spa::pod::Object {
+        type_: spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: spa::param::ParamType::EnumFormat.as_raw(),
+        properties: audio_info.into(),
+    };
+    let values = spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &spa::pod::Value::Object(obj),
+    )
+    .map_err(|e| CaptureError::Stream(format!("Failed to serialize format: {:?}", e)))?
+    .0
+    .into_inner();
+    Ok(values)
+}
+
+/// Run the PipeWire main loop until a message is received on `quit`
+fn run_stream(
+    target: StreamNode,
+    sender: mpsc::Sender<Chunk>,
+    quit: pw::channel::Receiver<()>,
+    on_ready: impl FnOnce(),
+) -> Result<(), CaptureError> {
+    pw::init();
+
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Capture",
+        *pw::keys::MEDIA_ROLE => "Music",
+        *pw::keys::NODE_NAME => "vn_record",
+        *pw::keys::TARGET_OBJECT => target.serial.as_str(),
+        // Never fall back to the default source (the microphone) if the stream goes away
+        *pw::keys::NODE_DONT_RECONNECT => "true",
+    };
+
+    let stream = Stream::new(&core, "vn_record-capture", props)?;
+    let _listener = stream
+        .add_local_listener_with_user_data(sender)
+        .state_changed(|_, sender, _, new| {
+            if let StreamState::Error(e) = new {
+                let _ = sender.send(Err(CaptureError::Stream(e)));
+            }
+        })
+        .process(|stream, sender| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let datas = buffer.datas_mut();
+            let Some(data) = datas.first_mut() else {
+                return;
+            };
+            let offset = data.chunk().offset() as usize;
+            let size = data.chunk().size() as usize;
+            if let Some(bytes) = data.data() {
+                let end = (offset + size).min(bytes.len());
+                let samples = bytes[offset.min(end)..end]
+                    .chunks_exact(size_of::<f32>())
+                    .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                    .collect();
+                let _ = sender.send(Ok(samples));
+            }
+        })
+        .register()?;
+
+    // Ask PipeWire to convert to our fixed format
+    let values = format_pod(PipeWireSource::FORMAT)?;
+    let mut params = [Pod::from_bytes(&values)
+        .ok_or_else(|| CaptureError::Stream("Invalid format pod".to_string()))?];
+
+    stream.connect(
+        spa::utils::Direction::Input,
+        None,
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    let _quit = quit.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+
+    on_ready();
+    mainloop.run();
+    Ok(())
+}
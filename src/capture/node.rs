@@ -0,0 +1,278 @@
+#[cfg(feature = "pipewire")]
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+};
+
+#[cfg(feature = "pipewire")]
+use pipewire::{self as pw, registry::GlobalObject, spa::utils::dict::DictRef, types::ObjectType};
+
+use super::CaptureError;
+
+const MEDIA_CLASS: &str = "Stream/Output/Audio";
+
+/// An application's audio output stream in the PipeWire graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamNode {
+    pub id: u32,
+    /// `object.serial`, preferred over `id` for targeting since ids get reused
+    pub serial: String,
+    pub pid: u32,
+    pub name: String,
+}
+
+/// Registry connection kept open on its own thread, shared by every capture so listing the
+/// streams doesn't connect to PipeWire each time
+#[cfg(feature = "pipewire")]
+static WATCH: Mutex<Option<Arc<RegistryWatch>>> = Mutex::new(None);
+
+/// Stream nodes kept up to date from the registry's `global` and `global_remove` events
+#[cfg(feature = "pipewire")]
+struct RegistryWatch {
+    nodes: Mutex<HashMap<u32, StreamNode>>,
+    /// Cleared when the connection is lost, e.g. when PipeWire restarts
+    connected: AtomicBool,
+}
+
+#[cfg(feature = "pipewire")]
+impl RegistryWatch {
+    /// Connect on a new thread, returning once every existing stream has been seen
+    fn start() -> Result<Arc<RegistryWatch>, CaptureError> {
+        let watch = Arc::new(RegistryWatch {
+            nodes: Mutex::new(HashMap::new()),
+            connected: AtomicBool::new(true),
+        });
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("pipewire-registry".into())
+            .spawn({
+                let watch = watch.clone();
+                move || {
+                    if let Err(e) = watch.run(&ready_sender) {
+                        let _ = ready_sender.send(Err(e));
+                    }
+                    watch.connected.store(false, Ordering::Relaxed);
+                }
+            })?;
+        ready_receiver
+            .recv()
+            .map_err(|_| CaptureError::Disconnected)??;
+        Ok(watch)
+    }
+
+    /// Run the PipeWire main loop until the connection is lost
+    fn run(
+        self: &Arc<Self>,
+        ready: &mpsc::Sender<Result<(), CaptureError>>,
+    ) -> Result<(), CaptureError> {
+        pw::init();
+
+        let mainloop = pw::main_loop::MainLoop::new(None)?;
+        let context = pw::context::Context::new(&mainloop)?;
+        let core = context.connect(None)?;
+        let registry = core.get_registry()?;
+
+        // The server answers the sync after it has sent us every existing global
+        let pending = core.sync(0)?;
+        let ready = Cell::new(Some(ready.clone()));
+        let _core_listener = core
+            .add_listener_local()
+            .done(move |id, seq| {
+                if id == pw::core::PW_ID_CORE
+                    && seq == pending
+                    && let Some(ready) = ready.take()
+                {
+                    let _ = ready.send(Ok(()));
+                }
+            })
+            .error({
+                let mainloop = mainloop.clone();
+                move |id, _, _, message| {
+                    if id == pw::core::PW_ID_CORE {
+                        eprintln!("PipeWire registry connection failed: {}", message);
+                        mainloop.quit();
+                    }
+                }
+            })
+            .register();
+        let _registry_listener = registry
+            .add_listener_local()
+            .global({
+                let watch = self.clone();
+                move |global| {
+                    if let Some(node) = stream_node(global) {
+                        watch.nodes.lock().unwrap().insert(node.id, node);
+                    }
+                }
+            })
+            .global_remove({
+                let watch = self.clone();
+                move |id| {
+                    watch.nodes.lock().unwrap().remove(&id);
+                }
+            })
+            .register();
+
+        mainloop.run();
+        Ok(())
+    }
+}
+
+/// The audio output stream described by a registry global, if it is one
+#[cfg(feature = "pipewire")]
+fn stream_node(global: &GlobalObject<&DictRef>) -> Option<StreamNode> {
+    if global.type_ != ObjectType::Node {
+        return None;
+    }
+    let props = global.props?;
+    if props.get(*pw::keys::MEDIA_CLASS) != Some(MEDIA_CLASS) {
+        return None;
+    }
+    let pid = props.get(*pw::keys::APP_PROCESS_ID)?.parse().ok()?;
+    let name = [*pw::keys::APP_NAME, *pw::keys::NODE_NAME]
+        .into_iter()
+        .find_map(|key| props.get(key))
+        .unwrap_or("Unknown")
+        .to_string();
+    Some(StreamNode {
+        id: global.id,
+        serial: props
+            .get(*pw::keys::OBJECT_SERIAL)
+            .map_or_else(|| global.id.to_string(), str::to_string),
+        pid,
+        name,
+    })
+}
+
+/// List all audio output streams currently in the PipeWire graph, connecting to it again if
+/// the connection was lost
+#[cfg(feature = "pipewire")]
+pub fn stream_nodes() -> Result<Vec<StreamNode>, CaptureError> {
+    let watch = {
+        let mut current = WATCH.lock().unwrap();
+        match &*current {
+            Some(watch) if watch.connected.load(Ordering::Relaxed) => watch.clone(),
+            _ => current.insert(RegistryWatch::start()?).clone(),
+        }
+    };
+    let mut nodes: Vec<_> = watch.nodes.lock().unwrap().values().cloned().collect();
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+/// List all audio output streams currently in the PipeWire graph, as dumped by `pw-dump`
+#[cfg(not(feature = "pipewire"))]
+pub fn stream_nodes() -> Result<Vec<StreamNode>, CaptureError> {
+    let output = duct::cmd!("pw-dump").stdout_capture().run()?;
+    parse_pw_dump(&output.stdout)
+}
+
+/// Read the audio output stream nodes out of `pw-dump`'s JSON
+#[cfg(any(test, not(feature = "pipewire")))]
+fn parse_pw_dump(json: &[u8]) -> Result<Vec<StreamNode>, CaptureError> {
+    let objects: Vec<serde_json::Value> = serde_json::from_slice(json)
+        .map_err(|e| CaptureError::Stream(format!("Invalid pw-dump output: {}", e)))?;
+    // Numbers are dumped as JSON numbers, but accept strings as the registry reports them
+    let prop = |props: &serde_json::Value, key: &str| match &props[key] {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    };
+
+    Ok(objects
+        .iter()
+        .filter(|object| object["type"] == "PipeWire:Interface:Node")
+        .filter_map(|object| {
+            let id = object["id"].as_u64()? as u32;
+            let props = &object["info"]["props"];
+            if props["media.class"] != MEDIA_CLASS {
+                return None;
+            }
+            let pid = prop(props, "application.process.id")?.parse().ok()?;
+            let name = ["application.name", "node.name"]
+                .into_iter()
+                .find_map(|key| prop(props, key))
+                .unwrap_or_else(|| "Unknown".to_string());
+            Some(StreamNode {
+                id,
+                serial: prop(props, "object.serial").unwrap_or_else(|| id.to_string()),
+                pid,
+                name,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_output_streams_from_pw_dump() {
+        let json = br#"[
+            { "id": 30, "type": "PipeWire:Interface:Core", "info": {} },
+            {
+                "id": 57,
+                "type": "PipeWire:Interface:Node",
+                "info": { "props": {
+                    "media.class": "Stream/Output/Audio",
+                    "application.process.id": 4242,
+                    "application.name": "game.exe",
+                    "node.name": "wine",
+                    "object.serial": 1203
+                } }
+            },
+            {
+                "id": 58,
+                "type": "PipeWire:Interface:Node",
+                "info": { "props": {
+                    "media.class": "Stream/Output/Audio",
+                    "application.process.id": "77",
+                    "node.name": "mpv"
+                } }
+            },
+            {
+                "id": 59,
+                "type": "PipeWire:Interface:Node",
+                "info": { "props": {
+                    "media.class": "Audio/Sink",
+                    "application.process.id": 1
+                } }
+            },
+            {
+                "id": 60,
+                "type": "PipeWire:Interface:Node",
+                "info": { "props": { "media.class": "Stream/Output/Audio" } }
+            }
+        ]"#;
+
+        let nodes = parse_pw_dump(json).unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                StreamNode {
+                    id: 57,
+                    serial: "1203".to_string(),
+                    pid: 4242,
+                    name: "game.exe".to_string(),
+                },
+                StreamNode {
+                    id: 58,
+                    serial: "58".to_string(),
+                    pid: 77,
+                    name: "mpv".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_pw_dump() {
+        assert!(parse_pw_dump(b"not json").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    io::Read,
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use duct::{ReaderHandle, cmd};
+
+use super::{CaptureError, PcmFormat, PcmSource, StreamNode};
+
+type Chunk = Result<Vec<f32>, CaptureError>;
+
+/// Fallback backend reading raw PCM from a `pw-record` subprocess
+pub struct ShellSource {
+    receiver: mpsc::Receiver<Chunk>,
+    reader: Arc<ReaderHandle>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ShellSource {
+    const FORMAT: PcmFormat = PcmFormat::STEREO_48K;
+
+    pub fn start(target: &StreamNode) -> Result<ShellSource, CaptureError> {
+        // based on https://github.com/JayXT/RecordAudioOutput/blob/main/record_audio_output_pw
+
+        // Never fall back to the default source (the microphone) if the stream goes away
+        let inner_expr = format!(
+            "exec pw-record --target \"{}\" -P '{{ node.dont-reconnect=true }}' --format f32 --rate {} --channels {} -",
+            target.serial,
+            Self::FORMAT.sample_rate,
+            Self::FORMAT.channels,
+        );
+        // afaik, must wrap in shell context otherwise it won't record the correct audio
+        let expr = cmd("/usr/bin/env", ["bash", "-c", &inner_expr]);
+        let reader = Arc::new(expr.unchecked().reader()?);
+
+        let (sender, receiver) = mpsc::channel();
+        let thread = std::thread::Builder::new()
+            .name("pw-record-reader".into())
+            .spawn({
+                let reader = reader.clone();
+                move || read_pcm(&reader, sender)
+            })?;
+
+        Ok(ShellSource {
+            receiver,
+            reader,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl PcmSource for ShellSource {
+    fn format(&self) -> PcmFormat {
+        Self::FORMAT
+    }
+
+    fn read(&mut self, timeout: Duration) -> Result<Option<Vec<f32>>, CaptureError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(chunk) => chunk.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(CaptureError::Disconnected),
+        }
+    }
+}
+
+impl Drop for ShellSource {
+    fn drop(&mut self) {
+        // Killing pw-record closes the pipe, which ends the reader thread
+        let _ = self.reader.kill();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Forward the f32le samples written by pw-record until it exits
+fn read_pcm(mut reader: &ReaderHandle, sender: mpsc::Sender<Chunk>) {
+    let mut buf = vec![0u8; 4096];
+    let mut pending = Vec::new();
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => {
+                if let Ok(Some(output)) = reader.try_wait()
+                    && !output.status.success()
+                {
+                    let _ = sender.send(Err(CaptureError::Stream(format!(
+                        "pw-record exited with {}",
+                        output.status
+                    ))));
+                }
+                return;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                let _ = sender.send(Err(e.into()));
+                return;
+            }
+        };
+
+        pending.extend_from_slice(&buf[..n]);
+        let whole = pending.len() - pending.len() % size_of::<f32>();
+        let samples = pending[..whole]
+            .chunks_exact(size_of::<f32>())
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        pending.drain(..whole);
+        if sender.send(Ok(samples)).is_err() {
+            return;
+        }
+    }
+}
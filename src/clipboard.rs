@@ -1,41 +1,377 @@
-use std::{path::Path, process::Command};
+mod shell;
+mod wayland;
+mod x11;
 
-use duct::cmd;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::LazyLock,
+};
 
-#[deprecated]
-pub fn write_image(path: &Path) {
-    let res = Command::new("xclip")
-        .arg("-selection")
-        .arg("clipboard")
-        .arg("-t")
-        .arg("image/png")
-        .arg("-i")
-        .arg(path)
-        .status()
-        .unwrap();
+use thiserror::Error;
 
-    if !res.success() {
-        eprintln!("Failed to write image to clipboard");
+use crate::process::find_executable;
+
+pub use x11::X11Error;
+
+/// Text types a sentence may be offered as, other text types (URIs, HTML) aren't sentences
+const PLAIN_TEXT: [&str; 5] = [
+    "text/plain;charset=utf-8",
+    "UTF8_STRING",
+    "text/plain",
+    "STRING",
+    "TEXT",
+];
+
+#[derive(Error, Debug)]
+pub enum ClipboardError {
+    #[error("No clipboard available, install xclip, xsel or wl-clipboard")]
+    Unavailable,
+    #[error("Failed to read {}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Wayland clipboard failed: {0}")]
+    WaylandCopy(#[from] wl_clipboard_rs::copy::Error),
+    #[error("Wayland clipboard failed: {0}")]
+    WaylandPaste(#[from] wl_clipboard_rs::paste::Error),
+    #[error("X11 clipboard failed: {0}")]
+    X11(#[from] X11Error),
+    #[error("Failed to read the clipboard: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Failed to run `{tool}`: {source}")]
+    Spawn {
+        tool: &'static str,
+        source: std::io::Error,
+    },
+    #[error("`{tool}` exited with {status}")]
+    ToolFailed {
+        tool: &'static str,
+        status: ExitStatus,
+    },
+    #[error("`{tool}` can't copy {mime_type}")]
+    Unsupported {
+        tool: &'static str,
+        mime_type: String,
+    },
+}
+
+/// Clipboard implementation, several may be available in a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardBackend {
+    /// In-process data-control (wlr or ext) client on Wayland
+    Wayland,
+    /// In-process selection owner on X11
+    X11,
+    /// `xclip` subprocess
+    Xclip,
+    /// `xsel` subprocess, which only copies text
+    Xsel,
+    /// `wl-copy`/`wl-paste` subprocesses
+    WlCopy,
+}
+
+impl ClipboardBackend {
+    fn write(self, offers: &[Offer]) -> Result<(), ClipboardError> {
+        match self {
+            ClipboardBackend::Wayland => Ok(wayland::write(offers)?),
+            ClipboardBackend::X11 => Ok(x11::write(offers)?),
+            ClipboardBackend::Xclip => shell::xclip_write(offers),
+            ClipboardBackend::Xsel => shell::xsel_write(offers),
+            ClipboardBackend::WlCopy => shell::wl_copy(offers),
+        }
+    }
+
+    fn read_text(self) -> Result<Option<String>, ClipboardError> {
+        match self {
+            ClipboardBackend::Wayland => wayland::read_text(),
+            ClipboardBackend::X11 => Ok(x11::read_text()?),
+            ClipboardBackend::Xclip => shell::xclip_read_text(),
+            ClipboardBackend::Xsel => shell::xsel_read_text(),
+            ClipboardBackend::WlCopy => shell::wl_paste_text(),
+        }
+    }
+}
+
+impl Display for ClipboardBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ClipboardBackend::Wayland => "Wayland",
+            ClipboardBackend::X11 => "X11",
+            ClipboardBackend::Xclip => "xclip",
+            ClipboardBackend::Xsel => "xsel",
+            ClipboardBackend::WlCopy => "wl-copy",
+        })
+    }
+}
+
+static BACKENDS: LazyLock<Vec<ClipboardBackend>> = LazyLock::new(detect_backends);
+
+/// Clipboards usable in this session, in the order they're tried
+pub fn backends() -> &'static [ClipboardBackend] {
+    &BACKENDS
+}
+
+/// The in-process clients come first, the native protocol of the session first: they serve every
+/// offered type at once, where the tools can only take one (xclip, wl-copy) or only text (xsel),
+/// losing the HTML and raw image/audio offers. The data-control protocols are missing on some
+/// compositors (e.g. GNOME), where XWayland's clipboard is used instead. The installed tools
+/// follow as fallbacks, in the order xclip, xsel, wl-copy.
+fn detect_backends() -> Vec<ClipboardBackend> {
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let x11 = std::env::var_os("DISPLAY").is_some();
+
+    let mut backends = Vec::new();
+    if wayland {
+        match wayland::read_text() {
+            Ok(_) => backends.push(ClipboardBackend::Wayland),
+            Err(e) => eprintln!("Wayland clipboard unavailable: {}", e),
+        }
+    }
+    if x11 {
+        match x11::read_text() {
+            Ok(_) => backends.push(ClipboardBackend::X11),
+            Err(e) => eprintln!("X11 clipboard unavailable: {}", e),
+        }
+    }
+    let tools = [
+        (ClipboardBackend::Xclip, "xclip", x11),
+        (ClipboardBackend::Xsel, "xsel", x11),
+        (ClipboardBackend::WlCopy, "wl-copy", wayland),
+    ];
+    for (backend, tool, usable) in tools {
+        if usable && find_executable(tool).is_some() {
+            backends.push(backend);
+        }
+    }
+    backends
+}
+
+/// One representation of the copied data, pasting apps pick the one they understand
+#[derive(Debug, Clone)]
+pub struct Offer {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Offer {
+    pub fn new(mime_type: &str, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            mime_type: mime_type.to_string(),
+            data: data.into(),
+        }
+    }
+}
+
+/// Take the clipboard, serving the offers until another app takes it. Each backend is tried in
+/// turn, returning the error of the last one if none works.
+pub fn write(offers: Vec<Offer>) -> Result<(), ClipboardError> {
+    let mut res = Err(ClipboardError::Unavailable);
+    for backend in backends() {
+        res = backend.write(&offers);
+        match &res {
+            Ok(()) => break,
+            Err(e) => eprintln!("{} clipboard failed to copy: {}", backend, e),
+        }
     }
+    res
+}
+
+/// Copy files so they can be pasted anywhere: as files in file managers and editors taking
+/// URIs, as an HTML fragment in rich text editors, and as raw content in apps that only take
+/// images or audio (the first file of each kind).
+pub fn write_files(paths: &[impl AsRef<Path>]) -> Result<(), ClipboardError> {
+    write(file_offers(paths)?)
 }
 
-pub fn write_file_uris(paths: &[impl AsRef<Path>]) {
-    let uri = paths
+/// The offers [`write_files`] copies
+fn file_offers(paths: &[impl AsRef<Path>]) -> Result<Vec<Offer>, ClipboardError> {
+    let paths = paths
         .iter()
         .map(|p| {
+            let path = p.as_ref();
+            path.canonicalize().map_err(|source| ClipboardError::File {
+                path: path.to_path_buf(),
+                source,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let uris: Vec<String> = paths.iter().map(|p| file_uri(p)).collect();
+
+    let html = paths
+        .iter()
+        .zip(&uris)
+        .filter_map(|(path, uri)| match mime_type(path)?.split_once('/')?.0 {
+            "image" => Some(format!("<img src=\"{}\">", html_escape(uri))),
+            "audio" => Some(format!(
+                "<audio controls src=\"{}\"></audio>",
+                html_escape(uri)
+            )),
+            _ => None,
+        })
+        .collect::<String>();
+
+    let mut offers = vec![
+        // First, since the xclip fallback only offers one type
+        Offer::new("text/uri-list", uris.join("\r\n")),
+        Offer::new(
+            "x-special/gnome-copied-files",
+            format!("copy\n{}", uris.join("\n")),
+        ),
+    ];
+    if !html.is_empty() {
+        offers.push(Offer::new("text/html", html));
+    }
+    for path in &paths {
+        let Some(mime_type) = mime_type(path) else {
+            continue;
+        };
+        if offers.iter().any(|o| o.mime_type == mime_type) {
+            continue;
+        }
+        let data = std::fs::read(path).map_err(|source| ClipboardError::File {
+            path: path.clone(),
+            source,
+        })?;
+        offers.push(Offer::new(mime_type, data));
+    }
+    Ok(offers)
+}
+
+/// MIME type of the images and audio we save, from their extension
+fn mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    Some(match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "gif" => "image/gif",
+        "mp3" => "audio/mpeg",
+        "opus" | "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        _ => return None,
+    })
+}
+
+/// `file://` URI of an absolute path, percent-encoding everything but unreserved characters
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// Escape text for HTML content and quoted attribute values
+pub fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Text currently in the clipboard, `None` if it holds something else (like our file URIs)
+pub fn read_text() -> Result<Option<String>, ClipboardError> {
+    let mut res = Err(ClipboardError::Unavailable);
+    for backend in backends() {
+        res = backend.read_text();
+        match &res {
+            Ok(_) => break,
+            Err(e) => eprintln!("{} clipboard failed to paste: {}", backend, e),
+        }
+    }
+    // Tools copying only text (xsel) hold our file URIs as text
+    Ok(res?
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty() && !t.lines().all(|line| line.starts_with("file://"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer<'a>(offers: &'a [Offer], mime_type: &str) -> &'a [u8] {
+        &offers
+            .iter()
+            .find(|o| o.mime_type == mime_type)
+            .unwrap_or_else(|| panic!("no {} offer", mime_type))
+            .data
+    }
+
+    #[test]
+    fn files_are_offered_in_every_format() {
+        let dir = std::env::temp_dir().join(format!("vn_record-clip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let image = dir.join("画面 1.png");
+        let audio = dir.join("line&1.mp3");
+        std::fs::write(&image, b"png data").unwrap();
+        std::fs::write(&audio, b"mp3 data").unwrap();
+
+        let offers = file_offers(&[&image, &audio]).unwrap();
+        assert_eq!(offers[0].mime_type, "text/uri-list");
+        let base = file_uri(&dir);
+        let image_uri = format!("{}/%E7%94%BB%E9%9D%A2%201.png", base);
+        let audio_uri = format!("{}/line%261.mp3", base);
+        assert_eq!(
+            offer(&offers, "text/uri-list"),
+            format!("{}\r\n{}", image_uri, audio_uri).as_bytes()
+        );
+        assert_eq!(
+            offer(&offers, "x-special/gnome-copied-files"),
+            format!("copy\n{}\n{}", image_uri, audio_uri).as_bytes()
+        );
+        assert_eq!(
+            offer(&offers, "text/html"),
             format!(
-                "file://{}",
-                p.as_ref().canonicalize().unwrap().to_string_lossy()
+                "<img src=\"{}\"><audio controls src=\"{}\"></audio>",
+                image_uri, audio_uri
             )
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-    let res = cmd("xclip", ["-selection", "clipboard", "-t", "text/uri-list"])
-        .stdin_bytes(uri.as_bytes())
-        .run()
-        .unwrap();
-
-    if !res.status.success() {
-        eprintln!("Failed to write file uris to clipboard");
+            .as_bytes()
+        );
+        assert_eq!(offer(&offers, "image/png"), b"png data");
+        assert_eq!(offer(&offers, "audio/mpeg"), b"mp3 data");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let path = std::env::temp_dir().join("vn_record-missing.png");
+        assert!(matches!(
+            file_offers(&[path]),
+            Err(ClipboardError::File { .. })
+        ));
+    }
+
+    #[test]
+    fn uris_encode_reserved_characters() {
+        assert_eq!(
+            file_uri(Path::new("/a b/c#d?e%f/ok-._~.wav")),
+            "file:///a%20b/c%23d%3Fe%25f/ok-._~.wav"
+        );
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(
+            html_escape(r#"<b>"A" & 'B'</b>"#),
+            "&lt;b&gt;&quot;A&quot; &amp; 'B'&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn mime_types_follow_extensions() {
+        assert_eq!(mime_type(Path::new("a.PNG")), Some("image/png"));
+        assert_eq!(mime_type(Path::new("a.opus")), Some("audio/ogg"));
+        assert_eq!(mime_type(Path::new("a_meta.json")), None);
+        assert_eq!(mime_type(Path::new("noext")), None);
     }
 }
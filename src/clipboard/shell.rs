@@ -0,0 +1,101 @@
+use duct::{Expression, cmd};
+
+use super::{ClipboardError, Offer, PLAIN_TEXT};
+
+/// Run a copying tool, which forks to serve the clipboard. Its output isn't captured, since the
+/// forked server would keep the pipes open.
+fn copy(tool: &'static str, expression: Expression, data: &[u8]) -> Result<(), ClipboardError> {
+    let res = expression
+        .stdin_bytes(data)
+        .stdout_null()
+        .unchecked()
+        .run()
+        .map_err(|source| ClipboardError::Spawn { tool, source })?;
+    if !res.status.success() {
+        return Err(ClipboardError::ToolFailed {
+            tool,
+            status: res.status,
+        });
+    }
+    Ok(())
+}
+
+/// Run a pasting tool, `None` if it fails, which they do when the clipboard is empty
+fn paste(tool: &'static str, expression: Expression) -> Result<Option<Vec<u8>>, ClipboardError> {
+    let res = expression
+        .stdout_capture()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .map_err(|source| ClipboardError::Spawn { tool, source })?;
+    Ok(res.status.success().then_some(res.stdout))
+}
+
+/// Copy with `xclip`. It can only offer one type, so the first offer is used.
+pub fn xclip_write(offers: &[Offer]) -> Result<(), ClipboardError> {
+    let Some(offer) = offers.first() else {
+        return Ok(());
+    };
+    let expression = cmd(
+        "xclip",
+        ["-selection", "clipboard", "-t", offer.mime_type.as_str()],
+    );
+    copy("xclip", expression, &offer.data)
+}
+
+pub fn xclip_read_text() -> Result<Option<String>, ClipboardError> {
+    let text = paste(
+        "xclip",
+        cmd(
+            "xclip",
+            ["-selection", "clipboard", "-o", "-t", "UTF8_STRING"],
+        ),
+    )?;
+    Ok(text.map(|t| String::from_utf8_lossy(&t).into_owned()))
+}
+
+/// Copy with `xsel`, which only copies text, so the first text offer is used
+pub fn xsel_write(offers: &[Offer]) -> Result<(), ClipboardError> {
+    let Some(offer) = offers.iter().find(|o| o.mime_type.starts_with("text/")) else {
+        return Err(ClipboardError::Unsupported {
+            tool: "xsel",
+            mime_type: offers
+                .first()
+                .map(|o| o.mime_type.clone())
+                .unwrap_or_default(),
+        });
+    };
+    copy("xsel", cmd("xsel", ["--clipboard", "--input"]), &offer.data)
+}
+
+pub fn xsel_read_text() -> Result<Option<String>, ClipboardError> {
+    let text = paste("xsel", cmd("xsel", ["--clipboard", "--output"]))?;
+    Ok(text.map(|t| String::from_utf8_lossy(&t).into_owned()))
+}
+
+/// Copy with `wl-copy`. It can only offer one type, so the first offer is used.
+pub fn wl_copy(offers: &[Offer]) -> Result<(), ClipboardError> {
+    let Some(offer) = offers.first() else {
+        return Ok(());
+    };
+    let expression = cmd("wl-copy", ["--type", offer.mime_type.as_str()]);
+    copy("wl-copy", expression, &offer.data)
+}
+
+pub fn wl_paste_text() -> Result<Option<String>, ClipboardError> {
+    let Some(types) = paste("wl-paste", cmd("wl-paste", ["--list-types"]))? else {
+        return Ok(None);
+    };
+    let types = String::from_utf8_lossy(&types);
+    let Some(mime_type) = PLAIN_TEXT
+        .iter()
+        .find(|plain| types.lines().any(|t| t == **plain))
+    else {
+        return Ok(None);
+    };
+    let text = paste(
+        "wl-paste",
+        cmd("wl-paste", ["--no-newline", "--type", mime_type]),
+    )?;
+    Ok(text.map(|t| String::from_utf8_lossy(&t).into_owned()))
+}
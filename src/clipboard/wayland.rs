@@ -0,0 +1,44 @@
+use std::io::Read;
+
+use wl_clipboard_rs::{
+    copy::{self, MimeSource, Source},
+    paste::{self, ClipboardType, Seat, get_contents},
+};
+
+use super::{ClipboardError, Offer, PLAIN_TEXT};
+
+/// Offer the data through the data-control protocol, served from a background thread
+pub fn write(offers: &[Offer]) -> Result<(), copy::Error> {
+    let sources = offers
+        .iter()
+        .map(|offer| MimeSource {
+            source: Source::Bytes(offer.data.clone().into()),
+            mime_type: copy::MimeType::Specific(offer.mime_type.clone()),
+        })
+        .collect();
+    let mut options = copy::Options::new();
+    // Pasting our URIs as text would be surprising, and the text hook would read them back
+    options.omit_additional_text_mime_types(true);
+    options.copy_multi(sources)
+}
+
+pub fn read_text() -> Result<Option<String>, ClipboardError> {
+    match get_contents(
+        ClipboardType::Regular,
+        Seat::Unspecified,
+        paste::MimeType::Text,
+    ) {
+        Ok((mut reader, mime_type)) => {
+            if !PLAIN_TEXT.contains(&mime_type.as_str()) {
+                return Ok(None);
+            }
+            let mut text = Vec::new();
+            reader.read_to_end(&mut text)?;
+            Ok(Some(String::from_utf8_lossy(&text).into_owned()))
+        }
+        Err(paste::Error::NoSeats | paste::Error::ClipboardEmpty | paste::Error::NoMimeType) => {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,288 @@
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use x11rb::{
+    CURRENT_TIME, NONE,
+    connection::{Connection, RequestConnection},
+    errors::{ConnectError, ConnectionError, ReplyError, ReplyOrIdError},
+    protocol::{
+        Event,
+        xproto::{
+            AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask,
+            PropMode, Property, SelectionNotifyEvent, SelectionRequestEvent, Window, WindowClass,
+        },
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+};
+
+use super::Offer;
+
+/// How long to wait for the clipboard owner to answer a read
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        CLIPBOARD,
+        TARGETS,
+        INCR,
+        UTF8_STRING,
+        VN_RECORD_CLIP,
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum X11Error {
+    #[error("Failed to connect to the X server: {0}")]
+    Connect(#[from] ConnectError),
+    #[error("X server connection error: {0}")]
+    Connection(#[from] ConnectionError),
+    #[error("X server request failed: {0}")]
+    Reply(#[from] ReplyError),
+    #[error("X server request failed: {0}")]
+    ReplyOrId(#[from] ReplyOrIdError),
+    #[error("Another client took the clipboard")]
+    NotOwner,
+}
+
+/// Hidden window owning the selection or receiving it
+fn create_window(conn: &RustConnection, screen: usize) -> Result<Window, X11Error> {
+    let root = conn.setup().roots[screen].root;
+    let window = conn.generate_id()?;
+    conn.create_window(
+        0,
+        window,
+        root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_ONLY,
+        0,
+        &CreateWindowAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+    )?;
+    Ok(window)
+}
+
+/// Own the clipboard and serve the offers from a background thread until another client takes it
+pub fn write(offers: &[Offer]) -> Result<(), X11Error> {
+    let (conn, screen) = x11rb::connect(None)?;
+    let atoms = Atoms::new(&conn)?.reply()?;
+    let window = create_window(&conn, screen)?;
+
+    let mut targets = Vec::with_capacity(offers.len());
+    for offer in offers {
+        let atom = conn
+            .intern_atom(false, offer.mime_type.as_bytes())?
+            .reply()?
+            .atom;
+        targets.push((atom, offer.data.clone()));
+    }
+
+    conn.set_selection_owner(window, atoms.CLIPBOARD, CURRENT_TIME)?;
+    if conn.get_selection_owner(atoms.CLIPBOARD)?.reply()?.owner != window {
+        return Err(X11Error::NotOwner);
+    }
+
+    std::thread::spawn(move || {
+        let owner = Owner {
+            conn,
+            atoms,
+            targets,
+            transfers: Vec::new(),
+        };
+        if let Err(e) = owner.serve() {
+            eprintln!("Clipboard owner stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Data sent in chunks to a requestor with the INCR protocol
+struct Transfer {
+    requestor: Window,
+    property: u32,
+    target: u32,
+    data: Vec<u8>,
+    offset: usize,
+}
+
+struct Owner {
+    conn: RustConnection,
+    atoms: Atoms,
+    /// Offered target atoms and their data
+    targets: Vec<(u32, Vec<u8>)>,
+    transfers: Vec<Transfer>,
+}
+
+impl Owner {
+    fn serve(mut self) -> Result<(), X11Error> {
+        loop {
+            match self.conn.wait_for_event()? {
+                Event::SelectionRequest(request) => self.answer(request)?,
+                Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                    self.continue_transfer(event.window, event.atom)?
+                }
+                Event::SelectionClear(event) if event.selection == self.atoms.CLIPBOARD => {
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Largest chunk of data that fits in one request
+    fn chunk_size(&self) -> usize {
+        self.conn
+            .maximum_request_bytes()
+            .saturating_sub(100)
+            .max(1024)
+    }
+
+    fn answer(&mut self, request: SelectionRequestEvent) -> Result<(), X11Error> {
+        // Obsolete clients may not set a property, the target is used instead
+        let property = if request.property == u32::from(AtomEnum::NONE) {
+            request.target
+        } else {
+            request.property
+        };
+
+        let accepted = if request.target == self.atoms.TARGETS {
+            let mut atoms = vec![self.atoms.TARGETS];
+            atoms.extend(self.targets.iter().map(|(atom, _)| *atom));
+            self.conn.change_property32(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                AtomEnum::ATOM,
+                &atoms,
+            )?;
+            true
+        } else if let Some((_, data)) = self.targets.iter().find(|(a, _)| *a == request.target) {
+            if data.len() > self.chunk_size() {
+                let transfer = Transfer {
+                    requestor: request.requestor,
+                    property,
+                    target: request.target,
+                    data: data.clone(),
+                    offset: 0,
+                };
+                // The requestor deleting the property asks for the next chunk
+                self.conn.change_window_attributes(
+                    request.requestor,
+                    &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+                )?;
+                self.conn.change_property32(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    self.atoms.INCR,
+                    &[u32::try_from(data.len()).unwrap_or(u32::MAX)],
+                )?;
+                self.transfers.push(transfer);
+            } else {
+                self.conn.change_property8(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    request.target,
+                    data,
+                )?;
+            }
+            true
+        } else {
+            false
+        };
+
+        let notify = SelectionNotifyEvent {
+            response_type: x11rb::protocol::xproto::SELECTION_NOTIFY_EVENT,
+            sequence: 0,
+            time: request.time,
+            requestor: request.requestor,
+            selection: request.selection,
+            target: request.target,
+            property: if accepted { property } else { NONE },
+        };
+        self.conn
+            .send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn continue_transfer(&mut self, window: Window, property: u32) -> Result<(), X11Error> {
+        let Some(index) = self
+            .transfers
+            .iter()
+            .position(|t| t.requestor == window && t.property == property)
+        else {
+            return Ok(());
+        };
+        let chunk_size = self.chunk_size();
+        let transfer = &mut self.transfers[index];
+        let end = (transfer.offset + chunk_size).min(transfer.data.len());
+        // The last chunk is empty, which ends the transfer
+        self.conn.change_property8(
+            PropMode::REPLACE,
+            transfer.requestor,
+            transfer.property,
+            transfer.target,
+            &transfer.data[transfer.offset..end],
+        )?;
+        if transfer.offset == end {
+            self.transfers.swap_remove(index);
+        } else {
+            transfer.offset = end;
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+/// Ask the clipboard owner for UTF-8 text, `None` if there's no owner or it doesn't have text
+pub fn read_text() -> Result<Option<String>, X11Error> {
+    let (conn, screen) = x11rb::connect(None)?;
+    let atoms = Atoms::new(&conn)?.reply()?;
+    if conn.get_selection_owner(atoms.CLIPBOARD)?.reply()?.owner == NONE {
+        return Ok(None);
+    }
+
+    let window = create_window(&conn, screen)?;
+    conn.convert_selection(
+        window,
+        atoms.CLIPBOARD,
+        atoms.UTF8_STRING,
+        atoms.VN_RECORD_CLIP,
+        CURRENT_TIME,
+    )?;
+    conn.flush()?;
+
+    let deadline = Instant::now() + READ_TIMEOUT;
+    let notify = loop {
+        match conn.poll_for_event()? {
+            Some(Event::SelectionNotify(event)) if event.requestor == window => break event,
+            Some(_) => {}
+            None if Instant::now() >= deadline => return Ok(None),
+            None => std::thread::sleep(Duration::from_millis(5)),
+        }
+    };
+    if notify.property == NONE {
+        return Ok(None);
+    }
+
+    let reply = conn
+        .get_property(
+            true,
+            window,
+            atoms.VN_RECORD_CLIP,
+            AtomEnum::ANY,
+            0,
+            u32::MAX / 4,
+        )?
+        .reply()?;
+    // Sentences are short, large INCR transfers aren't worth following
+    if reply.type_ == atoms.INCR {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8_lossy(&reply.value).into_owned()))
+}
@@ -0,0 +1,838 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use global_hotkey::hotkey::{Code, HotKey, Modifiers};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    animation::AnimationFormat,
+    capture::CaptureBackend,
+    crop::CropRect,
+    encode::AudioFormat,
+    hotkey::{GHKMessage, find_conflicts},
+    process::WindowTarget,
+    screenshot::{ScreenshotFormat, ScreenshotTiming},
+};
+
+/// Current version of the config file layout, bump when making a breaking change or adding a
+/// hotkey action (see [`hotkey_added_in`]).
+pub const CONFIG_VERSION: u32 = 2;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub version: u32,
+    pub output_dir: PathBuf,
+    pub hotkeys: BTreeMap<GHKMessage, HotKey>,
+    pub trim: TrimConfig,
+    pub loudness: LoudnessConfig,
+    pub background: BackgroundConfig,
+    pub audio: AudioConfig,
+    pub replay: ReplayConfig,
+    pub vad: VadConfig,
+    pub feedback: FeedbackConfig,
+    pub anki: AnkiConfig,
+    pub text_hook: TextHookConfig,
+    pub screenshot: ScreenshotConfig,
+    pub animation: AnimationConfig,
+    /// Window of the game selected last, attached again on startup
+    pub last_window: Option<WindowTarget>,
+    /// Games selected recently, latest first, pinned at the top of the process picker
+    pub recent_windows: Vec<WindowTarget>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrimConfig {
+    pub enabled: bool,
+    /// Amplitude (in percent) below which audio is considered silence
+    pub threshold_percent: f32,
+    /// Minimum duration (in seconds) of non-silence before trimming stops
+    pub min_duration_secs: f32,
+    /// Length of the fade in/out applied to the record's edges, 0 to disable
+    pub fade_ms: u32,
+}
+
+/// EBU R128 loudness normalization, so every record plays at a similar volume
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoudnessConfig {
+    pub enabled: bool,
+    /// Integrated loudness (in LUFS) records are normalized to
+    pub target_lufs: f32,
+}
+
+/// Suppression of background music/noise under the voice, through spectral subtraction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackgroundConfig {
+    pub enabled: bool,
+    /// How much of the estimated background spectrum is subtracted, above 1 to over-subtract
+    pub strength: f32,
+    /// Fraction (0 to 1) of the original signal always kept, higher sounds more natural
+    pub floor: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub backend: CaptureBackend,
+    /// Record only the selected process's audio streams instead of every application's
+    pub per_application: bool,
+    pub format: AudioFormat,
+    /// lame VBR quality, 0 (best) to 9 (smallest)
+    pub mp3_vbr_quality: u8,
+    pub opus_bitrate_kbps: u32,
+    /// oggenc quality, -1 (smallest) to 10 (best)
+    pub vorbis_quality: f32,
+    /// flac compression level, 0 (fastest) to 8 (smallest)
+    pub flac_compression: u8,
+    /// Downmix to a single channel
+    pub mono: bool,
+    /// Resample to this rate (in Hz), keeping the capture rate if unset
+    pub sample_rate: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    /// Keep the selected process's recent audio in memory so it can be saved after the fact
+    pub enabled: bool,
+    pub duration_secs: u32,
+}
+
+/// How the outcome of actions is reported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedbackConfig {
+    /// Show success toasts inside the app window, errors are always shown
+    pub toasts: bool,
+    /// Show freedesktop notifications, visible even when the game is fullscreen
+    pub desktop_notifications: bool,
+    /// Play short sounds on record start/stop, copy and failure
+    pub sounds: bool,
+    /// Volume of the sounds, from 0 to 1
+    pub sound_volume: f32,
+}
+
+/// Exporting records to Anki notes through the AnkiConnect add-on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnkiConfig {
+    /// Address of the AnkiConnect HTTP server
+    pub url: String,
+    /// Note field the audio is put in, empty to skip it
+    pub audio_field: String,
+    /// Note field the screenshot is put in, empty to skip it
+    pub picture_field: String,
+    /// Note field the animation is put in, empty to skip it
+    pub animation_field: String,
+    /// Note field the captured sentence is put in, empty to skip it
+    pub sentence_field: String,
+    /// Show the updated note in Anki's browser
+    pub open_browser: bool,
+}
+
+/// Pairing records with the sentences a text hooker copies to the clipboard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextHookConfig {
+    pub enabled: bool,
+    /// How often the clipboard is checked for a new sentence
+    pub poll_ms: u32,
+}
+
+/// Timing, cropping and encoding of the screenshots saved with records
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenshotConfig {
+    pub format: ScreenshotFormat,
+    /// Screenshots are scaled down to fit these dimensions, unlimited if unset
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// JPEG, WebP and AVIF quality, 1 (smallest) to 100 (best)
+    pub quality: u8,
+    /// Save without the alpha channel, which game windows don't use
+    pub strip_alpha: bool,
+    pub timing: ScreenshotTiming,
+    /// Delay after the recording starts for the offset timing
+    pub offset_ms: u32,
+    /// Frames kept by the burst timing, spread over the recording
+    pub burst_count: u32,
+    pub burst_interval_ms: u32,
+    /// Cut the black bars (letterboxing) around the picture
+    pub crop_black_bars: bool,
+    /// Channel level (0-255) at or below which a pixel counts as black
+    pub black_threshold: u8,
+    /// Region kept from each game's window, by game name
+    pub crops: BTreeMap<String, CropRect>,
+}
+
+/// Animation of the window saved alongside manual records
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnimationConfig {
+    pub enabled: bool,
+    pub format: AnimationFormat,
+    /// Frames sampled per second
+    pub fps: u32,
+    /// Frames are scaled down to this width
+    pub max_width: u32,
+    /// Only the beginning of longer recordings is animated
+    pub max_secs: u32,
+    /// Frames are scaled down further until the file fits, Anki syncs media up to a few MiB
+    pub max_size_kb: u32,
+}
+
+/// Voice activity detection used by the automatic recording mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VadConfig {
+    /// RMS level (in dBFS) above which audio is considered voice
+    pub threshold_db: f32,
+    /// How long the audio must stay below the threshold before an utterance ends
+    pub hangover_ms: u32,
+    /// Utterances with less voiced audio than this are discarded
+    pub min_utterance_ms: u32,
+    /// Audio kept from before the utterance starts
+    pub pre_roll_ms: u32,
+    /// Utterances are split when they get longer than this
+    pub max_utterance_secs: u32,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to write config file {}: {source}", path.display())]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse config file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Failed to serialize config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Config version {0} is newer than supported version {CONFIG_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Invalid value for {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+    #[error("Could not determine the config directory")]
+    NoConfigDir,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            output_dir: default_output_dir(),
+            hotkeys: default_hotkeys(),
+            trim: TrimConfig::default(),
+            loudness: LoudnessConfig::default(),
+            background: BackgroundConfig::default(),
+            audio: AudioConfig::default(),
+            replay: ReplayConfig::default(),
+            vad: VadConfig::default(),
+            feedback: FeedbackConfig::default(),
+            anki: AnkiConfig::default(),
+            text_hook: TextHookConfig::default(),
+            screenshot: ScreenshotConfig::default(),
+            animation: AnimationConfig::default(),
+            last_window: None,
+            recent_windows: Vec::new(),
+        }
+    }
+}
+
+impl Default for TrimConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold_percent: 1.0,
+            min_duration_secs: 0.1,
+            fade_ms: 10,
+        }
+    }
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_lufs: -18.0,
+        }
+    }
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            strength: 1.5,
+            floor: 0.05,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            backend: CaptureBackend::default(),
+            per_application: true,
+            format: AudioFormat::default(),
+            mp3_vbr_quality: 7,
+            opus_bitrate_kbps: 64,
+            vorbis_quality: 4.0,
+            flac_compression: 5,
+            mono: false,
+            sample_rate: None,
+        }
+    }
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            duration_secs: 15,
+        }
+    }
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self {
+            toasts: true,
+            desktop_notifications: false,
+            sounds: false,
+            sound_volume: 0.5,
+        }
+    }
+}
+
+impl Default for AnkiConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8765".to_string(),
+            audio_field: "Audio".to_string(),
+            picture_field: "Picture".to_string(),
+            animation_field: String::new(),
+            sentence_field: String::new(),
+            open_browser: true,
+        }
+    }
+}
+
+impl Default for TextHookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poll_ms: 250,
+        }
+    }
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        Self {
+            format: ScreenshotFormat::default(),
+            max_width: None,
+            max_height: None,
+            quality: 85,
+            strip_alpha: true,
+            timing: ScreenshotTiming::default(),
+            offset_ms: 1000,
+            burst_count: 5,
+            burst_interval_ms: 500,
+            crop_black_bars: false,
+            black_threshold: 16,
+            crops: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            format: AnimationFormat::default(),
+            fps: 10,
+            max_width: 480,
+            max_secs: 8,
+            max_size_kb: 2048,
+        }
+    }
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db: -40.0,
+            hangover_ms: 600,
+            min_utterance_ms: 300,
+            pre_roll_ms: 200,
+            max_utterance_secs: 30,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from the default location, or the default config if no file exists yet.
+    pub fn load() -> Result<Config, ConfigError> {
+        Self::load_from(&config_path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Config, ConfigError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_path_buf(),
+                    source,
+                });
+            }
+        };
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Config, ConfigError> {
+        let mut config: Config = toml::from_str(content)?;
+        if config.version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(config.version));
+        }
+        // Older versions only lack fields which are filled by defaults, and hotkeys of the
+        // actions added since. Actions the user unbound are left unbound.
+        let version = std::mem::replace(&mut config.version, CONFIG_VERSION);
+        for (msg, key) in default_hotkeys() {
+            if hotkey_added_in(msg) > version
+                && !config.hotkeys.values().any(|k| k.id() == key.id())
+            {
+                config.hotkeys.entry(msg).or_insert(key);
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Save the config to the default location.
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.save_to(&config_path()?)
+    }
+
+    /// Write the config by writing to a temporary file then renaming it over the old one,
+    /// so a crash mid-write never leaves a truncated config behind.
+    pub fn save_to(&self, path: &Path) -> Result<(), ConfigError> {
+        self.validate()?;
+        let content = toml::to_string_pretty(self)?;
+
+        let write_err = |source| ConfigError::Write {
+            path: path.to_path_buf(),
+            source,
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(write_err)?;
+        }
+        let tmp_path = path.with_extension("toml.tmp");
+        let mut file = std::fs::File::create(&tmp_path).map_err(write_err)?;
+        file.write_all(content.as_bytes()).map_err(write_err)?;
+        file.sync_all().map_err(write_err)?;
+        std::fs::rename(&tmp_path, path).map_err(write_err)?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        fn invalid(field: &'static str, reason: impl Into<String>) -> ConfigError {
+            ConfigError::Invalid {
+                field,
+                reason: reason.into(),
+            }
+        }
+
+        if self.output_dir.as_os_str().is_empty() {
+            return Err(invalid("output_dir", "must not be empty"));
+        }
+        if !self.output_dir.is_absolute() {
+            return Err(invalid("output_dir", "must be an absolute path"));
+        }
+        if !(self.trim.threshold_percent > 0.0 && self.trim.threshold_percent <= 100.0) {
+            return Err(invalid(
+                "trim.threshold_percent",
+                format!("{} is not in range (0, 100]", self.trim.threshold_percent),
+            ));
+        }
+        if !(self.trim.min_duration_secs >= 0.0 && self.trim.min_duration_secs.is_finite()) {
+            return Err(invalid(
+                "trim.min_duration_secs",
+                format!(
+                    "{} is not a non-negative duration",
+                    self.trim.min_duration_secs
+                ),
+            ));
+        }
+        if self.trim.fade_ms > 1000 {
+            return Err(invalid(
+                "trim.fade_ms",
+                format!("{} is not in range 0-1000", self.trim.fade_ms),
+            ));
+        }
+        if !(-50.0..=-5.0).contains(&self.loudness.target_lufs) {
+            return Err(invalid(
+                "loudness.target_lufs",
+                format!("{} is not in range -50 to -5", self.loudness.target_lufs),
+            ));
+        }
+        if !(0.0..=4.0).contains(&self.background.strength) {
+            return Err(invalid(
+                "background.strength",
+                format!("{} is not in range 0-4", self.background.strength),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.background.floor) {
+            return Err(invalid(
+                "background.floor",
+                format!("{} is not in range 0-1", self.background.floor),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.feedback.sound_volume) {
+            return Err(invalid(
+                "feedback.sound_volume",
+                format!("{} is not in range 0-1", self.feedback.sound_volume),
+            ));
+        }
+        if !(self.anki.url.starts_with("http://") || self.anki.url.starts_with("https://")) {
+            return Err(invalid(
+                "anki.url",
+                format!("{:?} is not an http:// or https:// URL", self.anki.url),
+            ));
+        }
+        if !(50..=5000).contains(&self.text_hook.poll_ms) {
+            return Err(invalid(
+                "text_hook.poll_ms",
+                format!("{} is not in range 50-5000", self.text_hook.poll_ms),
+            ));
+        }
+        if !(1..=100).contains(&self.screenshot.quality) {
+            return Err(invalid(
+                "screenshot.quality",
+                format!("{} is not in range 1-100", self.screenshot.quality),
+            ));
+        }
+        for (field, max) in [
+            ("screenshot.max_width", self.screenshot.max_width),
+            ("screenshot.max_height", self.screenshot.max_height),
+        ] {
+            if let Some(max) = max
+                && max < 16
+            {
+                return Err(invalid(field, format!("{} is below 16", max)));
+            }
+        }
+        if !(2..=10).contains(&self.screenshot.burst_count) {
+            return Err(invalid(
+                "screenshot.burst_count",
+                format!("{} is not in range 2-10", self.screenshot.burst_count),
+            ));
+        }
+        if !(100..=10000).contains(&self.screenshot.burst_interval_ms) {
+            return Err(invalid(
+                "screenshot.burst_interval_ms",
+                format!(
+                    "{} is not in range 100-10000",
+                    self.screenshot.burst_interval_ms
+                ),
+            ));
+        }
+        if !(1..=30).contains(&self.animation.fps) {
+            return Err(invalid(
+                "animation.fps",
+                format!("{} is not in range 1-30", self.animation.fps),
+            ));
+        }
+        if !(64..=3840).contains(&self.animation.max_width) {
+            return Err(invalid(
+                "animation.max_width",
+                format!("{} is not in range 64-3840", self.animation.max_width),
+            ));
+        }
+        if !(1..=60).contains(&self.animation.max_secs) {
+            return Err(invalid(
+                "animation.max_secs",
+                format!("{} is not in range 1-60", self.animation.max_secs),
+            ));
+        }
+        if self.animation.max_size_kb < 64 {
+            return Err(invalid(
+                "animation.max_size_kb",
+                format!("{} is below 64", self.animation.max_size_kb),
+            ));
+        }
+        if let Some((game, _)) = self
+            .screenshot
+            .crops
+            .iter()
+            .find(|(_, crop)| crop.width == 0 || crop.height == 0)
+        {
+            return Err(invalid(
+                "screenshot.crops",
+                format!("crop of {:?} is empty", game),
+            ));
+        }
+        if let Some((hotkey, messages)) = find_conflicts(&self.hotkeys).first() {
+            let labels: Vec<_> = messages.iter().map(|m| m.label()).collect();
+            return Err(invalid(
+                "hotkeys",
+                format!(
+                    "{} is bound to multiple actions: {}",
+                    hotkey,
+                    labels.join(", ")
+                ),
+            ));
+        }
+        if !(1..=300).contains(&self.replay.duration_secs) {
+            return Err(invalid(
+                "replay.duration_secs",
+                format!("{} is not in range 1-300", self.replay.duration_secs),
+            ));
+        }
+        if !(self.vad.threshold_db < 0.0 && self.vad.threshold_db.is_finite()) {
+            return Err(invalid(
+                "vad.threshold_db",
+                format!("{} is not a negative level", self.vad.threshold_db),
+            ));
+        }
+        if !(20..=5000).contains(&self.vad.hangover_ms) {
+            return Err(invalid(
+                "vad.hangover_ms",
+                format!("{} is not in range 20-5000", self.vad.hangover_ms),
+            ));
+        }
+        if self.vad.min_utterance_ms > 10000 {
+            return Err(invalid(
+                "vad.min_utterance_ms",
+                format!("{} is not in range 0-10000", self.vad.min_utterance_ms),
+            ));
+        }
+        if self.vad.pre_roll_ms > 2000 {
+            return Err(invalid(
+                "vad.pre_roll_ms",
+                format!("{} is not in range 0-2000", self.vad.pre_roll_ms),
+            ));
+        }
+        if !(1..=300).contains(&self.vad.max_utterance_secs) {
+            return Err(invalid(
+                "vad.max_utterance_secs",
+                format!("{} is not in range 1-300", self.vad.max_utterance_secs),
+            ));
+        }
+        if self.vad.min_utterance_ms as u64 > self.vad.max_utterance_secs as u64 * 1000 {
+            return Err(invalid(
+                "vad.min_utterance_ms",
+                "is longer than vad.max_utterance_secs",
+            ));
+        }
+        if self.audio.mp3_vbr_quality > 9 {
+            return Err(invalid(
+                "audio.mp3_vbr_quality",
+                format!("{} is not in range 0-9", self.audio.mp3_vbr_quality),
+            ));
+        }
+        if !(6..=510).contains(&self.audio.opus_bitrate_kbps) {
+            return Err(invalid(
+                "audio.opus_bitrate_kbps",
+                format!("{} is not in range 6-510", self.audio.opus_bitrate_kbps),
+            ));
+        }
+        if !(-1.0..=10.0).contains(&self.audio.vorbis_quality) {
+            return Err(invalid(
+                "audio.vorbis_quality",
+                format!("{} is not in range -1 to 10", self.audio.vorbis_quality),
+            ));
+        }
+        if self.audio.flac_compression > 8 {
+            return Err(invalid(
+                "audio.flac_compression",
+                format!("{} is not in range 0-8", self.audio.flac_compression),
+            ));
+        }
+        if let Some(rate) = self.audio.sample_rate
+            && !(8000..=192000).contains(&rate)
+        {
+            return Err(invalid(
+                "audio.sample_rate",
+                format!("{} is not in range 8000-192000", rate),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn config_path() -> Result<PathBuf, ConfigError> {
+    let mut path = dirs::config_dir().ok_or(ConfigError::NoConfigDir)?;
+    path.push("vn_record");
+    path.push(CONFIG_FILE_NAME);
+    Ok(path)
+}
+
+/// `~/.local/share/vn_record`, or `vn_record` in the working directory if there's no home
+pub fn default_output_dir() -> PathBuf {
+    dirs::data_local_dir().unwrap_or_default().join("vn_record")
+}
+
+/// Config version an action's hotkey was added in. Its default key is bound when loading older
+/// configs, which can't have unbound it.
+fn hotkey_added_in(msg: GHKMessage) -> u32 {
+    match msg {
+        GHKMessage::Record
+        | GHKMessage::CopyLastRecord
+        | GHKMessage::CopyLastScreenshot
+        | GHKMessage::CopyLastAudio => 1,
+        GHKMessage::SaveReplay | GHKMessage::SendToAnki => 2,
+    }
+}
+
+pub fn default_hotkeys() -> BTreeMap<GHKMessage, HotKey> {
+    BTreeMap::from([
+        (
+            GHKMessage::Record,
+            HotKey::new(Some(Modifiers::SHIFT), Code::Space),
+        ),
+        (
+            GHKMessage::CopyLastRecord,
+            HotKey::new(Some(Modifiers::SHIFT), Code::Digit1),
+        ),
+        (
+            GHKMessage::CopyLastScreenshot,
+            HotKey::new(Some(Modifiers::SHIFT), Code::Digit2),
+        ),
+        (
+            GHKMessage::CopyLastAudio,
+            HotKey::new(Some(Modifiers::SHIFT), Code::Digit3),
+        ),
+        (
+            GHKMessage::SaveReplay,
+            HotKey::new(Some(Modifiers::SHIFT), Code::Digit4),
+        ),
+        (
+            GHKMessage::SendToAnki,
+            HotKey::new(Some(Modifiers::SHIFT), Code::Digit5),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_is_the_default_config() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.version, CONFIG_VERSION);
+    }
+
+    #[test]
+    fn missing_fields_are_filled_by_defaults() {
+        let config = Config::parse("[trim]\nthreshold_percent = 5.0\n").unwrap();
+        assert_eq!(config.trim.threshold_percent, 5.0);
+        assert_eq!(config.trim.fade_ms, TrimConfig::default().fade_ms);
+        assert_eq!(config.audio, AudioConfig::default());
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let content = format!("version = {}", CONFIG_VERSION + 1);
+        assert!(matches!(
+            Config::parse(&content),
+            Err(ConfigError::UnsupportedVersion(v)) if v == CONFIG_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn invalid_value_names_its_field() {
+        let res = Config::parse("[trim]\nthreshold_percent = 0.0\n");
+        assert!(matches!(
+            res,
+            Err(ConfigError::Invalid {
+                field: "trim.threshold_percent",
+                ..
+            })
+        ));
+        assert!(matches!(
+            Config::parse("output_dir = \"records\""),
+            Err(ConfigError::Invalid {
+                field: "output_dir",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unbound_hotkeys_stay_unbound() {
+        let mut config = Config::default();
+        config.hotkeys.remove(&GHKMessage::Record);
+        config.hotkeys.remove(&GHKMessage::SendToAnki);
+        let content = toml::to_string_pretty(&config).unwrap();
+        assert_eq!(Config::parse(&content).unwrap().hotkeys, config.hotkeys);
+    }
+
+    #[test]
+    fn hotkeys_added_since_are_bound() {
+        let content = "version = 1\n[hotkeys]\nrecord = \"shift+KeyR\"\n";
+        let config = Config::parse(content).unwrap();
+        let bound: Vec<_> = config.hotkeys.keys().copied().collect();
+        assert_eq!(
+            bound,
+            [
+                GHKMessage::Record,
+                GHKMessage::SaveReplay,
+                GHKMessage::SendToAnki
+            ]
+        );
+    }
+
+    #[test]
+    fn vad_lengths_are_validated() {
+        for (content, field) in [
+            ("[vad]\nhangover_ms = 0", "vad.hangover_ms"),
+            ("[vad]\nmin_utterance_ms = 20000", "vad.min_utterance_ms"),
+            ("[vad]\npre_roll_ms = 5000", "vad.pre_roll_ms"),
+            ("[vad]\nmax_utterance_secs = 0", "vad.max_utterance_secs"),
+            (
+                "[vad]\nmin_utterance_ms = 3000\nmax_utterance_secs = 2",
+                "vad.min_utterance_ms",
+            ),
+        ] {
+            let res = Config::parse(content);
+            assert!(
+                matches!(res, Err(ConfigError::Invalid { field: f, .. }) if f == field),
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn saved_config_loads_back() {
+        let dir = std::env::temp_dir().join(format!("vn_record-config-{}", std::process::id()));
+        let path = dir.join(CONFIG_FILE_NAME);
+        assert_eq!(Config::load_from(&path).unwrap(), Config::default());
+
+        let mut config = Config::default();
+        config.trim.fade_ms = 25;
+        config.audio.format = AudioFormat::Opus;
+        config.save_to(&path).unwrap();
+        assert!(!path.with_extension("toml.tmp").exists());
+        assert_eq!(Config::load_from(&path).unwrap(), config);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
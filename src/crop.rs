@@ -0,0 +1,361 @@
+use iced::{
+    Alignment::Center,
+    Border, Color, ContentFit, Element, Point, Theme, mouse,
+    widget::{Column, Row, button, container, image, mouse_area, space, stack, text},
+};
+use serde::{Deserialize, Serialize};
+use xcap::{
+    XCapError,
+    image::{RgbaImage, imageops},
+};
+
+use crate::{Message, config::ScreenshotConfig, process::Process};
+
+/// Width the window is shown at while choosing the crop
+const PREVIEW_WIDTH: f32 = 800.0;
+/// Smaller selections are taken as a click, not a crop
+const MIN_CROP_SIZE: u32 = 8;
+
+/// Region of a game window, in pixels of the window at the size it had when the region was chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Size of the window the region was chosen on, 0 for crops saved before it was kept, which
+    /// are used unscaled
+    #[serde(default)]
+    pub window_width: u32,
+    #[serde(default)]
+    pub window_height: u32,
+}
+
+impl CropRect {
+    /// Rectangle spanning two corners of a window of the given size
+    fn from_corners(
+        (x1, y1): (u32, u32),
+        (x2, y2): (u32, u32),
+        (window_width, window_height): (u32, u32),
+    ) -> Self {
+        Self {
+            x: x1.min(x2),
+            y: y1.min(y2),
+            width: x1.abs_diff(x2),
+            height: y1.abs_diff(y2),
+            window_width,
+            window_height,
+        }
+    }
+
+    /// The same region of the window at another size, so a crop follows a resized window
+    fn scale_to(self, width: u32, height: u32) -> Self {
+        if self.window_width == 0 || self.window_height == 0 {
+            return self;
+        }
+        let scale = |v: u32, from: u32, to: u32| (v as u64 * to as u64 / from as u64) as u32;
+        let x = |v| scale(v, self.window_width, width);
+        let y = |v| scale(v, self.window_height, height);
+        Self {
+            x: x(self.x),
+            y: y(self.y),
+            width: x(self.x + self.width) - x(self.x),
+            height: y(self.y + self.height) - y(self.y),
+            window_width: width,
+            window_height: height,
+        }
+    }
+
+    /// The part of the rectangle inside an image, `None` if there's none
+    fn clamp(self, width: u32, height: u32) -> Option<Self> {
+        let right = (self.x + self.width).min(width);
+        let bottom = (self.y + self.height).min(height);
+        (self.x < right && self.y < bottom).then(|| Self {
+            width: right - self.x,
+            height: bottom - self.y,
+            ..self
+        })
+    }
+}
+
+/// Crop a screenshot of a game to its saved region, then cut the black bars left around the
+/// picture if enabled.
+pub fn apply(mut screenshot: RgbaImage, game: &str, config: &ScreenshotConfig) -> RgbaImage {
+    if let Some(rect) = config.crops.get(game) {
+        screenshot = crop(&screenshot, *rect).unwrap_or(screenshot);
+    }
+    if config.crop_black_bars
+        && let Some(rect) = detect_black_bars(&screenshot, config.black_threshold)
+    {
+        screenshot = crop(&screenshot, rect).unwrap_or(screenshot);
+    }
+    screenshot
+}
+
+fn crop(image: &RgbaImage, rect: CropRect) -> Option<RgbaImage> {
+    let (width, height) = image.dimensions();
+    let rect = rect.scale_to(width, height).clamp(width, height)?;
+    Some(imageops::crop_imm(image, rect.x, rect.y, rect.width, rect.height).to_image())
+}
+
+/// The picture inside uniform black borders, `None` if there are no borders or the whole image
+/// is black (like during a fade).
+pub fn detect_black_bars(image: &RgbaImage, threshold: u8) -> Option<CropRect> {
+    let (width, height) = image.dimensions();
+    let is_black = |x: u32, y: u32| {
+        let [r, g, b, _] = image.get_pixel(x, y).0;
+        r.max(g).max(b) <= threshold
+    };
+    let row_is_black = |y: u32| (0..width).all(|x| is_black(x, y));
+
+    let top = (0..height).find(|&y| !row_is_black(y))?;
+    let bottom = (top..height).rev().find(|&y| !row_is_black(y))? + 1;
+    let col_is_black = |x: u32| (top..bottom).all(|y| is_black(x, y));
+    let left = (0..width).find(|&x| !col_is_black(x))?;
+    let right = (left..width).rev().find(|&x| !col_is_black(x))? + 1;
+
+    let rect = CropRect {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+        window_width: width,
+        window_height: height,
+    };
+    (rect.width != width || rect.height != height).then_some(rect)
+}
+
+#[derive(Debug, Clone)]
+pub enum CropAction {
+    /// Cursor moved over the preview, in preview coordinates
+    Move(Point),
+    Press,
+    Release,
+    DetectBlackBars,
+    Clear,
+    Save,
+}
+
+/// Page choosing the region of a game's window kept in screenshots, by dragging on a preview
+pub struct CropEditor {
+    game: String,
+    preview: image::Handle,
+    size: (u32, u32),
+    screenshot: RgbaImage,
+    black_threshold: u8,
+    rect: Option<CropRect>,
+    /// Corner the drag started from, in window pixels
+    drag_from: Option<(u32, u32)>,
+    cursor: (u32, u32),
+}
+
+impl CropEditor {
+    pub fn new(process: &Process, config: &ScreenshotConfig) -> Result<Self, XCapError> {
+        let screenshot = process.capture_image()?;
+        let size = screenshot.dimensions();
+        Ok(Self {
+            game: process.game().to_string(),
+            preview: image::Handle::from_rgba(size.0, size.1, screenshot.as_raw().clone()),
+            size,
+            screenshot,
+            black_threshold: config.black_threshold,
+            rect: config
+                .crops
+                .get(process.game())
+                .and_then(|rect| rect.scale_to(size.0, size.1).clamp(size.0, size.1)),
+            drag_from: None,
+            cursor: (0, 0),
+        })
+    }
+
+    pub fn game(&self) -> &str {
+        &self.game
+    }
+
+    /// Chosen crop, `None` keeps the whole window
+    pub fn rect(&self) -> Option<CropRect> {
+        self.rect
+    }
+
+    fn scale(&self) -> f32 {
+        PREVIEW_WIDTH / self.size.0.max(1) as f32
+    }
+
+    pub fn update(&mut self, action: CropAction) {
+        match action {
+            CropAction::Move(point) => {
+                let scale = self.scale();
+                self.cursor = (
+                    ((point.x / scale) as u32).min(self.size.0),
+                    ((point.y / scale) as u32).min(self.size.1),
+                );
+                if let Some(from) = self.drag_from {
+                    self.rect = Some(CropRect::from_corners(from, self.cursor, self.size));
+                }
+            }
+            CropAction::Press => {
+                self.drag_from = Some(self.cursor);
+                self.rect = None;
+            }
+            CropAction::Release => {
+                self.drag_from = None;
+                self.rect
+                    .take_if(|r| r.width < MIN_CROP_SIZE || r.height < MIN_CROP_SIZE);
+            }
+            CropAction::DetectBlackBars => {
+                self.rect = detect_black_bars(&self.screenshot, self.black_threshold);
+            }
+            CropAction::Clear => self.rect = None,
+            // Handled by the app, which owns the config
+            CropAction::Save => {}
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let scale = self.scale();
+        let (width, height) = (PREVIEW_WIDTH, self.size.1 as f32 * scale);
+
+        let preview = image(self.preview.clone())
+            .width(width)
+            .height(height)
+            .content_fit(ContentFit::Fill);
+        let selection = self.rect.map(|rect| {
+            let outline = container(space())
+                .width(rect.width as f32 * scale)
+                .height(rect.height as f32 * scale)
+                .style(|theme: &Theme| {
+                    let color = theme.palette().primary;
+                    container::Style {
+                        background: Some(Color { a: 0.2, ..color }.into()),
+                        border: Border {
+                            color,
+                            width: 2.0,
+                            radius: 0.0.into(),
+                        },
+                        ..Default::default()
+                    }
+                });
+            Column::new()
+                .push(space().height(rect.y as f32 * scale))
+                .push(
+                    Row::new()
+                        .push(space().width(rect.x as f32 * scale))
+                        .push(outline),
+                )
+        });
+        let mut layers = vec![preview.into()];
+        layers.extend(selection.map(Element::from));
+        let area = mouse_area(stack(layers))
+            .on_move(|p| Message::Crop(CropAction::Move(p)))
+            .on_press(Message::Crop(CropAction::Press))
+            .on_release(Message::Crop(CropAction::Release))
+            .interaction(mouse::Interaction::Crosshair);
+
+        let status = match self.rect {
+            Some(r) => format!("{}x{} at ({}, {})", r.width, r.height, r.x, r.y),
+            None => "Whole window".to_string(),
+        };
+        let controls = Row::new()
+            .push(button("Save").on_press(Message::Crop(CropAction::Save)))
+            .push(
+                button("Detect Black Bars")
+                    .on_press(Message::Crop(CropAction::DetectBlackBars))
+                    .style(button::secondary),
+            )
+            .push(
+                button("Clear")
+                    .on_press(Message::Crop(CropAction::Clear))
+                    .style(button::secondary),
+            )
+            .push(text(status).size(14).style(text::secondary))
+            .align_y(Center)
+            .spacing(10);
+
+        Column::new()
+            .push(
+                text(format!(
+                    "Drag to choose the part of {} kept in screenshots",
+                    self.game
+                ))
+                .size(16),
+            )
+            .push(area)
+            .push(controls)
+            .spacing(10)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xcap::image::Rgba;
+
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32, window: (u32, u32)) -> CropRect {
+        CropRect::from_corners((x, y), (x + width, y + height), window)
+    }
+
+    /// A black image with a white picture in the given rectangle
+    fn letterboxed(width: u32, height: u32, picture: CropRect) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let inside = (picture.x..picture.x + picture.width).contains(&x)
+                && (picture.y..picture.y + picture.height).contains(&y);
+            if inside {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([4, 4, 4, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn clamp_keeps_the_part_inside() {
+        let crop = rect(50, 20, 100, 100, (200, 100));
+        assert_eq!(
+            crop.clamp(200, 100),
+            Some(rect(50, 20, 100, 80, (200, 100)))
+        );
+        assert_eq!(crop.clamp(40, 100), None);
+    }
+
+    #[test]
+    fn crop_follows_a_resized_window() {
+        let chosen = rect(100, 50, 200, 100, (400, 200));
+        assert_eq!(
+            chosen.scale_to(800, 400),
+            rect(200, 100, 400, 200, (800, 400))
+        );
+        assert_eq!(chosen.scale_to(400, 200), chosen);
+
+        let image = RgbaImage::new(200, 100);
+        assert_eq!(crop(&image, chosen).unwrap().dimensions(), (100, 50));
+    }
+
+    #[test]
+    fn crop_without_window_size_is_absolute() {
+        let old: CropRect =
+            serde_json::from_str(r#"{"x":10,"y":10,"width":50,"height":50}"#).unwrap();
+        assert_eq!(old, rect(10, 10, 50, 50, (0, 0)));
+        assert_eq!(old.scale_to(800, 600), old);
+    }
+
+    #[test]
+    fn black_bars_are_detected() {
+        let picture = rect(0, 10, 64, 40, (64, 60));
+        let image = letterboxed(64, 60, picture);
+        assert_eq!(detect_black_bars(&image, 16), Some(picture));
+
+        let pillarboxed = rect(8, 0, 48, 60, (64, 60));
+        let image = letterboxed(64, 60, pillarboxed);
+        assert_eq!(detect_black_bars(&image, 16), Some(pillarboxed));
+    }
+
+    #[test]
+    fn no_black_bars_without_borders_or_picture() {
+        let full = letterboxed(32, 32, rect(0, 0, 32, 32, (32, 32)));
+        assert_eq!(detect_black_bars(&full, 16), None);
+        let black = RgbaImage::new(32, 32);
+        assert_eq!(detect_black_bars(&black, 16), None);
+    }
+}
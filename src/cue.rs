@@ -0,0 +1,271 @@
+use std::{f32::consts::PI, time::Duration};
+
+#[cfg(feature = "pipewire")]
+use pipewire::{
+    self as pw,
+    properties::properties,
+    spa::{self, pod::Pod},
+    stream::{Stream, StreamFlags, StreamState},
+};
+
+#[cfg(feature = "pipewire")]
+use crate::capture::format_pod;
+use crate::capture::{CaptureError, PcmFormat};
+
+/// Node name of the cue playback stream. Captures only record other applications' streams, so
+/// the cues never end up in a record.
+const CUE_NODE_NAME: &str = "vn_record-cue";
+
+const FORMAT: PcmFormat = PcmFormat::STEREO_48K;
+/// Silence after each cue, so stopping the stream never cuts off the tone itself
+const TAIL: Duration = Duration::from_millis(100);
+
+/// Short earcons confirming an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cue {
+    RecordStart,
+    RecordStop,
+    Success,
+    Failure,
+}
+
+impl Cue {
+    /// Tones (frequency in Hz, length) played one after another
+    fn tones(&self) -> &'static [(f32, Duration)] {
+        const SHORT: Duration = Duration::from_millis(70);
+        const LONG: Duration = Duration::from_millis(110);
+        match self {
+            Cue::RecordStart => &[(660.0, SHORT), (990.0, SHORT)],
+            Cue::RecordStop => &[(990.0, SHORT), (660.0, SHORT)],
+            Cue::Success => &[(1320.0, SHORT)],
+            Cue::Failure => &[(220.0, LONG), (0.0, SHORT), (220.0, LONG)],
+        }
+    }
+
+    /// Render the cue as interleaved PCM, a frequency of 0 is a pause
+    fn synthesize(&self, format: PcmFormat, volume: f32) -> Vec<f32> {
+        let rate = format.sample_rate as f32;
+        let ramp = (0.005 * rate) as usize;
+        let mut samples = Vec::new();
+        for &(freq, length) in self.tones() {
+            let frames = format.samples_in(length) / format.channels as usize;
+            for i in 0..frames {
+                // Short attack and release to avoid clicks
+                let envelope = (i.min(frames - 1 - i) as f32 / ramp as f32).min(1.0);
+                let t = i as f32 / rate;
+                // A bit of third harmonic makes the low failure tone audible on small speakers
+                let wave = (2.0 * PI * freq * t).sin() + 0.3 * (6.0 * PI * freq * t).sin();
+                let v = if freq > 0.0 {
+                    wave * envelope * volume * 0.5
+                } else {
+                    0.0
+                };
+                samples.extend(std::iter::repeat_n(v, format.channels as usize));
+            }
+        }
+        samples.resize(samples.len() + format.samples_in(TAIL), 0.0);
+        samples
+    }
+}
+
+/// Play a cue on its own output stream without blocking the caller.
+pub fn play(cue: Cue, volume: f32) {
+    let samples = cue.synthesize(FORMAT, volume);
+    let length = Duration::from_secs_f64(
+        samples.len() as f64 / FORMAT.channels as f64 / FORMAT.sample_rate as f64,
+    );
+    let res = std::thread::Builder::new()
+        .name("cue-playback".into())
+        .spawn(move || {
+            if let Err(e) = run_playback(samples, length) {
+                eprintln!("Failed to play cue {:?}: {}", cue, e);
+            }
+        });
+    if let Err(e) = res {
+        eprintln!("Failed to play cue {:?}: {}", cue, e);
+    }
+}
+
+#[cfg(feature = "pipewire")]
+struct Playback {
+    samples: Vec<f32>,
+    position: usize,
+    mainloop: pw::main_loop::MainLoop,
+}
+
+/// Play the samples through a PipeWire stream, returning once they have all been queued
+#[cfg(feature = "pipewire")]
+fn run_playback(samples: Vec<f32>, length: Duration) -> Result<(), CaptureError> {
+    pw::init();
+
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let props = properties! {
+        *pw::keys::MEDIA_TYPE => "Audio",
+        *pw::keys::MEDIA_CATEGORY => "Playback",
+        *pw::keys::MEDIA_ROLE => "Notification",
+        *pw::keys::NODE_NAME => CUE_NODE_NAME,
+    };
+    let stream = Stream::new(&core, CUE_NODE_NAME, props)?;
+    let state = Playback {
+        samples,
+        position: 0,
+        mainloop: mainloop.clone(),
+    };
+    let _listener = stream
+        .add_local_listener_with_user_data(state)
+        .state_changed(|_, state, _, new| {
+            if let StreamState::Error(e) = new {
+                eprintln!("Cue stream failed: {}", e);
+                state.mainloop.quit();
+            }
+        })
+        .process(|stream, state| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let channels = FORMAT.channels as usize;
+            let stride = size_of::<f32>() * channels;
+            let datas = buffer.datas_mut();
+            let Some(data) = datas.first_mut() else {
+                return;
+            };
+
+            let mut written = 0;
+            if let Some(bytes) = data.data() {
+                let remaining = &state.samples[state.position..];
+                let frames = (bytes.len() / stride).min(remaining.len() / channels);
+                let count = frames * channels;
+                for (out, s) in bytes
+                    .chunks_exact_mut(size_of::<f32>())
+                    .zip(&remaining[..count])
+                {
+                    out.copy_from_slice(&s.to_le_bytes());
+                }
+                state.position += count;
+                written = frames * stride;
+            }
+            let chunk = data.chunk_mut();
+            *chunk.offset_mut() = 0;
+            *chunk.stride_mut() = stride as i32;
+            *chunk.size_mut() = written as u32;
+
+            if state.position >= state.samples.len() {
+                state.mainloop.quit();
+            }
+        })
+        .register()?;
+
+    let values = format_pod(FORMAT)?;
+    let mut params = [Pod::from_bytes(&values)
+        .ok_or_else(|| CaptureError::Stream("Invalid format pod".to_string()))?];
+    stream.connect(
+        spa::utils::Direction::Output,
+        None,
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    // Don't hang around forever if the server never asks for the samples
+    let (quit, quit_receiver) = pw::channel::channel();
+    let _quit = quit_receiver.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    std::thread::spawn(move || {
+        std::thread::sleep(length + Duration::from_secs(2));
+        let _ = quit.send(());
+    });
+
+    mainloop.run();
+    Ok(())
+}
+
+/// Play the samples through `pw-play`, returning once it has played them
+#[cfg(not(feature = "pipewire"))]
+fn run_playback(samples: Vec<f32>, _length: Duration) -> Result<(), CaptureError> {
+    let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let output = duct::cmd!(
+        "pw-play",
+        "--raw",
+        "--format",
+        "f32",
+        "--rate",
+        FORMAT.sample_rate.to_string(),
+        "--channels",
+        FORMAT.channels.to_string(),
+        "--media-role",
+        "Notification",
+        "-P",
+        format!("{{ node.name={} }}", CUE_NODE_NAME),
+        "-"
+    )
+    .stdin_bytes(bytes)
+    .stderr_capture()
+    .unchecked()
+    .run()?;
+    if !output.status.success() {
+        return Err(CaptureError::Stream(format!(
+            "pw-play exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUES: [Cue; 4] = [
+        Cue::RecordStart,
+        Cue::RecordStop,
+        Cue::Success,
+        Cue::Failure,
+    ];
+
+    #[test]
+    fn cues_last_as_long_as_their_tones() {
+        for cue in CUES {
+            let tones: usize = cue
+                .tones()
+                .iter()
+                .map(|&(_, length)| FORMAT.samples_in(length))
+                .sum();
+            let samples = cue.synthesize(FORMAT, 1.0);
+            assert_eq!(samples.len(), tones + FORMAT.samples_in(TAIL), "{:?}", cue);
+        }
+    }
+
+    #[test]
+    fn cues_follow_the_volume() {
+        let peak = |samples: &[f32]| samples.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        for cue in CUES {
+            let loud = peak(&cue.synthesize(FORMAT, 1.0));
+            let quiet = peak(&cue.synthesize(FORMAT, 0.25));
+            // Half scale plus the third harmonic, never clipping
+            assert!(loud > 0.4 && loud <= 0.65, "{:?} peaks at {}", cue, loud);
+            assert!((quiet - loud * 0.25).abs() < 1e-3, "{:?}", cue);
+            assert!(cue.synthesize(FORMAT, 0.0).iter().all(|&s| s == 0.0));
+        }
+    }
+
+    #[test]
+    fn cues_fade_in_and_out_on_every_channel() {
+        let samples = Cue::Failure.synthesize(FORMAT, 1.0);
+        let channels = FORMAT.channels as usize;
+        assert!(samples.chunks(channels).all(|frame| frame[0] == frame[1]));
+        assert_eq!(samples[0], 0.0);
+        // The pause between the two tones and the tail are silent
+        let first = FORMAT.samples_in(Duration::from_millis(110));
+        let pause = FORMAT.samples_in(Duration::from_millis(70));
+        assert!(samples[first..first + pause].iter().all(|&s| s == 0.0));
+        let tail = FORMAT.samples_in(TAIL);
+        assert!(samples[samples.len() - tail..].iter().all(|&s| s == 0.0));
+        // The attack ramps up rather than starting at full level
+        assert!(samples[channels].abs() < 0.05);
+    }
+}
@@ -0,0 +1,592 @@
+use std::{f64::consts::PI, sync::Arc, time::Duration};
+
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+use crate::{
+    capture::PcmFormat,
+    config::{BackgroundConfig, LoudnessConfig, TrimConfig},
+};
+
+/// Window the silence detection measures its level on
+const TRIM_WINDOW: Duration = Duration::from_millis(10);
+/// Highest peak allowed after loudness normalization, to avoid clipping
+const PEAK_CEILING: f32 = 0.98;
+/// STFT frame length of the background suppression, about 43ms at 48kHz
+const STFT_LEN: usize = 2048;
+
+/// Apply the configured background suppression, trimming, loudness normalization and fades to
+/// interleaved PCM.
+///
+/// `pre_roll` is audio captured just before the record, which the background is estimated from.
+/// When there isn't enough of it, the start of the record before anything reaches the trim
+/// threshold is used instead.
+pub fn process(
+    samples: &mut Vec<f32>,
+    pre_roll: &[f32],
+    format: PcmFormat,
+    background: &BackgroundConfig,
+    trim: &TrimConfig,
+    loudness: &LoudnessConfig,
+) {
+    // Suppress first so trimming works on the voice rather than the music under it
+    if background.enabled {
+        let lead_in;
+        let estimate_from = if pre_roll.len() / format.channels.max(1) as usize >= STFT_LEN {
+            pre_roll
+        } else {
+            lead_in =
+                samples[..lead_in_len(samples, format, trim.threshold_percent / 100.0)].to_vec();
+            &lead_in
+        };
+        suppress_background(
+            samples,
+            estimate_from,
+            format,
+            background.strength,
+            background.floor,
+        );
+    }
+    if trim.enabled {
+        trim_silence(
+            samples,
+            format,
+            trim.threshold_percent / 100.0,
+            trim.min_duration_secs,
+        );
+    }
+    if loudness.enabled {
+        normalize_loudness(samples, format, loudness.target_lufs);
+    }
+    fade(samples, format, Duration::from_millis(trim.fade_ms as u64));
+}
+
+/// Remove the silence from the beginning and end of the audio.
+///
+/// Like sox's `silence` effect, audio is kept from the first stretch of at least `min_duration`
+/// seconds above `threshold` (0.0 to 1.0), and likewise from the end. If nothing is loud enough
+/// the audio is left untouched, rather than producing an empty record.
+pub fn trim_silence(samples: &mut Vec<f32>, format: PcmFormat, threshold: f32, min_duration: f32) {
+    let window = format.samples_in(TRIM_WINDOW).max(format.channels as usize);
+    let min_windows = ((min_duration as f64 / TRIM_WINDOW.as_secs_f64()).ceil() as usize).max(1);
+    let loud: Vec<bool> = samples
+        .chunks(window)
+        .map(|w| rms(w) >= threshold)
+        .collect();
+
+    // Index of the window where the first long enough loud run begins, in iteration order
+    let first_run = |windows: &mut dyn Iterator<Item = (usize, &bool)>| {
+        let mut run_start = None;
+        let mut run = 0;
+        for (i, &loud) in windows {
+            if !loud {
+                run = 0;
+                continue;
+            }
+            if run == 0 {
+                run_start = Some(i);
+            }
+            run += 1;
+            if run == min_windows {
+                return run_start;
+            }
+        }
+        None
+    };
+    let Some(start) = first_run(&mut loud.iter().enumerate()) else {
+        eprintln!("No audio above the silence threshold, skipping trim");
+        return;
+    };
+    // Scanning backwards finds the last loud window
+    let end = first_run(&mut loud.iter().enumerate().rev()).map_or(loud.len(), |i| i + 1);
+
+    samples.truncate((end * window).min(samples.len()));
+    samples.drain(..start * window);
+}
+
+/// Number of samples before the first window reaching `threshold`
+fn lead_in_len(samples: &[f32], format: PcmFormat, threshold: f32) -> usize {
+    let window = format.samples_in(TRIM_WINDOW).max(format.channels as usize);
+    samples
+        .chunks(window)
+        .position(|w| rms(w) >= threshold)
+        .map_or(samples.len(), |i| i * window)
+}
+
+/// Scale the audio to the target integrated loudness (EBU R128 / ITU-R BS.1770), lowering the
+/// gain if needed so the peak stays below clipping.
+pub fn normalize_loudness(samples: &mut [f32], format: PcmFormat, target_lufs: f32) {
+    let Some(loudness) = integrated_loudness(samples, format) else {
+        return;
+    };
+
+    let mut gain = 10f32.powf((target_lufs - loudness as f32) / 20.0);
+    let peak = samples.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+    if peak * gain > PEAK_CEILING {
+        gain = PEAK_CEILING / peak;
+    }
+    for s in samples {
+        *s *= gain;
+    }
+}
+
+/// Integrated loudness in LUFS, `None` for silence
+pub fn integrated_loudness(samples: &[f32], format: PcmFormat) -> Option<f64> {
+    const ABSOLUTE_GATE: f64 = -70.0;
+    const RELATIVE_GATE: f64 = -10.0;
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+
+    let channels = format.channels as usize;
+    if channels == 0 || samples.len() < channels {
+        return None;
+    }
+
+    // Per frame sum of the K-weighted channel powers
+    let mut filters: Vec<_> = (0..channels)
+        .map(|_| KWeighting::new(format.sample_rate as f64))
+        .collect();
+    let power: Vec<f64> = samples
+        .chunks_exact(channels)
+        .map(|frame| {
+            frame
+                .iter()
+                .zip(&mut filters)
+                .map(|(s, filter)| filter.process(*s as f64).powi(2))
+                .sum()
+        })
+        .collect();
+
+    // 400ms gating blocks with 75% overlap, or a single block for shorter audio
+    let block = (format.sample_rate as usize * 4 / 10).min(power.len());
+    let step = (block / 4).max(1);
+    let blocks: Vec<f64> = (0..=power.len() - block)
+        .step_by(step)
+        .map(|start| power[start..start + block].iter().sum::<f64>() / block as f64)
+        .filter(|p| loudness(*p) > ABSOLUTE_GATE)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let mean = |blocks: &mut dyn Iterator<Item = f64>| {
+        let (sum, n) = blocks.fold((0.0, 0), |(sum, n), p| (sum + p, n + 1));
+        (n > 0).then(|| sum / n as f64)
+    };
+    let relative_gate = loudness(mean(&mut blocks.iter().copied())?) + RELATIVE_GATE;
+    let gated = mean(
+        &mut blocks
+            .iter()
+            .copied()
+            .filter(|p| loudness(*p) > relative_gate),
+    )?;
+    Some(loudness(gated))
+}
+
+/// Linearly fade the audio in and out to avoid clicks at the cut points
+pub fn fade(samples: &mut [f32], format: PcmFormat, duration: Duration) {
+    let channels = format.channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    let fade_frames = (format.samples_in(duration) / channels).min(frames / 2);
+    if fade_frames == 0 {
+        return;
+    }
+
+    for i in 0..fade_frames {
+        let gain = i as f32 / fade_frames as f32;
+        let head = i * channels;
+        let tail = (frames - 1 - i) * channels;
+        for c in 0..channels {
+            samples[head + c] *= gain;
+            samples[tail + c] *= gain;
+        }
+    }
+}
+
+/// Remove stationary background (e.g. BGM or noise) from voice through spectral subtraction.
+///
+/// The background spectrum is estimated from `background`, audio where only the background
+/// plays such as the moment just before the voice line. Nothing is done if it's shorter than
+/// one STFT frame. `strength` scales the subtracted spectrum, `floor` is the fraction of each bin
+/// always kept to avoid "musical noise" artifacts.
+pub fn suppress_background(
+    samples: &mut [f32],
+    background: &[f32],
+    format: PcmFormat,
+    strength: f32,
+    floor: f32,
+) {
+    let channels = format.channels.max(1) as usize;
+    if background.len() / channels < STFT_LEN {
+        eprintln!("Not enough audio before the line to estimate the background, skipping");
+        return;
+    }
+
+    let mut planner = FftPlanner::new();
+    let stft = Stft {
+        fft: planner.plan_fft_forward(STFT_LEN),
+        ifft: planner.plan_fft_inverse(STFT_LEN),
+        // A periodic Hann window at 50% overlap sums to one, so overlap-add reconstructs the
+        // signal
+        window: (0..STFT_LEN)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / STFT_LEN as f32).cos())
+            .collect(),
+    };
+    let channel = |samples: &[f32], c: usize| -> Vec<f32> {
+        samples.iter().skip(c).step_by(channels).copied().collect()
+    };
+    for c in 0..channels {
+        let spectrum = stft.average_magnitude(&channel(background, c));
+        let cleaned = stft.subtract(&channel(samples, c), &spectrum, strength, floor);
+        for (s, v) in samples.iter_mut().skip(c).step_by(channels).zip(cleaned) {
+            *s = v;
+        }
+    }
+}
+
+struct Stft {
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+}
+
+impl Stft {
+    const HOP: usize = STFT_LEN / 2;
+
+    /// Spectra of the windowed frames of `signal`, every `HOP` samples
+    fn spectra(&self, signal: &[f32]) -> Vec<Vec<Complex<f32>>> {
+        signal
+            .windows(STFT_LEN)
+            .step_by(Self::HOP)
+            .map(|frame| {
+                let mut spectrum: Vec<_> = frame
+                    .iter()
+                    .zip(&self.window)
+                    .map(|(s, w)| Complex::new(s * w, 0.0))
+                    .collect();
+                self.fft.process(&mut spectrum);
+                spectrum
+            })
+            .collect()
+    }
+
+    /// Average magnitude spectrum of the whole signal, at least `STFT_LEN` long
+    fn average_magnitude(&self, signal: &[f32]) -> Vec<f32> {
+        let spectra = self.spectra(signal);
+        let mut average = vec![0.0f32; STFT_LEN];
+        for spectrum in &spectra {
+            for (a, c) in average.iter_mut().zip(spectrum) {
+                *a += c.norm() / spectra.len() as f32;
+            }
+        }
+        average
+    }
+
+    fn subtract(&self, signal: &[f32], background: &[f32], strength: f32, floor: f32) -> Vec<f32> {
+        let hop = Self::HOP;
+        // Pad so every sample is covered by two frames
+        let mut padded = vec![0.0; hop];
+        padded.extend_from_slice(signal);
+        padded.resize(padded.len().next_multiple_of(hop) + hop, 0.0);
+        let mut spectra = self.spectra(&padded);
+
+        let mut output = vec![0.0f32; padded.len()];
+        for (n, spectrum) in spectra.iter_mut().enumerate() {
+            for (c, b) in spectrum.iter_mut().zip(background) {
+                let magnitude = c.norm();
+                if magnitude > 0.0 {
+                    let cleaned = (magnitude - strength * b).max(floor * magnitude);
+                    *c *= cleaned / magnitude;
+                }
+            }
+            self.ifft.process(spectrum);
+            let start = n * hop;
+            for (o, c) in output[start..start + STFT_LEN]
+                .iter_mut()
+                .zip(spectrum.iter())
+            {
+                *o += c.re / STFT_LEN as f32;
+            }
+        }
+
+        output.drain(..hop);
+        output.truncate(signal.len());
+        output
+    }
+}
+
+/// Average the channels of interleaved PCM into a single channel
+pub fn downmix(samples: &[f32], channels: u16) -> Vec<f32> {
+    let channels = channels.max(1) as usize;
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Resample interleaved PCM to `sample_rate` with a windowed sinc (Lanczos) interpolator, which
+/// also low-passes the audio when downsampling to avoid aliasing.
+pub fn resample(samples: &[f32], format: PcmFormat, sample_rate: u32) -> Vec<f32> {
+    const TAPS: f64 = 8.0;
+    let channels = format.channels.max(1) as usize;
+    let frames = samples.len() / channels;
+    let ratio = sample_rate as f64 / format.sample_rate as f64;
+    let cutoff = ratio.min(1.0);
+    let radius = TAPS / cutoff;
+    let sinc = |x: f64| {
+        if x.abs() < 1e-9 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        }
+    };
+
+    let out_frames = (frames as f64 * ratio) as usize;
+    let mut output = Vec::with_capacity(out_frames * channels);
+    let mut acc = vec![0.0f64; channels];
+    for n in 0..out_frames {
+        let center = n as f64 / ratio;
+        let first = (center - radius).ceil().max(0.0) as usize;
+        let last = ((center + radius).floor() as usize).min(frames.saturating_sub(1));
+        acc.fill(0.0);
+        let mut weight_sum = 0.0;
+        for i in first..=last {
+            let x = (i as f64 - center) * cutoff;
+            let weight = sinc(x) * sinc(x / TAPS);
+            weight_sum += weight;
+            for (c, a) in acc.iter_mut().enumerate() {
+                *a += samples[i * channels + c] as f64 * weight;
+            }
+        }
+        let norm = if weight_sum.abs() > 1e-9 {
+            weight_sum
+        } else {
+            1.0
+        };
+        output.extend(acc.iter().map(|a| (a / norm) as f32));
+    }
+    output
+}
+
+/// Root mean square amplitude of the samples
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples.iter().map(|s| s * s).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+/// The BS.1770 K-weighting pre-filter: a high shelf followed by a high pass
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        // Filter parameters matching the reference coefficients at 48kHz, derived for any rate
+        let shelf = {
+            let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+            let k = (PI * f0 / sample_rate).tan();
+            let vh = 10f64.powf(gain_db / 20.0);
+            let vb = vh.powf(0.4996667741545416);
+            let a0 = 1.0 + k / q + k * k;
+            Biquad::new(
+                [
+                    (vh + vb * k / q + k * k) / a0,
+                    2.0 * (k * k - vh) / a0,
+                    (vh - vb * k / q + k * k) / a0,
+                ],
+                [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            )
+        };
+        let high_pass = {
+            let (f0, q) = (38.13547087602444, 0.5003270373238773);
+            let k = (PI * f0 / sample_rate).tan();
+            let a0 = 1.0 + k / q + k * k;
+            Biquad::new(
+                [1.0, -2.0, 1.0],
+                [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            )
+        };
+        KWeighting {
+            stages: [shelf, high_pass],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.stages.iter_mut().fold(x, |x, stage| stage.process(x))
+    }
+}
+
+/// Direct form I biquad filter
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MONO_48K: PcmFormat = PcmFormat {
+        sample_rate: 48000,
+        channels: 1,
+    };
+
+    /// Interleaved sine of the same amplitude on every channel
+    fn sine(format: PcmFormat, freq: f64, amplitude: f32, duration: Duration) -> Vec<f32> {
+        let frames = format.samples_in(duration) / format.channels as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f64 / format.sample_rate as f64;
+                let s = amplitude * (2.0 * PI * freq * t).sin() as f32;
+                std::iter::repeat_n(s, format.channels as usize)
+            })
+            .collect()
+    }
+
+    fn silence(format: PcmFormat, duration: Duration) -> Vec<f32> {
+        vec![0.0; format.samples_in(duration)]
+    }
+
+    #[test]
+    fn trim_keeps_the_tone_between_silences() {
+        let tone = sine(MONO_48K, 440.0, 0.5, Duration::from_secs(1));
+        let mut samples = silence(MONO_48K, Duration::from_millis(500));
+        samples.extend(&tone);
+        samples.extend(silence(MONO_48K, Duration::from_millis(700)));
+
+        trim_silence(&mut samples, MONO_48K, 0.05, 0.1);
+        assert_eq!(samples, tone);
+    }
+
+    #[test]
+    fn trim_skips_clicks_shorter_than_min_duration() {
+        let tone = sine(MONO_48K, 440.0, 0.5, Duration::from_secs(1));
+        let mut samples = sine(MONO_48K, 440.0, 0.5, Duration::from_millis(20));
+        samples.extend(silence(MONO_48K, Duration::from_millis(500)));
+        samples.extend(&tone);
+
+        trim_silence(&mut samples, MONO_48K, 0.05, 0.1);
+        assert_eq!(samples, tone);
+    }
+
+    #[test]
+    fn trim_leaves_silence_untouched() {
+        let mut samples = sine(MONO_48K, 440.0, 0.01, Duration::from_secs(1));
+        let original = samples.clone();
+        trim_silence(&mut samples, MONO_48K, 0.05, 0.1);
+        assert_eq!(samples, original);
+    }
+
+    #[test]
+    fn loudness_of_a_minus_20_dbfs_sine_is_minus_20_lufs() {
+        let samples = sine(PcmFormat::STEREO_48K, 1000.0, 0.1, Duration::from_secs(3));
+        let loudness = integrated_loudness(&samples, PcmFormat::STEREO_48K).unwrap();
+        assert!((loudness + 20.0).abs() < 0.1, "measured {loudness} LUFS");
+    }
+
+    #[test]
+    fn loudness_of_silence_is_none() {
+        let samples = silence(PcmFormat::STEREO_48K, Duration::from_secs(1));
+        assert_eq!(integrated_loudness(&samples, PcmFormat::STEREO_48K), None);
+    }
+
+    #[test]
+    fn normalize_reaches_the_target_loudness() {
+        let mut samples = sine(PcmFormat::STEREO_48K, 1000.0, 0.1, Duration::from_secs(3));
+        normalize_loudness(&mut samples, PcmFormat::STEREO_48K, -16.0);
+        let loudness = integrated_loudness(&samples, PcmFormat::STEREO_48K).unwrap();
+        assert!((loudness + 16.0).abs() < 0.1, "measured {loudness} LUFS");
+    }
+
+    fn mixed(a: &[f32], b: &[f32]) -> Vec<f32> {
+        a.iter().zip(b).map(|(a, b)| a + b).collect()
+    }
+
+    #[test]
+    fn suppression_removes_the_background_heard_before() {
+        let bgm = sine(MONO_48K, 200.0, 0.2, Duration::from_secs(2));
+        let (pre_roll, clip) = bgm.split_at(MONO_48K.samples_in(Duration::from_millis(500)));
+        let mut samples = clip.to_vec();
+        suppress_background(&mut samples, pre_roll, MONO_48K, 1.0, 0.0);
+        assert!(rms(&samples) < 0.1 * rms(clip), "{}", rms(&samples));
+    }
+
+    #[test]
+    fn suppression_keeps_voice_without_pauses() {
+        let duration = Duration::from_secs(1);
+        let voice = sine(MONO_48K, 1000.0, 0.3, duration);
+        let bgm = sine(MONO_48K, 200.0, 0.2, duration * 2);
+        let (pre_roll, under_voice) = bgm.split_at(bgm.len() / 2);
+        let mut samples = mixed(&voice, under_voice);
+        suppress_background(&mut samples, pre_roll, MONO_48K, 1.0, 0.0);
+
+        let residual = mixed(&samples, &voice.iter().map(|s| -s).collect::<Vec<_>>());
+        assert!((rms(&samples) - rms(&voice)).abs() < 0.1 * rms(&voice));
+        assert!(rms(&residual) < 0.2 * rms(under_voice));
+    }
+
+    #[test]
+    fn suppression_needs_a_whole_frame_of_background() {
+        let mut samples = sine(MONO_48K, 200.0, 0.2, Duration::from_millis(500));
+        let original = samples.clone();
+        suppress_background(&mut samples, &original[..STFT_LEN - 1], MONO_48K, 1.0, 0.0);
+        assert_eq!(samples, original);
+    }
+
+    #[test]
+    fn lead_in_ends_at_the_first_loud_window() {
+        let mut samples = sine(MONO_48K, 200.0, 0.01, Duration::from_millis(300));
+        samples.extend(sine(MONO_48K, 200.0, 0.5, Duration::from_millis(300)));
+        assert_eq!(lead_in_len(&samples, MONO_48K, 0.05), 14400);
+        let quiet = silence(MONO_48K, Duration::from_millis(100));
+        assert_eq!(lead_in_len(&quiet, MONO_48K, 0.05), quiet.len());
+    }
+
+    #[test]
+    fn fade_ramps_both_edges_linearly() {
+        let format = PcmFormat {
+            sample_rate: 1000,
+            channels: 2,
+        };
+        let mut samples = vec![1.0; 200];
+        fade(&mut samples, format, Duration::from_millis(10));
+
+        let frames: Vec<&[f32]> = samples.chunks_exact(2).collect();
+        for i in 0..10 {
+            let gain = i as f32 / 10.0;
+            assert_eq!(frames[i], [gain, gain]);
+            assert_eq!(frames[99 - i], [gain, gain]);
+        }
+        assert!(frames[10..90].iter().all(|frame| *frame == [1.0, 1.0]));
+    }
+
+    #[test]
+    fn fade_is_limited_to_half_the_audio() {
+        let mut samples = vec![1.0; 4];
+        fade(&mut samples, MONO_48K, Duration::from_secs(1));
+        assert_eq!(samples, [0.0, 0.5, 0.5, 0.0]);
+    }
+}
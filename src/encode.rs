@@ -0,0 +1,406 @@
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use duct::cmd;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{capture::PcmFormat, config::AudioConfig, dsp, process::find_executable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFormat {
+    #[default]
+    Mp3,
+    Opus,
+    Vorbis,
+    Flac,
+    Wav,
+}
+
+impl AudioFormat {
+    pub const ALL: [AudioFormat; 5] = [
+        AudioFormat::Mp3,
+        AudioFormat::Opus,
+        AudioFormat::Vorbis,
+        AudioFormat::Flac,
+        AudioFormat::Wav,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Vorbis => "ogg",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Wav => "wav",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<AudioFormat> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.extension().eq_ignore_ascii_case(extension))
+    }
+
+    /// External encoder the format needs, if any
+    pub fn tool(&self) -> Option<&'static str> {
+        match self {
+            AudioFormat::Mp3 => Some("lame"),
+            AudioFormat::Opus => Some("opusenc"),
+            AudioFormat::Vorbis => Some("oggenc"),
+            AudioFormat::Flac => Some("flac"),
+            AudioFormat::Wav => None,
+        }
+    }
+
+    /// Make sure the encoder is installed, so a recording isn't lost when it's saved
+    pub fn check_tool(&self) -> Result<(), EncodeError> {
+        match self.tool() {
+            Some(tool) if find_executable(tool).is_none() => Err(EncodeError::MissingTool(tool)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for AudioFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AudioFormat::Mp3 => "MP3",
+            AudioFormat::Opus => "Opus",
+            AudioFormat::Vorbis => "OGG Vorbis",
+            AudioFormat::Flac => "FLAC",
+            AudioFormat::Wav => "WAV",
+        })
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EncodeError {
+    #[error("Encoder `{0}` is not installed")]
+    MissingTool(&'static str),
+    #[error("Encoder `{tool}` failed: {stderr}")]
+    Failed { tool: &'static str, stderr: String },
+    #[error("IO error on encoding: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// Convert interleaved f32 PCM to the configured channel layout and sample rate, then encode it
+/// to `audio_path` in the configured format.
+pub fn encode(
+    samples: &[f32],
+    format: PcmFormat,
+    audio_path: &Path,
+    audio: &AudioConfig,
+) -> Result<(), EncodeError> {
+    let (samples, format) = convert(samples, format, audio);
+    let pcm: Vec<u8> = samples
+        .iter()
+        .flat_map(|s| ((s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+        .collect();
+
+    let rate = format.sample_rate.to_string();
+    let channels = format.channels.to_string();
+    let (tool, mut args): (_, Vec<OsString>) = match audio.format {
+        AudioFormat::Mp3 => (
+            "lame",
+            vec![
+                "-r".into(),
+                "--signed".into(),
+                "--little-endian".into(),
+                "--bitwidth".into(),
+                "16".into(),
+                "-s".into(),
+                (format.sample_rate as f32 / 1000.0).to_string().into(),
+                "-m".into(),
+                if format.channels == 1 { "m" } else { "s" }.into(),
+                format!("-V{}", audio.mp3_vbr_quality).into(),
+                "-".into(),
+            ],
+        ),
+        AudioFormat::Opus => (
+            "opusenc",
+            vec![
+                "--quiet".into(),
+                "--raw".into(),
+                "--raw-bits".into(),
+                "16".into(),
+                "--raw-rate".into(),
+                rate.into(),
+                "--raw-chan".into(),
+                channels.into(),
+                "--bitrate".into(),
+                audio.opus_bitrate_kbps.to_string().into(),
+                "-".into(),
+            ],
+        ),
+        AudioFormat::Vorbis => (
+            "oggenc",
+            vec![
+                "--quiet".into(),
+                "-r".into(),
+                "-B".into(),
+                "16".into(),
+                "-R".into(),
+                rate.into(),
+                "-C".into(),
+                channels.into(),
+                "-q".into(),
+                audio.vorbis_quality.to_string().into(),
+                "-".into(),
+                "-o".into(),
+            ],
+        ),
+        AudioFormat::Flac => (
+            "flac",
+            vec![
+                "--silent".into(),
+                "--force".into(),
+                "--force-raw-format".into(),
+                "--endian=little".into(),
+                "--sign=signed".into(),
+                "--bps=16".into(),
+                format!("--sample-rate={}", rate).into(),
+                format!("--channels={}", channels).into(),
+                format!("-{}", audio.flac_compression).into(),
+                "-".into(),
+                "-o".into(),
+            ],
+        ),
+        AudioFormat::Wav => return write_wav(&pcm, format, audio_path),
+    };
+
+    args.push(audio_path.into());
+
+    let output = cmd(tool, args)
+        .stdin_bytes(pcm)
+        .stdout_null()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => EncodeError::MissingTool(tool),
+            _ => EncodeError::IO(e),
+        })?;
+
+    if !output.status.success() {
+        return Err(EncodeError::Failed {
+            tool,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Downmix and resample according to the config
+fn convert(samples: &[f32], format: PcmFormat, audio: &AudioConfig) -> (Vec<f32>, PcmFormat) {
+    let mut samples = samples.to_vec();
+    let mut format = format;
+    if audio.mono && format.channels > 1 {
+        samples = dsp::downmix(&samples, format.channels);
+        format.channels = 1;
+    }
+    if let Some(rate) = audio.sample_rate
+        && rate != format.sample_rate
+    {
+        samples = dsp::resample(&samples, format, rate);
+        format.sample_rate = rate;
+    }
+    (samples, format)
+}
+
+/// Write 16-bit PCM as a WAV file
+fn write_wav(pcm: &[u8], format: PcmFormat, audio_path: &Path) -> Result<(), EncodeError> {
+    let channels = format.channels;
+    let block_align = channels * 2;
+    let byte_rate = format.sample_rate * block_align as u32;
+    let data_len = pcm.len() as u32;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(audio_path)?);
+    file.write_all(b"RIFF")?;
+    file.write_all(&(36 + data_len).to_le_bytes())?;
+    file.write_all(b"WAVEfmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?; // PCM
+    file.write_all(&channels.to_le_bytes())?;
+    file.write_all(&format.sample_rate.to_le_bytes())?;
+    file.write_all(&byte_rate.to_le_bytes())?;
+    file.write_all(&block_align.to_le_bytes())?;
+    file.write_all(&16u16.to_le_bytes())?; // bits per sample
+    file.write_all(b"data")?;
+    file.write_all(&data_len.to_le_bytes())?;
+    file.write_all(pcm)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Read the duration of an encoded file from its headers, without decoding it.
+pub fn probe_duration(path: &Path, format: AudioFormat) -> Option<Duration> {
+    let mut file = File::open(path).ok()?;
+    let mut head = Vec::new();
+    (&mut file).take(16 * 1024).read_to_end(&mut head).ok()?;
+
+    let secs = match format {
+        AudioFormat::Wav => probe_wav(&head)?,
+        AudioFormat::Flac => probe_flac(&head)?,
+        AudioFormat::Mp3 => probe_mp3(&head)?,
+        AudioFormat::Opus | AudioFormat::Vorbis => {
+            // The granule position of the last page is the length in samples
+            let len = file.metadata().ok()?.len();
+            file.seek(SeekFrom::Start(len.saturating_sub(64 * 1024)))
+                .ok()?;
+            let mut tail = Vec::new();
+            file.read_to_end(&mut tail).ok()?;
+            let page = tail.windows(4).rposition(|w| w == b"OggS")?;
+            let granule = u64::from_le_bytes(tail.get(page + 6..page + 14)?.try_into().ok()?);
+
+            if format == AudioFormat::Opus {
+                // Opus always runs at 48kHz, minus the pre-skip
+                let head_pos = find(&head, b"OpusHead")?;
+                let pre_skip =
+                    u16::from_le_bytes(head.get(head_pos + 10..head_pos + 12)?.try_into().ok()?);
+                granule.saturating_sub(pre_skip as u64) as f64 / 48000.0
+            } else {
+                let id_pos = find(&head, b"\x01vorbis")?;
+                let rate = u32::from_le_bytes(head.get(id_pos + 12..id_pos + 16)?.try_into().ok()?);
+                granule as f64 / rate as f64
+            }
+        }
+    };
+    Some(Duration::from_secs_f64(secs))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn probe_wav(head: &[u8]) -> Option<f64> {
+    let fmt = find(head, b"fmt ")?;
+    let byte_rate = u32::from_le_bytes(head.get(fmt + 16..fmt + 20)?.try_into().ok()?);
+    let data = find(head, b"data")?;
+    let data_len = u32::from_le_bytes(head.get(data + 4..data + 8)?.try_into().ok()?);
+    Some(data_len as f64 / byte_rate as f64)
+}
+
+fn probe_flac(head: &[u8]) -> Option<f64> {
+    // STREAMINFO is always the first metadata block, right after the magic and block header
+    if !head.starts_with(b"fLaC") {
+        return None;
+    }
+    let info = u64::from_be_bytes(head.get(18..26)?.try_into().ok()?);
+    let rate = info >> 44;
+    let total_samples = info & 0xF_FFFF_FFFF;
+    (rate > 0).then(|| total_samples as f64 / rate as f64)
+}
+
+fn probe_mp3(head: &[u8]) -> Option<f64> {
+    // Skip an ID3v2 tag, its size is a 28-bit syncsafe integer
+    let mut pos = 0;
+    if head.starts_with(b"ID3") {
+        let size = head
+            .get(6..10)?
+            .iter()
+            .fold(0usize, |acc, b| acc << 7 | (b & 0x7f) as usize);
+        pos = 10 + size;
+    }
+    let frame = pos
+        + head
+            .get(pos..)?
+            .windows(2)
+            .position(|w| w[0] == 0xff && w[1] & 0xe0 == 0xe0)?;
+    let header = head.get(frame..frame + 4)?;
+
+    let (version_rates, samples_per_frame, mpeg1) = match (header[1] >> 3) & 0b11 {
+        0b11 => ([44100, 48000, 32000], 1152, true),
+        0b10 => ([22050, 24000, 16000], 576, false),
+        0b00 => ([11025, 12000, 8000], 576, false),
+        _ => return None,
+    };
+    let rate = *version_rates.get(((header[2] >> 2) & 0b11) as usize)?;
+    let mono = header[3] >> 6 == 0b11;
+
+    // lame writes the number of frames in a Xing/Info header inside the first frame
+    let side_info = match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let xing = frame + 4 + side_info;
+    let tag = head.get(xing..xing + 4)?;
+    if tag != b"Xing" && tag != b"Info" {
+        return None;
+    }
+    let flags = u32::from_be_bytes(head.get(xing + 4..xing + 8)?.try_into().ok()?);
+    if flags & 1 == 0 {
+        return None;
+    }
+    let frames = u32::from_be_bytes(head.get(xing + 8..xing + 12)?.try_into().ok()?);
+    Some(frames as f64 * samples_per_frame as f64 / rate as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_secs(duration: Option<Duration>, secs: f64) {
+        let duration = duration.expect("no duration").as_secs_f64();
+        assert!((duration - secs).abs() < 1e-6, "{} != {}", duration, secs);
+    }
+
+    #[test]
+    fn written_wav_probes_to_its_length() {
+        let dir = std::env::temp_dir().join(format!("vn_record-wav-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("probe.wav");
+        for format in [
+            PcmFormat::STEREO_48K,
+            PcmFormat {
+                sample_rate: 22050,
+                channels: 1,
+            },
+        ] {
+            // 1.5 s of 16-bit samples
+            let samples = format.samples_in(Duration::from_millis(1500));
+            write_wav(&vec![0; samples * 2], format, &path).unwrap();
+            assert_secs(probe_duration(&path, AudioFormat::Wav), 1.5);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn flac_length_is_read_from_streaminfo() {
+        let head = include_bytes!("../tests/fixtures/streaminfo.flac");
+        assert_secs(probe_flac(head).map(Duration::from_secs_f64), 1.5);
+        assert_eq!(probe_flac(b"RIFF not a flac"), None);
+    }
+
+    #[test]
+    fn mp3_length_is_read_from_the_info_header() {
+        // Behind an ID3v2 tag, in an MPEG-1 stereo frame
+        let stereo = include_bytes!("../tests/fixtures/info_stereo.mp3");
+        assert_secs(
+            probe_mp3(stereo).map(Duration::from_secs_f64),
+            38.0 * 1152.0 / 44100.0,
+        );
+        // In an MPEG-2 mono frame, whose side info is shorter
+        let mono = include_bytes!("../tests/fixtures/info_mono.mp3");
+        assert_secs(
+            probe_mp3(mono).map(Duration::from_secs_f64),
+            100.0 * 576.0 / 22050.0,
+        );
+    }
+
+    #[test]
+    fn mp3_without_info_header_has_no_length() {
+        let mut mp3 = include_bytes!("../tests/fixtures/info_mono.mp3").to_vec();
+        let tag = find(&mp3, b"Info").unwrap();
+        mp3[tag..tag + 4].copy_from_slice(b"\0\0\0\0");
+        assert_eq!(probe_mp3(&mp3), None);
+    }
+}
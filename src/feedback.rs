@@ -0,0 +1,373 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use iced::{
+    Alignment::Center,
+    Element,
+    widget::{Column, Row, button, container, text},
+};
+use notify_rust::{Notification, Timeout, Urgency};
+use thiserror::Error;
+
+use crate::{Message, cue::Cue};
+
+/// How long success toasts stay on screen, errors stay until dismissed
+const TOAST_DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedbackKind {
+    Success,
+    Error,
+}
+
+/// Outcome of a user action, reported through toasts and desktop notifications
+#[derive(Debug, Clone)]
+pub struct Feedback {
+    pub kind: FeedbackKind,
+    pub message: String,
+    /// Sound played along, when sounds are enabled
+    pub cue: Option<Cue>,
+}
+
+impl Feedback {
+    pub fn success(message: impl Into<String>) -> Self {
+        Self {
+            kind: FeedbackKind::Success,
+            message: message.into(),
+            cue: Some(Cue::Success),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            kind: FeedbackKind::Error,
+            message: message.into(),
+            cue: Some(Cue::Failure),
+        }
+    }
+
+    pub fn with_cue(self, cue: Option<Cue>) -> Self {
+        Self { cue, ..self }
+    }
+}
+
+struct Toast {
+    id: u64,
+    feedback: Feedback,
+    expires: Option<Instant>,
+}
+
+/// In-app notifications shown over the current page
+#[derive(Default)]
+pub struct Toasts {
+    next_id: u64,
+    toasts: Vec<Toast>,
+}
+
+impl Toasts {
+    /// Show the feedback, errors always and successes only if `successes` is set
+    pub fn report(&mut self, feedback: Feedback, successes: bool) {
+        if feedback.kind == FeedbackKind::Error || successes {
+            self.push(feedback);
+        }
+    }
+
+    pub fn push(&mut self, feedback: Feedback) {
+        let expires = match feedback.kind {
+            FeedbackKind::Success => Some(Instant::now() + TOAST_DURATION),
+            FeedbackKind::Error => None,
+        };
+        self.toasts.push(Toast {
+            id: self.next_id,
+            feedback,
+            expires,
+        });
+        self.next_id += 1;
+    }
+
+    pub fn dismiss(&mut self, id: u64) {
+        self.toasts.retain(|t| t.id != id);
+    }
+
+    /// Remove the toasts which have been shown long enough
+    pub fn expire(&mut self, now: Instant) {
+        self.toasts.retain(|t| t.expires.is_none_or(|e| e > now));
+    }
+
+    /// Whether some toasts are waiting to expire, errors staying until dismissed
+    pub fn expiring(&self) -> bool {
+        self.toasts.iter().any(|t| t.expires.is_some())
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        Column::from_iter(self.toasts.iter().map(|toast| {
+            let style = match toast.feedback.kind {
+                FeedbackKind::Success => container::success,
+                FeedbackKind::Error => container::danger,
+            };
+            container(
+                Row::new()
+                    .push(text(&toast.feedback.message).size(14))
+                    .push(
+                        button(text("X").size(12))
+                            .on_press(Message::DismissToast(toast.id))
+                            .style(button::text),
+                    )
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .padding(10)
+            .max_width(400)
+            .style(style)
+            .into()
+        }))
+        .spacing(8)
+        .into()
+    }
+}
+
+/// A freedesktop notification reporting feedback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopNotification {
+    pub summary: &'static str,
+    pub body: String,
+    pub critical: bool,
+    pub timeout: Duration,
+}
+
+impl From<&Feedback> for DesktopNotification {
+    fn from(feedback: &Feedback) -> Self {
+        let (summary, critical) = match feedback.kind {
+            FeedbackKind::Success => ("VN Record", false),
+            FeedbackKind::Error => ("VN Record failed", true),
+        };
+        DesktopNotification {
+            summary,
+            body: feedback.message.clone(),
+            critical,
+            timeout: TOAST_DURATION,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Failed to show desktop notification: {0}")]
+pub struct NotifyError(String);
+
+/// Where desktop notifications are shown
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &DesktopNotification) -> Result<(), NotifyError>;
+}
+
+/// The notification server on the D-Bus session bus
+pub struct SessionBus;
+
+impl Notifier for SessionBus {
+    fn notify(&self, notification: &DesktopNotification) -> Result<(), NotifyError> {
+        Notification::new()
+            .appname("VN Record")
+            .summary(notification.summary)
+            .body(&notification.body)
+            .urgency(if notification.critical {
+                Urgency::Critical
+            } else {
+                Urgency::Low
+            })
+            .timeout(Timeout::Milliseconds(
+                notification.timeout.as_millis() as u32
+            ))
+            .show()
+            .map(|_| ())
+            .map_err(|e| NotifyError(e.to_string()))
+    }
+}
+
+/// Show the feedback as a desktop notification, without blocking the caller.
+pub fn notify_desktop(notifier: &Arc<dyn Notifier>, feedback: &Feedback) {
+    let notifier = notifier.clone();
+    let notification = DesktopNotification::from(feedback);
+    std::thread::spawn(move || {
+        if let Err(e) = notifier.notify(&notification) {
+            eprintln!("{}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader},
+        process::{Command, Stdio},
+        sync::{Mutex, mpsc},
+    };
+
+    use super::*;
+
+    /// Stands in for the session bus, handing the notifications over to the test
+    struct FakeBus {
+        sender: Mutex<mpsc::Sender<DesktopNotification>>,
+        fail: bool,
+    }
+
+    impl Notifier for FakeBus {
+        fn notify(&self, notification: &DesktopNotification) -> Result<(), NotifyError> {
+            self.sender
+                .lock()
+                .unwrap()
+                .send(notification.clone())
+                .unwrap();
+            if self.fail {
+                return Err(NotifyError("no notification server".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    fn fake_bus(fail: bool) -> (Arc<dyn Notifier>, mpsc::Receiver<DesktopNotification>) {
+        let (sender, receiver) = mpsc::channel();
+        let bus = FakeBus {
+            sender: Mutex::new(sender),
+            fail,
+        };
+        (Arc::new(bus), receiver)
+    }
+
+    #[test]
+    fn notifies_success_and_errors() {
+        let (bus, received) = fake_bus(false);
+        notify_desktop(&bus, &Feedback::success("Recording saved"));
+        notify_desktop(&bus, &Feedback::error("Failed to copy"));
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            received.recv_timeout(timeout).unwrap(),
+            DesktopNotification {
+                summary: "VN Record",
+                body: "Recording saved".to_string(),
+                critical: false,
+                timeout: TOAST_DURATION,
+            }
+        );
+        let error = received.recv_timeout(timeout).unwrap();
+        assert_eq!(error.summary, "VN Record failed");
+        assert_eq!(error.body, "Failed to copy");
+        assert!(error.critical);
+    }
+
+    /// Receives notifications as the desktop's notification server would
+    struct NotificationServer(Mutex<mpsc::Sender<(String, String, String, i32)>>);
+
+    #[zbus::interface(name = "org.freedesktop.Notifications")]
+    impl NotificationServer {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            app_name: String,
+            _replaces_id: u32,
+            _app_icon: String,
+            summary: String,
+            body: String,
+            _actions: Vec<String>,
+            _hints: HashMap<String, zbus::zvariant::OwnedValue>,
+            expire_timeout: i32,
+        ) -> u32 {
+            let notification = (app_name, summary, body, expire_timeout);
+            self.0.lock().unwrap().send(notification).unwrap();
+            1
+        }
+    }
+
+    #[test]
+    fn notifies_through_the_session_bus() {
+        let Ok(mut daemon) = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .spawn()
+        else {
+            eprintln!("dbus-daemon isn't installed, skipping");
+            return;
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim();
+
+        let (sender, received) = mpsc::channel();
+        let _server = zbus::blocking::connection::Builder::address(address)
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at(
+                "/org/freedesktop/Notifications",
+                NotificationServer(Mutex::new(sender)),
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        // SAFETY: no other test reads or writes this variable
+        unsafe { std::env::set_var("DBUS_SESSION_BUS_ADDRESS", address) };
+        let res = SessionBus.notify(&DesktopNotification::from(&Feedback::error(
+            "Failed to copy",
+        )));
+        let notification = received.recv_timeout(Duration::from_secs(5));
+        let _ = daemon.kill();
+        let _ = daemon.wait();
+
+        res.unwrap();
+        assert_eq!(
+            notification.unwrap(),
+            (
+                "VN Record".to_string(),
+                "VN Record failed".to_string(),
+                "Failed to copy".to_string(),
+                TOAST_DURATION.as_millis() as i32
+            )
+        );
+    }
+
+    #[test]
+    fn only_successes_expire() {
+        let mut toasts = Toasts::default();
+        toasts.push(Feedback::error("Failed to copy"));
+        assert!(!toasts.expiring());
+        toasts.push(Feedback::success("Recording saved"));
+        assert!(toasts.expiring());
+
+        toasts.expire(Instant::now() + TOAST_DURATION * 2);
+        assert!(!toasts.expiring());
+        assert_eq!(toasts.toasts.len(), 1);
+        assert_eq!(toasts.toasts[0].feedback.kind, FeedbackKind::Error);
+    }
+
+    #[test]
+    fn survives_a_failing_bus() {
+        let (bus, received) = fake_bus(true);
+        notify_desktop(&bus, &Feedback::error("Failed to copy"));
+        received.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn errors_are_shown_even_without_success_toasts() {
+        let mut toasts = Toasts::default();
+        toasts.report(Feedback::success("Copied"), false);
+        assert!(toasts.toasts.is_empty());
+        toasts.report(Feedback::error("Failed to copy"), false);
+        assert_eq!(toasts.toasts.len(), 1);
+        toasts.report(Feedback::success("Copied"), true);
+        assert_eq!(toasts.toasts.len(), 2);
+    }
+
+    #[test]
+    fn only_success_toasts_expire() {
+        let mut toasts = Toasts::default();
+        toasts.push(Feedback::success("Copied"));
+        toasts.push(Feedback::error("Failed to copy"));
+        toasts.expire(Instant::now() + TOAST_DURATION * 2);
+        assert_eq!(toasts.toasts.len(), 1);
+        assert_eq!(toasts.toasts[0].feedback.kind, FeedbackKind::Error);
+    }
+}
@@ -0,0 +1,770 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use iced::{
+    Alignment::Center,
+    Element,
+    Length::Fill,
+    widget::{Column, Row, button, image, scrollable, text, text_input},
+};
+use thiserror::Error;
+
+use crate::{
+    Message,
+    animation::AnimationFormat,
+    clipboard,
+    encode::{AudioFormat, probe_duration},
+    feedback::Feedback,
+    metadata::RecordMetadata,
+    record::{RecordQuery, RecordedData},
+    screenshot::ScreenshotFormat,
+};
+
+/// Number of clips shown before "Show more" is needed, so a large library doesn't load every
+/// screenshot at once
+const PAGE_SIZE: usize = 20;
+
+/// A record in the output directory, its audio and screenshot paired by their name prefix
+#[derive(Debug, Clone)]
+pub struct Clip {
+    pub name: String,
+    pub audio_path: PathBuf,
+    pub audio_format: AudioFormat,
+    pub screenshot_path: Option<PathBuf>,
+    pub metadata_path: Option<PathBuf>,
+    pub metadata: Option<RecordMetadata>,
+    pub animation_path: Option<PathBuf>,
+    pub modified: SystemTime,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("Invalid name {0:?}")]
+    InvalidName(String),
+    #[error("A record named {0:?} already exists")]
+    Exists(String),
+    #[error("IO error on history: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+impl Clip {
+    fn from_record(data: &RecordedData) -> Option<Clip> {
+        let file_name = data.audio_path.file_name()?.to_str()?;
+        let (name, _) = split_file_name(file_name)?;
+        Some(Clip {
+            name: name.to_string(),
+            audio_path: data.audio_path.clone(),
+            audio_format: data.audio_format,
+            screenshot_path: Some(data.screenshot_path.clone()),
+            metadata_path: Some(data.metadata_path.clone()),
+            metadata: Some(data.metadata.clone()),
+            animation_path: data.animation_path.clone(),
+            modified: SystemTime::now(),
+            duration: Some(data.duration),
+        })
+    }
+
+    /// Paths of the screenshot and audio
+    pub fn paths(&self) -> Vec<&PathBuf> {
+        self.screenshot_path
+            .iter()
+            .chain([&self.audio_path])
+            .collect()
+    }
+
+    /// Delete every file of the clip, even if some can't be, returning the first error. Files
+    /// already gone are skipped.
+    pub fn delete(&self) -> Result<(), HistoryError> {
+        let extra = self.metadata_path.iter().chain(&self.animation_path);
+        let mut res = Ok(());
+        for path in self.paths().into_iter().chain(extra) {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound && res.is_ok() => res = Err(e.into()),
+                _ => {}
+            }
+        }
+        res
+    }
+
+    /// Rename all files of the clip, keeping their suffixes so they stay paired.
+    pub fn rename(&self, new_name: &str) -> Result<Clip, HistoryError> {
+        let new_name = new_name.trim();
+        if new_name.is_empty()
+            || new_name.contains(['/', '\0'])
+            || new_name.starts_with('.')
+            || new_name.contains("_audio")
+            || new_name.contains("_screenshot")
+            || new_name.contains("_meta")
+            || new_name.contains("_animation")
+        {
+            return Err(HistoryError::InvalidName(new_name.to_string()));
+        }
+
+        let dir = self.audio_path.parent().unwrap_or(Path::new(""));
+        let audio_path = dir.join(format!(
+            "{}_audio.{}",
+            new_name,
+            self.audio_format.extension()
+        ));
+        let screenshot_path = self.screenshot_path.as_ref().map(|path| {
+            let extension = path.extension().unwrap_or_default().to_string_lossy();
+            dir.join(format!("{}_screenshot.{}", new_name, extension))
+        });
+        let metadata_path = self
+            .metadata_path
+            .as_ref()
+            .map(|_| dir.join(format!("{}_meta.json", new_name)));
+        let animation_path = self.animation_path.as_ref().map(|path| {
+            let extension = path.extension().unwrap_or_default().to_string_lossy();
+            dir.join(format!("{}_animation.{}", new_name, extension))
+        });
+        if [
+            Some(&audio_path),
+            screenshot_path.as_ref(),
+            metadata_path.as_ref(),
+            animation_path.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|p| p.exists())
+        {
+            return Err(HistoryError::Exists(new_name.to_string()));
+        }
+
+        let renames: Vec<(&PathBuf, &PathBuf)> = [
+            (Some(&self.audio_path), Some(&audio_path)),
+            (self.screenshot_path.as_ref(), screenshot_path.as_ref()),
+            (self.metadata_path.as_ref(), metadata_path.as_ref()),
+            (self.animation_path.as_ref(), animation_path.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(from, to)| from.zip(to))
+        .collect();
+        for (i, (from, to)) in renames.iter().enumerate() {
+            if let Err(e) = std::fs::rename(from, to) {
+                // Put back what was already renamed so the files stay paired
+                for (from, to) in renames[..i].iter().rev() {
+                    if let Err(e) = std::fs::rename(to, from) {
+                        eprintln!("Failed to restore {}: {}", from.display(), e);
+                    }
+                }
+                return Err(e.into());
+            }
+        }
+        Ok(Clip {
+            name: new_name.to_string(),
+            audio_path,
+            screenshot_path,
+            metadata_path,
+            animation_path,
+            ..self.clone()
+        })
+    }
+}
+
+/// Split `<name>_audio.<ext>`, `<name>_screenshot.<ext>`, `<name>_meta.json` or
+/// `<name>_animation.<ext>` into the name and the file kind
+fn split_file_name(file_name: &str) -> Option<(&str, FileKind)> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if let Some(name) = stem.strip_suffix("_audio") {
+        let format = AudioFormat::from_extension(extension)?;
+        Some((name, FileKind::Audio(format)))
+    } else if let Some(name) = stem.strip_suffix("_screenshot")
+        && ScreenshotFormat::from_extension(extension).is_some()
+    {
+        Some((name, FileKind::Screenshot))
+    } else if let Some(name) = stem.strip_suffix("_meta")
+        && extension == "json"
+    {
+        Some((name, FileKind::Metadata))
+    } else if let Some(name) = stem.strip_suffix("_animation")
+        && AnimationFormat::ALL
+            .iter()
+            .any(|f| f.extension() == extension)
+    {
+        Some((name, FileKind::Animation))
+    } else {
+        None
+    }
+}
+
+enum FileKind {
+    Audio(AudioFormat),
+    Screenshot,
+    Metadata,
+    Animation,
+}
+
+/// Query for a search, `tag:<name>` words matching only records with that exact tag
+fn parse_search(search: &str) -> RecordQuery {
+    let mut tag = None;
+    let mut words = Vec::new();
+    for word in search.split_whitespace() {
+        match word.strip_prefix("tag:") {
+            Some(name) if !name.is_empty() => tag = Some(name.to_string()),
+            _ => words.push(word),
+        }
+    }
+    RecordQuery {
+        text: (!words.is_empty()).then(|| words.join(" ")),
+        tag,
+        ..Default::default()
+    }
+}
+
+/// Index the records in `dir`, newest first. Screenshots without audio are skipped.
+pub fn scan(dir: &Path) -> std::io::Result<Vec<Clip>> {
+    let mut audio = BTreeMap::new();
+    let mut screenshots = BTreeMap::new();
+    let mut metadata = BTreeMap::new();
+    let mut animations = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some((name, kind)) = file_name.to_str().and_then(split_file_name) else {
+            continue;
+        };
+        match kind {
+            FileKind::Audio(format) => {
+                let modified = entry.metadata()?.modified()?;
+                audio.insert(name.to_string(), (entry.path(), format, modified));
+            }
+            FileKind::Screenshot => {
+                screenshots.insert(name.to_string(), entry.path());
+            }
+            FileKind::Metadata => {
+                metadata.insert(name.to_string(), entry.path());
+            }
+            FileKind::Animation => {
+                animations.insert(name.to_string(), entry.path());
+            }
+        }
+    }
+
+    let mut clips: Vec<Clip> = audio
+        .into_iter()
+        .map(|(name, (audio_path, audio_format, modified))| {
+            let metadata_path = metadata.remove(&name);
+            let metadata = metadata_path.as_deref().and_then(|path| {
+                RecordMetadata::load(path)
+                    .inspect_err(|e| eprintln!("Ignoring metadata {}: {}", path.display(), e))
+                    .ok()
+            });
+            let duration = match &metadata {
+                Some(metadata) => Some(Duration::from_secs_f64(metadata.duration_secs)),
+                None => probe_duration(&audio_path, audio_format),
+            };
+            Clip {
+                screenshot_path: screenshots.remove(&name),
+                animation_path: animations.remove(&name),
+                metadata_path,
+                metadata,
+                duration,
+                name,
+                audio_path,
+                audio_format,
+                modified,
+            }
+        })
+        .collect();
+    clips.sort_by_key(|c| std::cmp::Reverse(c.modified));
+    Ok(clips)
+}
+
+#[derive(Debug, Clone)]
+pub enum HistoryAction {
+    Refresh,
+    ShowMore,
+    Play(String),
+    Copy(String),
+    /// Ask for confirmation before deleting, `None` cancels
+    Delete(Option<String>),
+    ConfirmDelete(String),
+    /// Start renaming a clip, `None` cancels
+    Rename(Option<String>),
+    RenameInput(String),
+    ConfirmRename,
+    /// Start editing the tags of a clip, `None` cancels
+    Tags(Option<String>),
+    TagsInput(String),
+    ConfirmTags,
+    Search(String),
+}
+
+/// Browsable library of the records in the output directory
+pub struct History {
+    dir: PathBuf,
+    clips: Vec<Clip>,
+    shown: usize,
+    error: Option<String>,
+    deleting: Option<String>,
+    /// Clip being renamed and the name typed so far
+    renaming: Option<(String, String)>,
+    /// Clip whose tags are edited and the comma-separated list typed so far
+    tagging: Option<(String, String)>,
+    search: String,
+    /// Audio paths of the records matching the search, `None` shows every record
+    matches: Option<HashSet<PathBuf>>,
+}
+
+impl History {
+    pub fn new(dir: PathBuf) -> Self {
+        let mut history = Self {
+            dir,
+            clips: Vec::new(),
+            shown: PAGE_SIZE,
+            error: None,
+            deleting: None,
+            renaming: None,
+            tagging: None,
+            search: String::new(),
+            matches: None,
+        };
+        history.refresh();
+        history
+    }
+
+    /// Index another output directory
+    pub fn set_dir(&mut self, dir: PathBuf) {
+        if dir != self.dir {
+            self.dir = dir;
+            self.refresh();
+        }
+    }
+
+    pub fn refresh(&mut self) {
+        match scan(&self.dir) {
+            Ok(clips) => {
+                self.clips = clips;
+                self.error = None;
+            }
+            Err(e) => {
+                eprintln!("Failed to index {}: {}", self.dir.display(), e);
+                self.clips.clear();
+                self.error = Some(format!("Failed to read output folder: {}", e));
+            }
+        }
+        self.search();
+    }
+
+    /// Find the indexed records matching the search, only those with metadata can match
+    fn search(&mut self) {
+        if self.search.trim().is_empty() {
+            self.matches = None;
+            return;
+        }
+        let query = parse_search(&self.search);
+        self.matches = Some(
+            self.clips
+                .iter()
+                .filter(|c| c.metadata.as_ref().is_some_and(|m| query.matches(m)))
+                .map(|c| c.audio_path.clone())
+                .collect(),
+        );
+    }
+
+    /// Add a record that was just saved
+    pub fn add(&mut self, data: &RecordedData) {
+        if let Some(clip) = Clip::from_record(data) {
+            self.clips.insert(0, clip);
+            self.search();
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&Clip> {
+        self.clips.iter().find(|c| c.name == name)
+    }
+
+    /// Apply the action, returning feedback to show for it
+    pub fn update(&mut self, action: HistoryAction) -> Option<Feedback> {
+        match action {
+            HistoryAction::Refresh => {
+                self.refresh();
+                None
+            }
+            HistoryAction::ShowMore => {
+                self.shown += PAGE_SIZE;
+                None
+            }
+            HistoryAction::Play(name) => {
+                let clip = self.find(&name)?;
+                open::that(&clip.audio_path)
+                    .err()
+                    .map(|e| Feedback::error(format!("Failed to play {}: {}", name, e)))
+            }
+            HistoryAction::Copy(name) => {
+                let clip = self.find(&name)?;
+                Some(match clipboard::write_files(&clip.paths()) {
+                    Ok(()) => Feedback::success(format!("{} copied", name)),
+                    Err(e) => {
+                        Feedback::error(format!("Failed to copy {} to clipboard: {}", name, e))
+                    }
+                })
+            }
+            HistoryAction::Delete(name) => {
+                self.deleting = name;
+                None
+            }
+            HistoryAction::ConfirmDelete(name) => {
+                self.deleting = None;
+                let index = self.clips.iter().position(|c| c.name == name)?;
+                let res = self.clips[index].delete();
+                // Some files may be gone even if deleting another one failed
+                self.refresh();
+                Some(match res {
+                    Ok(()) => Feedback::success(format!("{} deleted", name)),
+                    Err(e) => Feedback::error(format!("Failed to delete {}: {}", name, e)),
+                })
+            }
+            HistoryAction::Rename(name) => {
+                self.renaming = name.map(|name| (name.clone(), name));
+                None
+            }
+            HistoryAction::Search(search) => {
+                self.search = search;
+                self.shown = PAGE_SIZE;
+                self.search();
+                None
+            }
+            HistoryAction::RenameInput(input) => {
+                if let Some((_, new_name)) = &mut self.renaming {
+                    *new_name = input;
+                }
+                None
+            }
+            HistoryAction::Tags(name) => {
+                self.tagging = name.and_then(|name| {
+                    let tags = self.find(&name)?.metadata.as_ref()?.tags.join(", ");
+                    Some((name, tags))
+                });
+                None
+            }
+            HistoryAction::TagsInput(input) => {
+                if let Some((_, tags)) = &mut self.tagging {
+                    *tags = input;
+                }
+                None
+            }
+            HistoryAction::ConfirmTags => {
+                let (name, tags) = self.tagging.take()?;
+                let clip = self.clips.iter_mut().find(|c| c.name == name)?;
+                let (Some(path), Some(metadata)) = (&clip.metadata_path, &mut clip.metadata) else {
+                    return None;
+                };
+                let mut edited = metadata.clone();
+                edited.set_tags(&tags);
+                match edited.save(path) {
+                    Ok(()) => {
+                        *metadata = edited;
+                        self.search();
+                        None
+                    }
+                    Err(e) => {
+                        self.tagging = Some((name.clone(), tags));
+                        Some(Feedback::error(format!(
+                            "Failed to save the tags of {}: {}",
+                            name, e
+                        )))
+                    }
+                }
+            }
+            HistoryAction::ConfirmRename => {
+                let (name, new_name) = self.renaming.take()?;
+                let index = self.clips.iter().position(|c| c.name == name)?;
+                match self.clips[index].rename(&new_name) {
+                    Ok(clip) => {
+                        self.clips[index] = clip;
+                        self.search();
+                        None
+                    }
+                    Err(e) => {
+                        // Keep the input so it can be fixed
+                        self.renaming = Some((name.clone(), new_name));
+                        Some(Feedback::error(format!("Failed to rename {}: {}", name, e)))
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let action = Message::History;
+        let mut list = Column::new().spacing(10);
+        let clips: Vec<&Clip> = self
+            .clips
+            .iter()
+            .filter(|c| {
+                self.matches
+                    .as_ref()
+                    .is_none_or(|m| m.contains(&c.audio_path))
+            })
+            .collect();
+        for clip in clips.iter().take(self.shown) {
+            let thumbnail: Element<Message> = match &clip.screenshot_path {
+                Some(path) => image(path).height(72).into(),
+                None => text("No screenshot").size(12).style(text::secondary).into(),
+            };
+
+            let modified: DateTime<Local> = clip.modified.into();
+            let duration = clip
+                .duration
+                .map(|d| format!("{:.1}s", d.as_secs_f32()))
+                .unwrap_or_else(|| "?".to_string());
+            let mut details = format!(
+                "{} · {} · {}",
+                modified.format("%Y-%m-%d %H:%M:%S"),
+                duration,
+                clip.audio_format
+            );
+            let metadata = clip.metadata.as_ref();
+            if let Some(app_name) = metadata.and_then(|m| m.app_name.as_ref()) {
+                details = format!("{} · {}", app_name, details);
+            }
+            let details = text(details).size(12).style(text::secondary);
+            let sentence = metadata
+                .and_then(|m| m.sentence.as_ref())
+                .map(|s| text(s).size(14));
+            let tags: Option<Element<Message>> = match &self.tagging {
+                Some((name, tags)) if *name == clip.name => Some(
+                    text_input("Tags, separated by commas", tags)
+                        .on_input(move |v| action(HistoryAction::TagsInput(v)))
+                        .on_submit(action(HistoryAction::ConfirmTags))
+                        .size(12)
+                        .into(),
+                ),
+                _ => metadata
+                    .filter(|m| !m.tags.is_empty())
+                    .map(|m| text(m.tags.join(", ")).size(12).into()),
+            };
+
+            let name: Element<Message> = match &self.renaming {
+                Some((name, new_name)) if *name == clip.name => text_input("Name", new_name)
+                    .on_input(move |v| action(HistoryAction::RenameInput(v)))
+                    .on_submit(action(HistoryAction::ConfirmRename))
+                    .size(14)
+                    .into(),
+                _ => text(&clip.name).size(16).into(),
+            };
+
+            let controls: Element<Message> = if self.deleting.as_ref() == Some(&clip.name) {
+                Row::new()
+                    .push(
+                        button(text("Confirm Delete").size(12))
+                            .on_press(action(HistoryAction::ConfirmDelete(clip.name.clone())))
+                            .style(button::danger),
+                    )
+                    .push(
+                        button(text("Cancel").size(12))
+                            .on_press(action(HistoryAction::Delete(None)))
+                            .style(button::secondary),
+                    )
+                    .spacing(5)
+                    .into()
+            } else if self
+                .tagging
+                .as_ref()
+                .is_some_and(|(name, _)| *name == clip.name)
+            {
+                Row::new()
+                    .push(
+                        button(text("Save").size(12)).on_press(action(HistoryAction::ConfirmTags)),
+                    )
+                    .push(
+                        button(text("Cancel").size(12))
+                            .on_press(action(HistoryAction::Tags(None)))
+                            .style(button::secondary),
+                    )
+                    .spacing(5)
+                    .into()
+            } else if self
+                .renaming
+                .as_ref()
+                .is_some_and(|(name, _)| *name == clip.name)
+            {
+                Row::new()
+                    .push(
+                        button(text("Save").size(12))
+                            .on_press(action(HistoryAction::ConfirmRename)),
+                    )
+                    .push(
+                        button(text("Cancel").size(12))
+                            .on_press(action(HistoryAction::Rename(None)))
+                            .style(button::secondary),
+                    )
+                    .spacing(5)
+                    .into()
+            } else {
+                let clip_button = |label, a| {
+                    button(text(label).size(12))
+                        .on_press(action(a))
+                        .style(button::secondary)
+                };
+                Row::new()
+                    .push(clip_button("Play", HistoryAction::Play(clip.name.clone())))
+                    .push(clip_button("Copy", HistoryAction::Copy(clip.name.clone())))
+                    .push(clip_button(
+                        "Rename",
+                        HistoryAction::Rename(Some(clip.name.clone())),
+                    ))
+                    .push(
+                        metadata.map(|_| {
+                            clip_button("Tags", HistoryAction::Tags(Some(clip.name.clone())))
+                        }),
+                    )
+                    .push(
+                        button(text("Delete").size(12))
+                            .on_press(action(HistoryAction::Delete(Some(clip.name.clone()))))
+                            .style(button::danger),
+                    )
+                    .spacing(5)
+                    .into()
+            };
+
+            list = list.push(
+                Row::new()
+                    .push(thumbnail)
+                    .push(
+                        Column::new()
+                            .push(name)
+                            .push(sentence)
+                            .push(tags)
+                            .push(details)
+                            .push(controls)
+                            .spacing(4),
+                    )
+                    .align_y(Center)
+                    .spacing(15),
+            );
+        }
+        if clips.len() > self.shown {
+            list = list.push(
+                button(text("Show more").size(12))
+                    .on_press(action(HistoryAction::ShowMore))
+                    .style(button::secondary),
+            );
+        }
+        if self.clips.is_empty() && self.error.is_none() {
+            list = list.push(text("No records yet").size(14).style(text::secondary));
+        } else if clips.is_empty() {
+            list = list.push(text("No matching records").size(14).style(text::secondary));
+        }
+
+        Column::new()
+            .push(
+                Row::new()
+                    .push(text(format!("{} records", clips.len())).size(14))
+                    .push(
+                        text_input(
+                            "Search game, title, sentence, tag or tag:name",
+                            &self.search,
+                        )
+                        .on_input(move |v| action(HistoryAction::Search(v)))
+                        .size(14)
+                        .width(300),
+                    )
+                    .push(
+                        button(text("Refresh").size(12))
+                            .on_press(action(HistoryAction::Refresh))
+                            .style(button::secondary),
+                    )
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .push(
+                self.error
+                    .as_ref()
+                    .map(|e| text(e).size(12).style(text::danger)),
+            )
+            .push(scrollable(list).height(Fill))
+            .spacing(10)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip_in(dir: &Path) -> Clip {
+        std::fs::create_dir_all(dir).unwrap();
+        let audio_path = dir.join("1_audio.wav");
+        let screenshot_path = dir.join("1_screenshot.png");
+        std::fs::write(&audio_path, b"audio").unwrap();
+        std::fs::write(&screenshot_path, b"png").unwrap();
+        Clip {
+            name: "1".to_string(),
+            audio_path,
+            audio_format: AudioFormat::Wav,
+            screenshot_path: Some(screenshot_path),
+            metadata_path: None,
+            metadata: None,
+            animation_path: None,
+            modified: SystemTime::now(),
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn search_splits_tag_words() {
+        let query = parse_search(" tag:kanji  first line ");
+        assert_eq!(query.tag.as_deref(), Some("kanji"));
+        assert_eq!(query.text.as_deref(), Some("first line"));
+
+        let query = parse_search("tag: line");
+        assert_eq!(query.tag, None);
+        assert_eq!(query.text.as_deref(), Some("tag: line"));
+    }
+
+    #[test]
+    fn rename_keeps_files_paired() {
+        let dir = std::env::temp_dir().join(format!("vn_record-rename-{}", std::process::id()));
+        let clip = clip_in(&dir);
+        let renamed = clip.rename("line").unwrap();
+        assert!(dir.join("line_audio.wav").exists());
+        assert!(dir.join("line_screenshot.png").exists());
+        assert_eq!(renamed.name, "line");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn delete_removes_what_it_can() {
+        let dir = std::env::temp_dir().join(format!("vn_record-delete-{}", std::process::id()));
+        let mut clip = clip_in(&dir);
+        std::fs::remove_file(clip.screenshot_path.as_ref().unwrap()).unwrap();
+        // A directory in place of the metadata can't be removed as a file
+        let metadata_path = dir.join("1_meta.json");
+        std::fs::create_dir(&metadata_path).unwrap();
+        let animation_path = dir.join("1_animation.webp");
+        std::fs::write(&animation_path, b"webp").unwrap();
+        clip.metadata_path = Some(metadata_path.clone());
+        clip.animation_path = Some(animation_path.clone());
+
+        assert!(matches!(clip.delete(), Err(HistoryError::IO(_))));
+        assert!(!clip.audio_path.exists());
+        assert!(!animation_path.exists());
+        assert!(metadata_path.exists());
+
+        std::fs::remove_dir(&metadata_path).unwrap();
+        assert!(clip.delete().is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_rename_restores_renamed_files() {
+        let dir = std::env::temp_dir().join(format!("vn_record-rollback-{}", std::process::id()));
+        let mut clip = clip_in(&dir);
+        // The screenshot vanished after indexing, so its rename fails after the audio's
+        std::fs::remove_file(clip.screenshot_path.as_ref().unwrap()).unwrap();
+        clip.screenshot_path = Some(dir.join("1_screenshot.png"));
+        assert!(matches!(clip.rename("line"), Err(HistoryError::IO(_))));
+        assert!(dir.join("1_audio.wav").exists());
+        assert!(!dir.join("line_audio.wav").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,47 +1,188 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+    time::Duration,
+};
 
-use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState, hotkey::HotKey};
+use global_hotkey::{
+    GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState,
+    hotkey::{Code, HotKey, Modifiers},
+};
 use iced::{
     futures::channel::mpsc,
     futures::{SinkExt, Stream, StreamExt},
-    stream,
+    keyboard, stream,
 };
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum GHKMessage {
     Record,
     CopyLastRecord,
     CopyLastScreenshot,
     CopyLastAudio,
+    SaveReplay,
+    SendToAnki,
+}
+
+impl GHKMessage {
+    pub const ALL: [GHKMessage; 6] = [
+        GHKMessage::Record,
+        GHKMessage::CopyLastRecord,
+        GHKMessage::CopyLastScreenshot,
+        GHKMessage::CopyLastAudio,
+        GHKMessage::SaveReplay,
+        GHKMessage::SendToAnki,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            GHKMessage::Record => "Start/Stop Record",
+            GHKMessage::CopyLastRecord => "Copy Last Record",
+            GHKMessage::CopyLastScreenshot => "Copy Last Screenshot",
+            GHKMessage::CopyLastAudio => "Copy Last Audio",
+            GHKMessage::SaveReplay => "Save Replay",
+            GHKMessage::SendToAnki => "Send Last Record to Anki",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum HotkeyError {
+    #[error("{hotkey} is already bound to \"{}\"", existing.label())]
+    Conflict {
+        hotkey: HotKey,
+        existing: GHKMessage,
+    },
+    #[error("Failed to register {hotkey}: {source}")]
+    Register {
+        hotkey: HotKey,
+        source: global_hotkey::Error,
+    },
+    #[error("Global hotkeys are unavailable: {0}")]
+    Unavailable(String),
 }
 
 pub struct GHKService {
-    manage: GlobalHotKeyManager,
+    /// Why global hotkeys can't be used on this system, if they can't
+    manage: Result<GlobalHotKeyManager, String>,
+    bindings: RwLock<Bindings>,
+}
+
+#[derive(Default)]
+struct Bindings {
     map: HashMap<u32, GHKMessage>,
     rev_map: HashMap<GHKMessage, Vec<HotKey>>,
 }
 
+impl Bindings {
+    fn bind(
+        &mut self,
+        manage: &GlobalHotKeyManager,
+        hotkey: HotKey,
+        message: GHKMessage,
+    ) -> Result<(), HotkeyError> {
+        if let Some(&existing) = self.map.get(&hotkey.id) {
+            if existing == message {
+                return Ok(());
+            }
+            return Err(HotkeyError::Conflict { hotkey, existing });
+        }
+        manage
+            .register(hotkey)
+            .map_err(|source| HotkeyError::Register { hotkey, source })?;
+        self.map.insert(hotkey.id, message);
+        self.rev_map.entry(message).or_default().push(hotkey);
+        Ok(())
+    }
+
+    fn unbind(&mut self, manage: &GlobalHotKeyManager, message: GHKMessage) {
+        for hotkey in self.rev_map.remove(&message).unwrap_or_default() {
+            if let Err(e) = manage.unregister(hotkey) {
+                eprintln!("Failed to unregister {}: {}", hotkey, e);
+            }
+            self.map.remove(&hotkey.id);
+        }
+    }
+}
+
 impl GHKService {
     pub fn new() -> Self {
         Self {
-            manage: GlobalHotKeyManager::new().unwrap(),
-            map: HashMap::new(),
-            rev_map: HashMap::new(),
+            manage: GlobalHotKeyManager::new().map_err(|e| e.to_string()),
+            bindings: Default::default(),
         }
     }
 
-    pub fn bind(&mut self, hotkey: HotKey, message: GHKMessage) {
-        self.manage.register(hotkey).unwrap();
-        self.map.insert(hotkey.id, message);
-        self.rev_map.entry(message).or_default().push(hotkey);
-    }
+    /// Bind each message to its hotkey, replacing the message's previous hotkeys.
+    ///
+    /// Messages absent from `bindings` are unbound. Returns the bindings which failed, those
+    /// messages keep their previous hotkeys.
+    pub fn rebind_all(
+        &self,
+        bindings: &BTreeMap<GHKMessage, HotKey>,
+    ) -> Vec<(GHKMessage, HotkeyError)> {
+        let manage = match &self.manage {
+            Ok(manage) => manage,
+            Err(e) => {
+                return bindings
+                    .keys()
+                    .map(|msg| (*msg, HotkeyError::Unavailable(e.clone())))
+                    .collect();
+            }
+        };
+        let mut state = self.bindings.write().unwrap();
+        let unbound: Vec<_> = state
+            .rev_map
+            .keys()
+            .filter(|msg| !bindings.contains_key(msg))
+            .copied()
+            .collect();
+        for msg in unbound {
+            state.unbind(manage, msg);
+        }
+        let changed: Vec<_> = bindings
+            .iter()
+            .filter(|(msg, key)| state.rev_map.get(msg).is_none_or(|keys| keys != &[**key]))
+            .collect();
+
+        // Unbind all changed messages first so that swapping hotkeys between messages works
+        let previous: Vec<_> = changed
+            .iter()
+            .map(|(msg, _)| {
+                let keys = state.rev_map.get(msg).cloned().unwrap_or_default();
+                state.unbind(manage, **msg);
+                keys
+            })
+            .collect();
+        let failed: Vec<_> = changed
+            .into_iter()
+            .zip(previous)
+            .filter_map(|((msg, key), previous)| {
+                let e = state.bind(manage, *key, *msg).err()?;
+                Some((*msg, e, previous))
+            })
+            .collect();
 
-    pub fn get_message(&self, key: HotKey) -> Option<GHKMessage> {
-        self.map.get(&key.id).copied()
+        // Only restore once every new hotkey is bound, as a previous one may now be taken
+        failed
+            .into_iter()
+            .map(|(msg, e, previous)| {
+                for key in previous {
+                    if let Err(e) = state.bind(manage, key, msg) {
+                        eprintln!("Failed to restore {} for {:?}: {}", key, msg, e);
+                    }
+                }
+                (msg, e)
+            })
+            .collect()
     }
 
-    pub fn get_key(&self, message: GHKMessage) -> &[HotKey] {
-        self.rev_map.get(&message).map_or(&[], |v| v)
+    pub fn get_key(&self, message: GHKMessage) -> Vec<HotKey> {
+        let bindings = self.bindings.read().unwrap();
+        bindings.rev_map.get(&message).cloned().unwrap_or_default()
     }
 
     pub fn stream<'a>(&'a self) -> impl Stream<Item = GHKMessage> + 'a {
@@ -49,9 +190,13 @@ impl GHKService {
             if ev.state != HotKeyState::Pressed {
                 return None;
             }
-            self.map.get(&ev.id).copied()
+            self.get_message_by_id(ev.id)
         })
     }
+
+    fn get_message_by_id(&self, id: u32) -> Option<GHKMessage> {
+        self.bindings.read().unwrap().map.get(&id).copied()
+    }
 }
 
 fn ghk_stream() -> impl Stream<Item = GlobalHotKeyEvent> {
@@ -68,3 +213,132 @@ fn ghk_stream() -> impl Stream<Item = GlobalHotKeyEvent> {
         },
     )
 }
+
+/// Find hotkeys which are bound to more than one message.
+pub fn find_conflicts(bindings: &BTreeMap<GHKMessage, HotKey>) -> Vec<(HotKey, Vec<GHKMessage>)> {
+    let mut by_key: BTreeMap<u32, (HotKey, Vec<GHKMessage>)> = BTreeMap::new();
+    for (msg, key) in bindings {
+        by_key
+            .entry(key.id)
+            .or_insert((*key, Vec::new()))
+            .1
+            .push(*msg);
+    }
+    by_key
+        .into_values()
+        .filter(|(_, messages)| messages.len() > 1)
+        .collect()
+}
+
+/// Convert a key press in the GUI into a hotkey, `None` if the key can't be used as a hotkey
+/// (e.g. a lone modifier key).
+pub fn hotkey_from_key_press(
+    physical_key: &keyboard::key::Physical,
+    modifiers: keyboard::Modifiers,
+) -> Option<HotKey> {
+    let keyboard::key::Physical::Code(code) = physical_key else {
+        return None;
+    };
+    let code = convert_code(*code)?;
+
+    let mut mods = Modifiers::empty();
+    mods.set(Modifiers::SHIFT, modifiers.shift());
+    mods.set(Modifiers::CONTROL, modifiers.control());
+    mods.set(Modifiers::ALT, modifiers.alt());
+    mods.set(Modifiers::SUPER, modifiers.logo());
+    Some(HotKey::new(Some(mods), code))
+}
+
+/// Map each listed key to the global-hotkey key of the same name, both crates following the W3C
+/// key code names
+macro_rules! same_codes {
+    ($code:expr, $($name:ident)*) => {
+        match $code {
+            $(keyboard::key::Code::$name => Some(Code::$name),)*
+            _ => None,
+        }
+    };
+}
+
+/// Convert an iced key code, `None` for modifier keys and keys global-hotkey doesn't know
+fn convert_code(code: keyboard::key::Code) -> Option<Code> {
+    same_codes!(
+        code,
+        Backquote Backslash BracketLeft BracketRight Comma Digit0 Digit1 Digit2 Digit3 Digit4 Digit5
+        Digit6 Digit7 Digit8 Digit9 Equal IntlBackslash IntlRo IntlYen KeyA KeyB KeyC KeyD KeyE KeyF
+        KeyG KeyH KeyI KeyJ KeyK KeyL KeyM KeyN KeyO KeyP KeyQ KeyR KeyS KeyT KeyU KeyV KeyW KeyX
+        KeyY KeyZ Minus Period Quote Semicolon Slash Backspace CapsLock ContextMenu Enter Space Tab
+        Convert KanaMode Lang1 Lang2 Lang3 Lang4 Lang5 NonConvert Delete End Help Home Insert
+        PageDown PageUp ArrowDown ArrowLeft ArrowRight ArrowUp NumLock Numpad0 Numpad1 Numpad2
+        Numpad3 Numpad4 Numpad5 Numpad6 Numpad7 Numpad8 Numpad9 NumpadAdd NumpadBackspace
+        NumpadClear NumpadClearEntry NumpadComma NumpadDecimal NumpadDivide NumpadEnter NumpadEqual
+        NumpadHash NumpadMemoryAdd NumpadMemoryClear NumpadMemoryRecall NumpadMemoryStore
+        NumpadMemorySubtract NumpadMultiply NumpadParenLeft NumpadParenRight NumpadStar
+        NumpadSubtract Escape Fn FnLock PrintScreen ScrollLock Pause BrowserBack BrowserFavorites
+        BrowserForward BrowserHome BrowserRefresh BrowserSearch BrowserStop Eject LaunchApp1
+        LaunchApp2 LaunchMail MediaPlayPause MediaSelect MediaStop MediaTrackNext MediaTrackPrevious
+        Power Sleep AudioVolumeDown AudioVolumeMute AudioVolumeUp WakeUp Hyper Turbo Abort Resume
+        Suspend Again Copy Cut Find Open Paste Props Select Undo Hiragana Katakana F1 F2 F3 F4 F5 F6
+        F7 F8 F9 F10 F11 F12 F13 F14 F15 F16 F17 F18 F19 F20 F21 F22 F23 F24 F25 F26 F27 F28 F29 F30
+        F31 F32 F33 F34 F35
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use keyboard::key::{Code as IcedCode, Physical};
+
+    use super::*;
+
+    #[test]
+    fn converts_keys_by_name() {
+        assert_eq!(convert_code(IcedCode::KeyR), Some(Code::KeyR));
+        assert_eq!(convert_code(IcedCode::Digit0), Some(Code::Digit0));
+        assert_eq!(convert_code(IcedCode::F12), Some(Code::F12));
+        assert_eq!(convert_code(IcedCode::NumpadEnter), Some(Code::NumpadEnter));
+        assert_eq!(convert_code(IcedCode::Backquote), Some(Code::Backquote));
+        assert_eq!(convert_code(IcedCode::ArrowLeft), Some(Code::ArrowLeft));
+    }
+
+    #[test]
+    fn modifier_keys_are_not_hotkeys() {
+        for code in [
+            IcedCode::ShiftLeft,
+            IcedCode::ControlRight,
+            IcedCode::AltLeft,
+            IcedCode::SuperLeft,
+            IcedCode::Meta,
+        ] {
+            let key = Physical::Code(code);
+            assert_eq!(hotkey_from_key_press(&key, Default::default()), None);
+        }
+    }
+
+    #[test]
+    fn key_press_keeps_modifiers() {
+        let key = Physical::Code(IcedCode::KeyR);
+        let hotkey =
+            hotkey_from_key_press(&key, keyboard::Modifiers::CTRL | keyboard::Modifiers::SHIFT);
+        assert_eq!(
+            hotkey,
+            Some(HotKey::new(
+                Some(Modifiers::CONTROL | Modifiers::SHIFT),
+                Code::KeyR
+            ))
+        );
+    }
+
+    #[test]
+    fn finds_hotkeys_bound_twice() {
+        let shared = HotKey::new(Some(Modifiers::ALT), Code::KeyR);
+        let bindings = BTreeMap::from([
+            (GHKMessage::Record, shared),
+            (GHKMessage::CopyLastRecord, HotKey::new(None, Code::F9)),
+            (GHKMessage::SaveReplay, shared),
+        ]);
+        assert_eq!(
+            find_conflicts(&bindings),
+            vec![(shared, vec![GHKMessage::Record, GHKMessage::SaveReplay])]
+        );
+    }
+}
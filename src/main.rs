@@ -1,61 +1,118 @@
+mod animation;
+mod anki;
+mod capture;
 mod clipboard;
+mod config;
+mod crop;
+mod cue;
+mod dsp;
+mod encode;
+mod feedback;
+mod history;
 mod hotkey;
+mod metadata;
+mod picker;
 mod process;
 mod record;
+mod screenshot;
+mod settings;
+mod texthook;
+mod vad;
 
-use std::{path::PathBuf, sync::LazyLock, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
 
-use global_hotkey::hotkey::{Code, HotKey, Modifiers};
+use anki::{NoteMedia, NoteTarget};
+use clipboard::ClipboardError;
+use config::Config;
+use crop::{CropAction, CropEditor};
+use cue::Cue;
+use feedback::{Feedback, FeedbackKind, Notifier, SessionBus, Toasts};
+use history::{History, HistoryAction};
 use hotkey::{GHKMessage, GHKService};
 use iced::{
     Alignment::{Center, End, Start},
-    Element, Font, Subscription, Theme,
+    Element, Font, Subscription, Task, Theme,
     font::{self, Weight},
-    futures::StreamExt,
-    widget::{Column, Container, Row, button, center, image, pick_list, text, value},
+    futures::{StreamExt, channel::oneshot},
+    keyboard,
+    widget::{
+        Column, Row, bottom_right, button, center, image, progress_bar, stack, text, text_input,
+        value,
+    },
 };
+use metadata::RecordMetadata;
+use picker::ProcessPicker;
 use process::{Process, processes};
-use record::{RecordConfig, RecordedData, Recorder};
-
-static GHK: LazyLock<GHKService> = LazyLock::new(|| {
-    let mut ghk = GHKService::new();
-    ghk.bind(
-        HotKey::new(Some(Modifiers::SHIFT), Code::Space),
-        GHKMessage::Record,
-    );
-    ghk.bind(
-        HotKey::new(Some(Modifiers::SHIFT), Code::Digit1),
-        GHKMessage::CopyLastRecord,
-    );
-    ghk.bind(
-        HotKey::new(Some(Modifiers::SHIFT), Code::Digit2),
-        GHKMessage::CopyLastScreenshot,
-    );
-    ghk.bind(
-        HotKey::new(Some(Modifiers::SHIFT), Code::Digit3),
-        GHKMessage::CopyLastAudio,
-    );
-    ghk
-});
+use record::{AutoRecorder, RecordConfig, RecordedData, Recorder, ReplayBuffer};
+use settings::{SettingEdit, SettingsForm};
+use texthook::TextHook;
+
+/// Games kept pinned at the top of the process picker
+const MAX_RECENT_WINDOWS: usize = 5;
+/// How often the text hook's state is refreshed in the main view
+const TEXT_HOOK_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+static GHK: LazyLock<GHKService> = LazyLock::new(GHKService::new);
 
 #[derive(Debug, Clone)]
 enum Message {
     RefreshProcessesList,
+    SearchProcesses(String),
+    /// A listed window was scrolled in or out of view, in the recent list or not
+    ProcessShown(u32, bool, bool),
     ProcessSelected(process::Process),
     ProcessDeselected,
     StartRecord(process::Process),
     StopRecord,
     ToggleRecord,
+    StartAuto(process::Process),
+    StopAuto,
     Tick(std::time::Instant),
     CopyLastRecord,
     CopyLastScreenshot,
     CopyLastAudio,
+    SaveReplay,
     OpenOutDir,
+    ShowPage(Page),
+    SettingEdited(SettingEdit),
+    HotkeyCaptureKey(keyboard::Event),
+    GlobalHotkey(GHKMessage),
+    SaveSettings,
+    RevertSettings,
+    DismissToast(u64),
+    History(HistoryAction),
+    AnkiNoteInput(String),
+    SendToAnki,
+    /// Export of the record with this metadata file finished, with the note ID or the error
+    AnkiSent(PathBuf, Result<u64, String>),
+    /// Records finished saving in the background
+    Saved(SaveKind, Vec<Result<RecordedData, String>>),
+    ChooseFrame(usize),
+    EditCrop(process::Process),
+    Crop(CropAction),
 }
 
+/// What was being saved, telling how the result is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SaveKind {
+    Recording,
+    Replay,
+    Auto,
+    /// The voice line in progress when auto recording was stopped
+    AutoStop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Page {
     Main,
-    Setting, // TODO
+    History,
+    Setting,
+    Crop,
 }
 
 struct RecordSession {
@@ -66,85 +123,179 @@ struct RecordSession {
 
 struct VNRecord {
     page: Page,
-    process_list: Vec<process::Process>,
+    /// Shown while no process is selected, its thumbnails stop when it's closed
+    process_picker: Option<ProcessPicker>,
     selected_process: Option<process::Process>,
     record_session: Option<RecordSession>,
     last_recorded: Option<RecordedData>,
+    /// Previews of the last record's burst frames and the one saved as its screenshot
+    frame_previews: Vec<image::Handle>,
+    chosen_frame: usize,
+    /// Anki note the last record is sent to, empty for the last added note
+    anki_note: String,
+    /// An export to Anki is running
+    anki_sending: bool,
+    history: History,
+    /// Open while the Crop page is shown
+    crop_editor: Option<CropEditor>,
+    replay: Option<ReplayBuffer>,
+    replay_error: Option<String>,
+    auto_recorder: Option<AutoRecorder>,
+    auto_error: Option<String>,
+    text_hook: Option<TextHook>,
+    text_hook_error: Option<String>,
 
-    out_dir: PathBuf,
+    config: Config,
+    settings_form: SettingsForm,
+    settings_error: Option<String>,
+    toasts: Toasts,
+    notifier: Arc<dyn Notifier>,
+    /// Hotkeys the OS refused to register
+    hotkey_errors: BTreeMap<GHKMessage, String>,
 }
 
 impl Default for VNRecord {
     fn default() -> Self {
-        Self {
+        let mut toasts = Toasts::default();
+        let (config, settings_error) = match Config::load() {
+            Ok(config) => (config, None),
+            Err(e) => {
+                let message = format!("Failed to load config, using default: {}", e);
+                toasts.push(Feedback::error(message));
+                (Config::default(), Some(e.to_string()))
+            }
+        };
+        if let Err(e) = ensure_output_dir(&config) {
+            toasts.push(Feedback::error(e));
+        }
+
+        let selected_process = config.last_window.as_ref().and_then(process::find_window);
+        if let Some(process) = &selected_process {
+            let feedback = Feedback::success(format!("Attached to {} again", process));
+            toasts.report(feedback, config.feedback.toasts);
+        }
+        let process_picker = selected_process.is_none().then(|| {
+            let list = processes().unwrap_or_else(|e| {
+                toasts.push(Feedback::error(format!("Failed to list processes: {}", e)));
+                Vec::new()
+            });
+            ProcessPicker::new(list, &config.recent_windows)
+        });
+        // Detect the clipboards now rather than on the first copy, which would stall
+        let backends = clipboard::backends();
+        if backends.is_empty() {
+            toasts.push(Feedback::error(ClipboardError::Unavailable.to_string()));
+        } else {
+            let names: Vec<_> = backends.iter().map(|b| b.to_string()).collect();
+            println!("Clipboard backends: {}", names.join(", "));
+        }
+
+        let mut app = Self {
             page: Page::Main,
-            process_list: processes().unwrap(),
-            selected_process: None,
+            process_picker,
+            selected_process,
             record_session: None,
             last_recorded: None,
+            frame_previews: Vec::new(),
+            chosen_frame: 0,
+            anki_note: String::new(),
+            anki_sending: false,
+            history: History::new(config.output_dir.clone()),
+            crop_editor: None,
+            replay: None,
+            replay_error: None,
+            auto_recorder: None,
+            auto_error: None,
+            text_hook: None,
+            text_hook_error: None,
 
-            out_dir: default_output_dir(),
-        }
+            settings_form: SettingsForm::from_config(&config),
+            config,
+            settings_error,
+            toasts,
+            notifier: Arc::new(SessionBus),
+            hotkey_errors: BTreeMap::new(),
+        };
+        app.apply_hotkeys();
+        app.restart_replay();
+        app.restart_text_hook();
+        app
     }
 }
 
 impl VNRecord {
-    pub fn view(&self) -> Container<'_, Message> {
+    pub fn view(&self) -> Element<'_, Message> {
+        let page_buttons = match self.page {
+            Page::Main => Row::new()
+                .push(
+                    button("History")
+                        .on_press(Message::ShowPage(Page::History))
+                        .style(button::secondary),
+                )
+                .push(
+                    button("Settings")
+                        .on_press(Message::ShowPage(Page::Setting))
+                        .style(button::secondary),
+                ),
+            Page::History | Page::Setting | Page::Crop => Row::new().push(
+                button("Back")
+                    .on_press(Message::ShowPage(Page::Main))
+                    .style(button::secondary),
+            ),
+        };
         let header = Column::new()
-            .push(text("VN Record").size(40))
-            .push(self.process_bar());
+            .push(
+                Row::new()
+                    .push(text("VN Record").size(40))
+                    .push(page_buttons.spacing(10))
+                    .align_y(Center)
+                    .spacing(20),
+            )
+            .push(
+                self.settings_error
+                    .as_ref()
+                    .map(|e| text(e).size(12).style(text::danger)),
+            );
 
-        center(
-            Column::new()
-                .push(header)
+        let body = match self.page {
+            Page::Main => Column::new()
+                .push(self.selected_process.as_ref().map(|p| self.process_bar(p)))
                 .push(self.selected_process.as_ref().map(|p| self.main_view(p)))
+                .push(self.process_picker.as_ref().map(|p| p.view()))
                 .push(self.setting_view())
                 .spacing(40),
-        )
-        .padding(20)
-    }
-
-    fn process_bar(&self) -> Row<'_, Message> {
-        let process_selector = || {
-            Row::new()
-                .push(
-                    pick_list(
-                        self.process_list.as_slice(),
-                        self.selected_process.clone(),
-                        Message::ProcessSelected,
-                    )
-                    .placeholder("Attach a process"),
-                )
-                .push(button("Refresh").on_press(Message::RefreshProcessesList))
+            Page::History => Column::new().push(self.history.view()),
+            Page::Setting => Column::new().push(self.settings_form.view(&self.hotkey_errors)),
+            Page::Crop => Column::new().push(self.crop_editor.as_ref().map(|e| e.view())),
         };
 
-        let process_display = |process: &Process| {
-            let text = value(process)
-                .font(Font {
-                    weight: Weight::Bold,
-                    style: font::Style::Italic,
-                    ..Default::default()
-                })
-                .size(20)
-                .style(text::secondary);
+        stack([
+            center(Column::new().push(header).push(body).spacing(40))
+                .padding(20)
+                .into(),
+            bottom_right(self.toasts.view()).padding(20).into(),
+        ])
+        .into()
+    }
 
-            Row::new()
-                .push(text)
-                .push(
-                    button("X")
-                        .on_press_maybe(
-                            (!self.is_recording()).then_some(Message::ProcessDeselected),
-                        )
-                        .style(button::secondary),
-                )
-                .spacing(8)
-        };
+    fn process_bar(&self, process: &Process) -> Row<'_, Message> {
+        let text = value(process)
+            .font(Font {
+                weight: Weight::Bold,
+                style: font::Style::Italic,
+                ..Default::default()
+            })
+            .size(20)
+            .style(text::secondary);
 
-        if let Some(process) = &self.selected_process {
-            process_display(process)
-        } else {
-            process_selector()
-        }
+        Row::new()
+            .push(text)
+            .push(
+                button("X")
+                    .on_press_maybe((!self.is_recording()).then_some(Message::ProcessDeselected))
+                    .style(button::secondary),
+            )
+            .spacing(8)
     }
 
     fn main_view(&self, selected_process: &process::Process) -> Element<'_, Message> {
@@ -161,26 +312,183 @@ impl VNRecord {
             c = c.push(
                 Column::new()
                     .align_x(Center)
-                    .push(image(&lr.screenshot_path).height(256))
-                    .push(text(duration_str(lr.duration)).size(20)),
+                    .push(match self.frame_previews.get(self.chosen_frame) {
+                        // The file was overwritten, but its image may still be cached
+                        Some(preview) => image(preview.clone()).height(256),
+                        None => image(&lr.screenshot_path).height(256),
+                    })
+                    .push((!self.frame_previews.is_empty()).then(|| {
+                        Row::from_iter(self.frame_previews.iter().enumerate().map(
+                            |(i, preview)| {
+                                button(image(preview.clone()).height(64))
+                                    .on_press(Message::ChooseFrame(i))
+                                    .style(if i == self.chosen_frame {
+                                        button::primary
+                                    } else {
+                                        button::text
+                                    })
+                                    .padding(2)
+                                    .into()
+                            },
+                        ))
+                        .spacing(6)
+                    }))
+                    .push(
+                        text(format!(
+                            "{} ({})",
+                            duration_str(lr.duration),
+                            lr.audio_format
+                        ))
+                        .size(20),
+                    )
+                    .push(
+                        lr.metadata
+                            .sentence
+                            .as_ref()
+                            .map(|s| text(s).size(16).style(text::secondary)),
+                    )
+                    .push(
+                        Row::new()
+                            .push(
+                                text_input("Anki note ID (last added if empty)", &self.anki_note)
+                                    .on_input(Message::AnkiNoteInput)
+                                    .on_submit(Message::SendToAnki)
+                                    .size(14)
+                                    .width(260),
+                            )
+                            .push(
+                                button(
+                                    text(if self.anki_sending {
+                                        "Sending..."
+                                    } else {
+                                        "Send to Anki"
+                                    })
+                                    .size(14),
+                                )
+                                .on_press_maybe((!self.anki_sending).then_some(Message::SendToAnki))
+                                .style(button::secondary),
+                            )
+                            .align_y(Center)
+                            .spacing(10),
+                    )
+                    .spacing(10),
             )
         }
 
-        c = c.push(if let Some(rs) = &self.record_session {
+        let controls: Element<Message> = if let Some(rs) = &self.record_session {
             let duration = text(duration_str(rs.elasped)).size(30);
+            let level = progress_bar(0.0..=1.0, rs.recorder.level())
+                .length(100)
+                .girth(8);
 
-            Row::new()
+            let controls = Row::new()
                 .push(
                     button("Stop")
                         .on_press(Message::StopRecord)
                         .style(button::danger),
                 )
                 .push(duration)
-                .spacing(30)
+                .push(level)
+                .align_y(Center)
+                .spacing(30);
+
+            Column::new()
+                .push(controls)
+                .push(
+                    rs.recorder
+                        .warning()
+                        .map(|w| text(w).size(12).style(text::warning)),
+                )
+                .spacing(8)
+                .into()
+        } else if let Some(auto) = &self.auto_recorder {
+            let status = if auto.is_running() {
+                text("Listening for voice lines").size(20)
+            } else {
+                text("Capture stopped").size(20).style(text::danger)
+            };
+            let controls = Row::new()
+                .push(
+                    button("Stop Auto")
+                        .on_press(Message::StopAuto)
+                        .style(button::danger),
+                )
+                .push(status)
+                .push(progress_bar(0.0..=1.0, auto.level()).length(100).girth(8))
+                .align_y(Center)
+                .spacing(30);
+
+            Column::new()
+                .push(controls)
+                .push(
+                    self.auto_error
+                        .as_deref()
+                        .map(|e| text(e).size(12).style(text::danger))
+                        .or_else(|| {
+                            auto.warning()
+                                .map(|w| text(w).size(12).style(text::warning))
+                        }),
+                )
+                .spacing(8)
                 .into()
         } else {
-            Element::from(button("Record").on_press(Message::StartRecord(selected_process.clone())))
-        });
+            Row::new()
+                .push(button("Record").on_press(Message::StartRecord(selected_process.clone())))
+                .push(
+                    button("Auto")
+                        .on_press(Message::StartAuto(selected_process.clone()))
+                        .style(button::secondary),
+                )
+                .push(
+                    button("Crop")
+                        .on_press(Message::EditCrop(selected_process.clone()))
+                        .style(button::secondary),
+                )
+                .push(
+                    self.auto_error
+                        .as_deref()
+                        .map(|e| text(e).size(12).style(text::danger)),
+                )
+                .align_y(Center)
+                .spacing(10)
+                .into()
+        };
+        c = c.push(controls);
+
+        // The view is refreshed by the tick while recording, so the sentence follows the game
+        if self.is_recording()
+            && let Some(sentence) = self.text_hook.as_ref().and_then(|hook| hook.current())
+        {
+            c = c.push(text(sentence.text).size(14).style(text::secondary));
+        }
+        if let Some(e) = self.text_hook_error.clone().or_else(|| {
+            let e = self.text_hook.as_ref()?.error()?;
+            Some(format!(
+                "Can't read the clipboard, sentences aren't captured: {}",
+                e
+            ))
+        }) {
+            c = c.push(text(e).size(12).style(text::warning));
+        }
+
+        if self.config.replay.enabled {
+            let replay_button = button(value(format!(
+                "Save Last {}s",
+                self.config.replay.duration_secs
+            )))
+            .on_press_maybe(self.replay.is_some().then_some(Message::SaveReplay))
+            .style(button::secondary);
+            let status = self
+                .replay_error
+                .as_deref()
+                .map(|e| text(e).size(12).style(text::danger))
+                .or_else(|| {
+                    let warning = self.replay.as_ref()?.warning()?;
+                    Some(text(warning).size(12).style(text::warning))
+                });
+
+            c = c.push(Column::new().push(replay_button).push(status).spacing(8));
+        }
 
         c.into()
     }
@@ -189,7 +497,7 @@ impl VNRecord {
         let mut elems: Vec<(&str, Element<Message>)> = vec![(
             "Output Folder",
             button(
-                value(self.out_dir.display())
+                value(self.config.output_dir.display())
                     .size(10)
                     .style(text::secondary),
             )
@@ -199,14 +507,17 @@ impl VNRecord {
             .into(),
         )];
 
-        for (label, msg) in [
-            ("Start/Stop Record", GHKMessage::Record),
-            ("Copy Last Record", GHKMessage::CopyLastRecord),
-            ("Copy Last Screenshot", GHKMessage::CopyLastScreenshot),
-            ("Copy Last Audio", GHKMessage::CopyLastAudio),
-        ] {
-            if let Some(key) = GHK.get_key(msg).first() {
-                elems.push((label, value(key).size(10).style(text::secondary).into()));
+        for msg in GHKMessage::ALL {
+            if self.hotkey_errors.contains_key(&msg) {
+                elems.push((
+                    msg.label(),
+                    text("Unavailable").size(10).style(text::danger).into(),
+                ));
+            } else if let Some(key) = GHK.get_key(msg).first() {
+                elems.push((
+                    msg.label(),
+                    value(key).size(10).style(text::secondary).into(),
+                ));
             }
         }
 
@@ -225,120 +536,615 @@ impl VNRecord {
     }
 
     pub fn subscription(&self) -> Subscription<Message> {
-        let tick = if self.record_session.is_some() {
+        let tick = if self.record_session.is_some()
+            || self.auto_recorder.is_some()
+            || self.toasts.expiring()
+        {
             iced::time::every(std::time::Duration::from_millis(100)).map(Message::Tick)
         } else {
             Subscription::none()
         };
 
-        let ghk = Subscription::run(|| {
-            GHK.stream().map(|msg| match msg {
-                GHKMessage::Record => Message::ToggleRecord,
-                GHKMessage::CopyLastRecord => Message::CopyLastRecord,
-                GHKMessage::CopyLastScreenshot => Message::CopyLastScreenshot,
-                GHKMessage::CopyLastAudio => Message::CopyLastAudio,
-            })
-        });
+        // Redraws the live thumbnails of the picker
+        let thumbnails = if self.process_picker.is_some() && self.page == Page::Main {
+            iced::time::every(picker::THUMBNAIL_INTERVAL).map(Message::Tick)
+        } else {
+            Subscription::none()
+        };
+
+        // Shows when the clipboard stops or starts being readable again
+        let text_hook = if self.text_hook.is_some() && self.page == Page::Main {
+            iced::time::every(TEXT_HOOK_STATUS_INTERVAL).map(Message::Tick)
+        } else {
+            Subscription::none()
+        };
+
+        let ghk = Subscription::run(|| GHK.stream().map(Message::GlobalHotkey));
+
+        let capture = if self.settings_form.is_capturing() {
+            keyboard::listen().map(Message::HotkeyCaptureKey)
+        } else {
+            Subscription::none()
+        };
 
-        Subscription::batch([tick, ghk])
+        Subscription::batch([tick, thumbnails, text_hook, ghk, capture])
     }
 
-    pub fn update(&mut self, message: Message) {
+    pub fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::RefreshProcessesList => {
-                self.process_list = processes().unwrap();
+                match processes() {
+                    Ok(list) => {
+                        if let Some(picker) = &mut self.process_picker {
+                            picker.set_processes(list, &self.config.recent_windows);
+                        }
+                    }
+                    Err(e) => {
+                        self.feedback(Feedback::error(format!("Failed to list processes: {}", e)))
+                    }
+                }
+                // The last game may have been started since
+                if self.selected_process.is_none()
+                    && let Some(process) = self
+                        .config
+                        .last_window
+                        .as_ref()
+                        .and_then(process::find_window)
+                {
+                    return self.update(Message::ProcessSelected(process));
+                }
+            }
+            Message::ProcessShown(id, recent, shown) => {
+                if let Some(picker) = &mut self.process_picker {
+                    picker.set_shown(id, recent, shown);
+                }
+            }
+            Message::SearchProcesses(search) => {
+                if let Some(picker) = &mut self.process_picker {
+                    picker.search(search);
+                }
             }
             Message::ProcessSelected(process) => {
+                self.remember_window(Some(&process));
+                self.process_picker = None;
                 self.selected_process = Some(process);
+                self.restart_replay();
             }
             Message::ProcessDeselected => {
-                if let Some(rs) = self.record_session.take() {
-                    eprintln!("Process deselected while recording, recording forced to stop");
-                    rs.recorder.stop().unwrap();
+                let mut tasks = Vec::new();
+                // The recording is stopped and saved, like with the Stop button
+                if self.record_session.is_some() {
+                    tasks.push(self.update(Message::StopRecord));
                 }
+                tasks.push(self.update(Message::StopAuto));
                 self.selected_process = None;
+                self.remember_window(None);
+                self.process_picker =
+                    Some(ProcessPicker::new(Vec::new(), &self.config.recent_windows));
+                tasks.push(self.update(Message::RefreshProcessesList));
+                self.restart_replay();
+                return Task::batch(tasks);
             }
-            Message::StartRecord(process) => {
-                let recorder = Recorder::start(RecordConfig {
-                    process,
-                    output_dir: self.out_dir.clone(),
-                });
-                self.record_session = Some(RecordSession {
-                    recorder,
-                    elasped: Default::default(),
-                    start_time: std::time::Instant::now(),
-                });
-                println!("Start recording");
-            }
+            Message::StartRecord(process) => match Recorder::start(
+                self.record_config(process),
+                self.replay
+                    .as_ref()
+                    .map(ReplayBuffer::pre_roll)
+                    .unwrap_or_default(),
+            ) {
+                Ok(recorder) => {
+                    self.record_session = Some(RecordSession {
+                        recorder,
+                        elasped: Default::default(),
+                        start_time: std::time::Instant::now(),
+                    });
+                    self.feedback(
+                        Feedback::success("Recording started").with_cue(Some(Cue::RecordStart)),
+                    );
+                }
+                Err(e) => {
+                    self.feedback(Feedback::error(format!("Failed to start recording: {}", e)))
+                }
+            },
             Message::StopRecord => {
                 if let Some(rs) = self.record_session.take() {
-                    let data = rs.recorder.stop().unwrap();
-                    self.last_recorded = Some(data);
+                    self.play_cue(Cue::RecordStop);
+                    return save_in_background(SaveKind::Recording, move || {
+                        vec![rs.recorder.stop()]
+                    });
                 }
-                println!("Stop recording");
-                if let Some(lr) = &self.last_recorded {
-                    clipboard::write_file_uris(&[&lr.screenshot_path, &lr.audio_path]);
-                    println!("Last record copied to clipboard");
+            }
+            Message::SaveReplay => {
+                let Some(replay) = &mut self.replay else {
+                    return Task::none();
+                };
+                match replay.take() {
+                    Ok(replay) => {
+                        return save_in_background(SaveKind::Replay, move || vec![replay.save()]);
+                    }
+                    Err(e) => {
+                        self.feedback(Feedback::error(format!("Failed to save replay: {}", e)));
+                        self.replay_error = Some(e.to_string());
+                    }
                 }
             }
+            Message::Saved(kind, records) => self.saved(kind, records),
             Message::ToggleRecord => {
                 let Some(selected_process) = self.selected_process.clone() else {
-                    return;
+                    return Task::none();
                 };
-                println!("Toggle recording");
-                match self.record_session {
+                if self.auto_recorder.is_some() {
+                    return self.update(Message::StopAuto);
+                }
+                return match self.record_session {
                     Some(_) => self.update(Message::StopRecord),
                     None => self.update(Message::StartRecord(selected_process)),
+                };
+            }
+            Message::StartAuto(process) => {
+                match AutoRecorder::start(self.record_config(process), self.config.vad.clone()) {
+                    Ok(auto) => {
+                        self.auto_recorder = Some(auto);
+                        self.auto_error = None;
+                        self.play_cue(Cue::RecordStart);
+                    }
+                    Err(e) => self.auto_error = Some(e.to_string()),
+                }
+            }
+            Message::StopAuto => {
+                if let Some(auto) = self.auto_recorder.take() {
+                    self.play_cue(Cue::RecordStop);
+                    return save_in_background(SaveKind::AutoStop, move || auto.stop());
                 }
             }
             Message::Tick(now) => {
                 if let Some(rs) = &mut self.record_session {
                     rs.elasped = now.duration_since(rs.start_time)
                 }
+                self.toasts.expire(now);
+                if let Some(auto) = &mut self.auto_recorder {
+                    let records = auto.poll();
+                    let records = records.into_iter().map(|r| r.map_err(|e| e.to_string()));
+                    self.saved(SaveKind::Auto, records.collect());
+                }
             }
             Message::CopyLastRecord => {
-                if let Some(lr) = &self.last_recorded {
-                    clipboard::write_file_uris(&[&lr.screenshot_path, &lr.audio_path]);
-                    println!("Last record copied to clipboard");
-                }
+                self.copy_last("Last record", RecordedData::files);
             }
             Message::CopyLastScreenshot => {
-                if let Some(lr) = &self.last_recorded {
-                    clipboard::write_file_uris(&[&lr.screenshot_path]);
-                    println!("Last screenshot copied to clipboard");
-                }
+                self.copy_last("Last screenshot", |lr| vec![&lr.screenshot_path]);
             }
             Message::CopyLastAudio => {
-                if let Some(lr) = &self.last_recorded {
-                    clipboard::write_file_uris(&[&lr.audio_path]);
-                    println!("Last audio copied to clipboard");
-                }
+                self.copy_last("Last audio", |lr| vec![&lr.audio_path]);
             }
             Message::OpenOutDir => {
-                let output_dir = &self.out_dir;
-                if output_dir.exists() {
-                    open::that(output_dir).unwrap();
+                let output_dir = &self.config.output_dir;
+                if output_dir.exists()
+                    && let Err(e) = open::that(output_dir)
+                {
+                    self.feedback(Feedback::error(format!(
+                        "Failed to open output folder: {}",
+                        e
+                    )));
+                }
+            }
+            Message::DismissToast(id) => {
+                self.toasts.dismiss(id);
+            }
+            Message::AnkiNoteInput(note) => {
+                self.anki_note = note;
+            }
+            Message::SendToAnki => return self.send_to_anki(),
+            Message::AnkiSent(metadata_path, res) => self.anki_sent(&metadata_path, res),
+            Message::History(action) => {
+                if let Some(feedback) = self.history.update(action) {
+                    self.feedback(feedback);
+                }
+                // The last record may have been deleted or renamed
+                self.last_recorded.take_if(|lr| !lr.audio_path.exists());
+            }
+            Message::ShowPage(page) => {
+                self.page = page;
+                if page != Page::Crop {
+                    self.crop_editor = None;
+                }
+            }
+            Message::EditCrop(process) => {
+                match CropEditor::new(&process, &self.config.screenshot) {
+                    Ok(editor) => {
+                        self.crop_editor = Some(editor);
+                        self.page = Page::Crop;
+                    }
+                    Err(e) => self.feedback(Feedback::error(format!(
+                        "Failed to capture the window: {}",
+                        e
+                    ))),
+                }
+            }
+            Message::ChooseFrame(index) => self.choose_frame(index),
+            Message::Crop(CropAction::Save) => self.save_crop(),
+            Message::Crop(action) => {
+                if let Some(editor) = &mut self.crop_editor {
+                    editor.update(action);
+                }
+            }
+            Message::SettingEdited(edit) => {
+                self.settings_form.edit(edit);
+            }
+            Message::HotkeyCaptureKey(event) => {
+                self.settings_form.capture_key(event);
+            }
+            Message::GlobalHotkey(msg) => {
+                // Don't trigger actions while the user is choosing a new hotkey
+                if self.settings_form.is_capturing() {
+                    return Task::none();
+                }
+                return self.update(match msg {
+                    GHKMessage::Record => Message::ToggleRecord,
+                    GHKMessage::CopyLastRecord => Message::CopyLastRecord,
+                    GHKMessage::CopyLastScreenshot => Message::CopyLastScreenshot,
+                    GHKMessage::CopyLastAudio => Message::CopyLastAudio,
+                    GHKMessage::SaveReplay => Message::SaveReplay,
+                    GHKMessage::SendToAnki => Message::SendToAnki,
+                });
+            }
+            Message::SaveSettings => {
+                let res = self
+                    .settings_form
+                    .to_config(&self.config)
+                    .and_then(|config| config.save().map(|_| config));
+                match res {
+                    Ok(config) => {
+                        let output_dir_error = ensure_output_dir(&config).err();
+                        self.history.set_dir(config.output_dir.clone());
+                        self.settings_form = SettingsForm::from_config(&config);
+                        self.config = config;
+                        self.settings_error = None;
+                        self.apply_hotkeys();
+                        self.restart_replay();
+                        self.restart_text_hook();
+                        match output_dir_error {
+                            Some(e) => self.feedback(Feedback::error(e)),
+                            None => {
+                                self.feedback(Feedback::success("Settings saved").with_cue(None))
+                            }
+                        }
+                    }
+                    Err(e) => self.settings_error = Some(e.to_string()),
                 }
             }
+            Message::RevertSettings => {
+                self.settings_form = SettingsForm::from_config(&self.config);
+                self.settings_error = None;
+            }
         }
+        Task::none()
     }
 
-    fn is_recording(&self) -> bool {
-        self.record_session.is_some()
+    fn record_config(&self, process: Process) -> RecordConfig {
+        RecordConfig {
+            process,
+            output_dir: self.config.output_dir.clone(),
+            trim: self.config.trim.clone(),
+            loudness: self.config.loudness.clone(),
+            background: self.config.background.clone(),
+            audio: self.config.audio.clone(),
+            screenshot: self.config.screenshot.clone(),
+            animation: self.config.animation.clone(),
+        }
     }
-}
 
-fn default_output_dir() -> PathBuf {
-    let mut output_dir = dirs::data_local_dir().unwrap();
-    output_dir.push("vn_record");
-    if !output_dir.exists() {
-        std::fs::create_dir_all(&output_dir).unwrap();
+    /// Keep the new record as the last one and copy it to the clipboard
+    fn set_last_recorded(&mut self, mut data: RecordedData, what: &str, cue: Option<Cue>) {
+        if let Some(sentence) = self
+            .text_hook
+            .as_ref()
+            .and_then(|hook| hook.sentence_for(&data.metadata))
+        {
+            data.metadata.sentence = Some(sentence.text);
+            if let Err(e) = data.save_metadata() {
+                data.warnings.push(format!(
+                    "Failed to save the sentence in the metadata: {}",
+                    e
+                ));
+            }
+        }
+        let res = clipboard::write_files(&data.files());
+        let duration = data.duration.as_secs_f32();
+        let warnings = data.warnings.clone();
+        self.history.add(&data);
+        self.frame_previews = data
+            .frames
+            .iter()
+            .map(|f| image::Handle::from_rgba(f.width(), f.height(), f.as_raw().clone()))
+            .collect();
+        self.chosen_frame = data.frames.len().saturating_sub(1);
+        self.last_recorded = Some(data);
+        match res {
+            Ok(()) => self.feedback(
+                Feedback::success(format!("{} saved and copied ({:.1}s)", what, duration))
+                    .with_cue(cue),
+            ),
+            Err(e) => self.feedback(Feedback::error(format!(
+                "{} saved, but failed to copy it to clipboard: {}",
+                what, e
+            ))),
+        }
+        for warning in warnings {
+            self.feedback(Feedback::error(warning).with_cue(None));
+        }
     }
 
-    // TODO: remove this line
-    println!("default output dir is {}", output_dir.display());
+    /// Copy files of the last record to the clipboard
+    fn copy_last(&mut self, what: &str, paths: impl Fn(&RecordedData) -> Vec<&PathBuf>) {
+        let Some(lr) = &self.last_recorded else {
+            return;
+        };
+        match clipboard::write_files(&paths(lr)) {
+            Ok(()) => self.feedback(Feedback::success(format!("{} copied", what))),
+            Err(e) => self.feedback(Feedback::error(format!(
+                "Failed to copy {} to clipboard: {}",
+                what.to_lowercase(),
+                e
+            ))),
+        }
+    }
 
-    output_dir
+    /// Replace the last record's screenshot with another burst frame and copy it again
+    fn choose_frame(&mut self, index: usize) {
+        let Some(lr) = &self.last_recorded else {
+            return;
+        };
+        if let Err(e) = lr.choose_frame(index, &self.config.screenshot) {
+            return self.feedback(Feedback::error(format!("Failed to save the frame: {}", e)));
+        }
+        self.chosen_frame = index;
+        self.copy_last("Chosen frame", RecordedData::files);
+    }
+
+    /// Save the crop being edited for its game and go back to the main page
+    fn save_crop(&mut self) {
+        let Some(editor) = self.crop_editor.take() else {
+            return;
+        };
+        let mut config = self.config.clone();
+        let crops = &mut config.screenshot.crops;
+        match editor.rect() {
+            Some(rect) => crops.insert(editor.game().to_string(), rect),
+            None => crops.remove(editor.game()),
+        };
+        match config.save() {
+            Ok(()) => {
+                self.config = config;
+                self.page = Page::Main;
+                self.feedback(Feedback::success(format!(
+                    "Screenshot crop saved for {}",
+                    editor.game()
+                )));
+            }
+            Err(e) => {
+                self.feedback(Feedback::error(format!("Failed to save crop: {}", e)));
+                self.crop_editor = Some(editor);
+            }
+        }
+    }
+
+    /// Attach the last record to the chosen Anki note, or the last added one, without blocking
+    /// the interface while AnkiConnect answers
+    fn send_to_anki(&mut self) -> Task<Message> {
+        let Some(lr) = &self.last_recorded else {
+            return Task::none();
+        };
+        if self.anki_sending {
+            return Task::none();
+        }
+        let target = match self.anki_note.trim() {
+            "" => NoteTarget::LastAdded,
+            id => match id.parse() {
+                Ok(id) => NoteTarget::Id(id),
+                Err(_) => {
+                    let message = format!("Invalid Anki note ID {:?}", id);
+                    self.feedback(Feedback::error(message));
+                    return Task::none();
+                }
+            },
+        };
+
+        let config = self.config.anki.clone();
+        let media = NoteMedia::from(lr);
+        let metadata_path = lr.metadata_path.clone();
+        let (sender, receiver) = oneshot::channel();
+        std::thread::spawn(move || {
+            let res = anki::export(&config, &media, target).map_err(|e| e.to_string());
+            let _ = sender.send(res);
+        });
+        self.anki_sending = true;
+        Task::perform(receiver, move |res| {
+            let res = res.unwrap_or_else(|_| Err("Export stopped unexpectedly".to_string()));
+            Message::AnkiSent(metadata_path.clone(), res)
+        })
+    }
+
+    /// Remember the note the record was attached to, in its metadata
+    fn anki_sent(&mut self, metadata_path: &Path, res: Result<u64, String>) {
+        self.anki_sending = false;
+        let note = match res {
+            Ok(note) => note,
+            Err(e) => {
+                let message = format!("Failed to send last record to Anki: {}", e);
+                return self.feedback(Feedback::error(message));
+            }
+        };
+        // Another record may have been made during the export
+        let saved = match &mut self.last_recorded {
+            Some(lr) if lr.metadata_path == metadata_path => {
+                lr.metadata.anki_notes.push(note);
+                lr.save_metadata().map_err(|e| e.to_string())
+            }
+            _ => RecordMetadata::load(metadata_path)
+                .and_then(|mut metadata| {
+                    metadata.anki_notes.push(note);
+                    metadata.save(metadata_path)
+                })
+                .map_err(|e| e.to_string()),
+        };
+        self.feedback(match saved {
+            Ok(()) => Feedback::success(format!("Last record sent to Anki note {}", note)),
+            Err(e) => Feedback::error(format!(
+                "Last record sent to Anki note {}, but failed to save it in the metadata: {}",
+                note, e
+            )),
+        });
+    }
+
+    /// Report the outcome of an action on the console, as a toast and as a desktop notification.
+    /// Errors are always shown in the app, the toasts setting only hides successes.
+    fn feedback(&mut self, feedback: Feedback) {
+        match feedback.kind {
+            FeedbackKind::Success => println!("{}", feedback.message),
+            FeedbackKind::Error => eprintln!("{}", feedback.message),
+        }
+        if self.config.feedback.desktop_notifications {
+            feedback::notify_desktop(&self.notifier, &feedback);
+        }
+        if let Some(cue) = feedback.cue {
+            self.play_cue(cue);
+        }
+        self.toasts.report(feedback, self.config.feedback.toasts);
+    }
+
+    fn play_cue(&self, cue: Cue) {
+        if self.config.feedback.sounds {
+            cue::play(cue, self.config.feedback.sound_volume);
+        }
+    }
+
+    /// Report records saved in the background
+    fn saved(&mut self, kind: SaveKind, records: Vec<Result<RecordedData, String>>) {
+        let what = match kind {
+            SaveKind::Recording => "Recording",
+            SaveKind::Replay => "Replay",
+            SaveKind::Auto | SaveKind::AutoStop => "Voice line",
+        };
+        // The stop cue was already played when stopping
+        let cue = match kind {
+            SaveKind::Recording | SaveKind::AutoStop => None,
+            SaveKind::Replay | SaveKind::Auto => Some(Cue::Success),
+        };
+        for record in records {
+            let error = record.as_ref().err().cloned();
+            match kind {
+                SaveKind::Recording => {}
+                SaveKind::Replay => self.replay_error = error,
+                SaveKind::Auto | SaveKind::AutoStop => self.auto_error = error,
+            }
+            match record {
+                Ok(data) => self.set_last_recorded(data, what, cue),
+                Err(e) => self.feedback(Feedback::error(format!(
+                    "Failed to save {}: {}",
+                    what.to_lowercase(),
+                    e
+                ))),
+            }
+        }
+    }
+
+    /// Save the selected window to attach it again on startup, and pin it in the picker
+    fn remember_window(&mut self, process: Option<&Process>) {
+        let mut config = self.config.clone();
+        config.last_window = process.map(Process::target);
+        if let Some(process) = process {
+            config
+                .recent_windows
+                .retain(|target| target.score(process).is_none());
+            config.recent_windows.insert(0, process.target());
+            config.recent_windows.truncate(MAX_RECENT_WINDOWS);
+        }
+        if config == self.config {
+            return;
+        }
+        match config.save() {
+            Ok(()) => self.config = config,
+            Err(e) => self.feedback(Feedback::error(format!(
+                "Failed to remember the selected window: {}",
+                e
+            ))),
+        }
+    }
+
+    /// (Re)start the replay buffer for the selected process according to the config
+    fn restart_replay(&mut self) {
+        self.replay = None;
+        self.replay_error = None;
+        let Some(process) = self.selected_process.clone() else {
+            return;
+        };
+        if !self.config.replay.enabled {
+            return;
+        }
+
+        let duration = Duration::from_secs(self.config.replay.duration_secs as u64);
+        match ReplayBuffer::start(self.record_config(process), duration) {
+            Ok(replay) => self.replay = Some(replay),
+            Err(e) => self.replay_error = Some(e.to_string()),
+        }
+    }
+
+    /// (Re)start watching the clipboard for sentences according to the config
+    fn restart_text_hook(&mut self) {
+        self.text_hook = None;
+        self.text_hook_error = None;
+        if !self.config.text_hook.enabled {
+            return;
+        }
+        let poll_interval = Duration::from_millis(self.config.text_hook.poll_ms as u64);
+        match TextHook::start(poll_interval) {
+            Ok(hook) => self.text_hook = Some(hook),
+            Err(e) => self.text_hook_error = Some(format!("Failed to watch the clipboard: {}", e)),
+        }
+    }
+
+    fn apply_hotkeys(&mut self) {
+        self.hotkey_errors.clear();
+        for (msg, e) in GHK.rebind_all(&self.config.hotkeys) {
+            self.hotkey_errors.insert(msg, e.to_string());
+        }
+    }
+
+    fn is_recording(&self) -> bool {
+        self.record_session.is_some() || self.auto_recorder.is_some()
+    }
+}
+
+/// Stop and save records on another thread, encoding them can take a while
+fn save_in_background(
+    kind: SaveKind,
+    save: impl FnOnce() -> Vec<Result<RecordedData, record::RecordError>> + Send + 'static,
+) -> Task<Message> {
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let records = save().into_iter().map(|r| r.map_err(|e| e.to_string()));
+        let _ = sender.send(records.collect());
+    });
+    Task::perform(receiver, move |res| {
+        let records = res.unwrap_or_else(|_| vec![Err("Saving stopped unexpectedly".to_string())]);
+        Message::Saved(kind, records)
+    })
+}
+
+/// Create the output folder if needed, returning why it couldn't be
+fn ensure_output_dir(config: &Config) -> Result<(), String> {
+    let output_dir = &config.output_dir;
+    if output_dir.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(output_dir).map_err(|e| {
+        format!(
+            "Failed to create output folder {}: {}",
+            output_dir.display(),
+            e
+        )
+    })
 }
 
 fn main() -> iced::Result {
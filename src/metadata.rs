@@ -0,0 +1,93 @@
+use std::{io::Write, path::Path};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::encode::AudioFormat;
+
+/// How a record was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    #[default]
+    Manual,
+    Replay,
+    Auto,
+}
+
+/// Context of a record, saved next to its files as `<name>_meta.json`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordMetadata {
+    pub kind: RecordKind,
+    pub audio_format: AudioFormat,
+    /// Application the recorded window belongs to
+    pub app_name: Option<String>,
+    /// Window title when the record was saved
+    pub window_title: Option<String>,
+    /// When the captured audio starts, before trimming
+    pub started_at: DateTime<Local>,
+    /// When the capture ended
+    pub ended_at: DateTime<Local>,
+    /// Length of the captured audio, before trimming
+    pub captured_secs: f64,
+    /// Length of the saved audio
+    pub duration_secs: f64,
+    /// Sentence shown by the game during the record
+    pub sentence: Option<String>,
+    /// Labels set on the history page
+    pub tags: Vec<String>,
+    /// Anki notes the record was attached to
+    pub anki_notes: Vec<u64>,
+}
+
+#[derive(Error, Debug)]
+pub enum MetadataError {
+    #[error("IO error on metadata: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Invalid metadata: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl RecordMetadata {
+    pub fn load(path: &Path) -> Result<RecordMetadata, MetadataError> {
+        let content = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// Replace the tags by those of a comma-separated list, ignoring empty and repeated ones
+    pub fn set_tags(&mut self, list: &str) {
+        self.tags.clear();
+        for tag in list.split(',').map(str::trim) {
+            if !tag.is_empty() && !self.tags.iter().any(|t| t == tag) {
+                self.tags.push(tag.to_string());
+            }
+        }
+    }
+
+    /// Write the metadata through a temporary file, so readers never see it half written.
+    pub fn save(&self, path: &Path) -> Result<(), MetadataError> {
+        let content = serde_json::to_vec_pretty(self)?;
+        let tmp_path = path.with_extension("json.tmp");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_tags_splits_the_list() {
+        let mut metadata = RecordMetadata::default();
+        metadata.set_tags(" kanji, ,grammar,kanji ,");
+        assert_eq!(metadata.tags, ["kanji", "grammar"]);
+        metadata.set_tags("");
+        assert!(metadata.tags.is_empty());
+    }
+}
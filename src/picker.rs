@@ -0,0 +1,423 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use iced::{
+    Alignment::Center,
+    Element,
+    Length::Fill,
+    widget::{Column, Row, button, image, scrollable, sensor, space, text, text_input},
+};
+use xcap::image::{
+    RgbaImage,
+    imageops::{self, FilterType},
+};
+
+use crate::{
+    Message,
+    process::{self, Process, WindowTarget},
+};
+
+/// How often the thumbnails of the shown windows are captured again
+pub const THUMBNAIL_INTERVAL: Duration = Duration::from_secs(2);
+/// Width thumbnails are scaled down to when captured
+const THUMBNAIL_WIDTH: u32 = 192;
+const THUMBNAIL_HEIGHT: f32 = 54.0;
+const ICON_SIZE: f32 = 24.0;
+/// Distance from the visible part of the list at which thumbnails start being captured
+const THUMBNAIL_ANTICIPATION: f32 = 200.0;
+
+/// A listed window, with what's shown of it read once per refresh
+struct Entry {
+    process: Process,
+    pid: Option<u32>,
+    title: String,
+    size: Option<(u32, u32)>,
+    /// Name and executable, matched against the search
+    haystack: String,
+}
+
+/// A recently selected game, with the listed window it matches if it's running
+struct Recent {
+    target: WindowTarget,
+    entry: Option<usize>,
+}
+
+/// Process list of the main page, grouped by process with the recently selected games pinned
+/// on top. Thumbnails are captured in the background while it's open.
+pub struct ProcessPicker {
+    entries: Vec<Entry>,
+    recent: Vec<Recent>,
+    search: String,
+    /// Icon of each window by id
+    icons: HashMap<u32, image::Handle>,
+    /// Latest capture of each window by id
+    thumbnails: Arc<Mutex<HashMap<u32, image::Handle>>>,
+    /// Listed windows, captured by the background thread while they're shown
+    windows: Arc<Mutex<Vec<Process>>>,
+    /// Entries scrolled into view, by window id and whether it's the one of the recent list
+    shown: Arc<Mutex<HashSet<(u32, bool)>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl ProcessPicker {
+    pub fn new(processes: Vec<Process>, recent: &[WindowTarget]) -> Self {
+        let mut picker = Self {
+            entries: Vec::new(),
+            recent: Vec::new(),
+            search: String::new(),
+            icons: HashMap::new(),
+            thumbnails: Arc::default(),
+            windows: Arc::default(),
+            shown: Arc::default(),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        picker.set_processes(processes, recent);
+
+        std::thread::spawn({
+            let thumbnails = picker.thumbnails.clone();
+            let windows = picker.windows.clone();
+            let shown = picker.shown.clone();
+            let stop = picker.stop.clone();
+            move || {
+                let mut next = Instant::now();
+                while !stop.load(Ordering::Relaxed) {
+                    if Instant::now() < next {
+                        std::thread::sleep(Duration::from_millis(50));
+                        continue;
+                    }
+                    next += THUMBNAIL_INTERVAL;
+                    let shown_ids: HashSet<u32> =
+                        shown.lock().unwrap().iter().map(|(id, _)| *id).collect();
+                    let windows: Vec<Process> = windows
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .filter(|p| shown_ids.contains(&p.id()))
+                        .cloned()
+                        .collect();
+                    for process in windows {
+                        if stop.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        // Windows closed since the refresh just keep their last thumbnail
+                        if let Ok(capture) = process.capture_window() {
+                            let thumbnail = thumbnail(&capture);
+                            thumbnails.lock().unwrap().insert(process.id(), thumbnail);
+                        }
+                    }
+                }
+            }
+        });
+
+        picker
+    }
+
+    /// Replace the listed windows, reading their icons and details
+    pub fn set_processes(&mut self, processes: Vec<Process>, recent: &[WindowTarget]) {
+        let is_listed = |id: &u32| processes.iter().any(|p| p.id() == *id);
+        self.icons.retain(|id, _| is_listed(id));
+        self.thumbnails
+            .lock()
+            .unwrap()
+            .retain(|id, _| is_listed(id));
+        self.shown.lock().unwrap().retain(|(id, _)| is_listed(id));
+        let new_ids = processes
+            .iter()
+            .map(Process::id)
+            .filter(|id| !self.icons.contains_key(id));
+        for (id, icon) in process::window_icons(new_ids.collect::<Vec<_>>()) {
+            let (width, height) = icon.dimensions();
+            let handle = image::Handle::from_rgba(width, height, icon.into_raw());
+            self.icons.insert(id, handle);
+        }
+        *self.windows.lock().unwrap() = processes.clone();
+
+        self.entries = processes
+            .into_iter()
+            .map(|process| Entry {
+                pid: process.pid(),
+                title: process
+                    .title()
+                    .filter(|t| !t.is_empty())
+                    .unwrap_or_else(|| process.to_string()),
+                size: process.size(),
+                haystack: format!("{} {}", process, process.exe().unwrap_or_default()),
+                process,
+            })
+            .collect();
+        self.recent = recent
+            .iter()
+            .map(|target| Recent {
+                target: target.clone(),
+                entry: self
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, e)| Some((target.score(&e.process)?, i)))
+                    .max_by_key(|(score, _)| *score)
+                    .map(|(_, i)| i),
+            })
+            .collect();
+    }
+
+    pub fn search(&mut self, search: String) {
+        self.search = search;
+    }
+
+    /// Track whether an entry is scrolled into view, only those have their thumbnail captured
+    pub fn set_shown(&mut self, id: u32, recent: bool, shown: bool) {
+        let mut entries = self.shown.lock().unwrap();
+        if shown {
+            entries.insert((id, recent));
+        } else {
+            entries.remove(&(id, recent));
+        }
+    }
+
+    /// Search score of a text, `None` if it doesn't match. Everything matches an empty search.
+    fn score(&self, haystack: &str) -> Option<u32> {
+        if self.search.trim().is_empty() {
+            return Some(0);
+        }
+        fuzzy_score(&self.search, haystack)
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
+        let mut list = Column::new().spacing(10);
+
+        let recent: Vec<Element<Message>> = self
+            .recent
+            .iter()
+            .filter_map(|r| match r.entry {
+                Some(i) => {
+                    let entry = &self.entries[i];
+                    self.score(&entry.haystack)?;
+                    Some(self.entry_view(entry, true))
+                }
+                None => {
+                    let label = recent_label(&r.target);
+                    self.score(&label)?;
+                    Some(
+                        Row::new()
+                            .push(space().width(ICON_SIZE))
+                            .push(text(label).size(14).style(text::secondary))
+                            .push(text("Not running").size(12).style(text::secondary))
+                            .align_y(Center)
+                            .spacing(10)
+                            .into(),
+                    )
+                }
+            })
+            .collect();
+        let has_recent = !recent.is_empty();
+        if has_recent {
+            list = list
+                .push(text("Recent").size(16))
+                .push(Column::with_children(recent).spacing(5));
+        }
+
+        // Windows of the same process together, in stacking order or by best match
+        let mut groups: Vec<(u32, Vec<&Entry>)> = Vec::new();
+        for entry in &self.entries {
+            let Some(score) = self.score(&entry.haystack) else {
+                continue;
+            };
+            let same_process = |(_, g): &&mut (u32, Vec<&Entry>)| {
+                let first = g[0];
+                first.pid == entry.pid && first.process.game() == entry.process.game()
+            };
+            match groups.iter_mut().find(same_process) {
+                Some((best, group)) => {
+                    *best = (*best).max(score);
+                    group.push(entry);
+                }
+                None => groups.push((score, vec![entry])),
+            }
+        }
+        groups.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+        if has_recent && !groups.is_empty() {
+            list = list.push(text("All windows").size(16));
+        }
+        for (_, group) in &groups {
+            list = list.push(self.group_view(group));
+        }
+        if self.entries.is_empty() {
+            list = list.push(text("No windows found").size(14).style(text::secondary));
+        } else if groups.is_empty() {
+            list = list.push(text("No matching windows").size(14).style(text::secondary));
+        }
+
+        Column::new()
+            .push(
+                Row::new()
+                    .push(
+                        text_input("Search windows by game, title or executable", &self.search)
+                            .on_input(Message::SearchProcesses)
+                            .size(14),
+                    )
+                    .push(button("Refresh").on_press(Message::RefreshProcessesList))
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .push(scrollable(list).height(Fill))
+            .spacing(10)
+            .into()
+    }
+
+    /// Header naming the process, followed by its windows
+    fn group_view<'a>(&'a self, group: &[&'a Entry]) -> Element<'a, Message> {
+        let first = group[0];
+        let mut details = Vec::new();
+        details.extend(first.process.exe().map(str::to_string));
+        details.extend(first.pid.map(|pid| format!("PID {}", pid)));
+
+        let header = Row::new()
+            .push(self.icon(first))
+            .push(text(first.process.game()).size(16))
+            .push(text(details.join(" · ")).size(12).style(text::secondary))
+            .align_y(Center)
+            .spacing(10);
+        group
+            .iter()
+            .fold(Column::new().push(header), |column, entry| {
+                column.push(self.entry_view(entry, false))
+            })
+            .spacing(5)
+            .into()
+    }
+
+    /// Button selecting a window, with its thumbnail and the icon of its process if asked
+    fn entry_view<'a>(&'a self, entry: &'a Entry, with_icon: bool) -> Element<'a, Message> {
+        let thumbnail: Element<Message> =
+            match self.thumbnails.lock().unwrap().get(&entry.process.id()) {
+                Some(handle) => image(handle.clone()).height(THUMBNAIL_HEIGHT).into(),
+                None => space()
+                    .width(THUMBNAIL_HEIGHT * 16.0 / 9.0)
+                    .height(THUMBNAIL_HEIGHT)
+                    .into(),
+            };
+        let size = entry
+            .size
+            .map(|(width, height)| format!("{}x{}", width, height));
+        let mut row = Row::new();
+        if with_icon {
+            row = row.push(self.icon(entry));
+        }
+        let row = row
+            .push(thumbnail)
+            .push(
+                Column::new()
+                    .push(text(&entry.title).size(14))
+                    .push(size.map(|s| text(s).size(12).style(text::secondary)))
+                    .spacing(2),
+            )
+            .align_y(Center)
+            .spacing(10);
+
+        // Recent entries have the icon, and are tracked apart from the same window's other entry
+        let id = entry.process.id();
+        let button = button(row)
+            .on_press(Message::ProcessSelected(entry.process.clone()))
+            .style(button::text)
+            .width(Fill);
+        sensor(button)
+            .key(id)
+            .anticipate(THUMBNAIL_ANTICIPATION)
+            .on_show(move |_| Message::ProcessShown(id, with_icon, true))
+            .on_hide(Message::ProcessShown(id, with_icon, false))
+            .into()
+    }
+
+    fn icon(&self, entry: &Entry) -> Element<'_, Message> {
+        match self.icons.get(&entry.process.id()) {
+            Some(handle) => image(handle.clone())
+                .width(ICON_SIZE)
+                .height(ICON_SIZE)
+                .into(),
+            None => space().width(ICON_SIZE).height(ICON_SIZE).into(),
+        }
+    }
+}
+
+impl Drop for ProcessPicker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn thumbnail(capture: &RgbaImage) -> image::Handle {
+    let (width, height) = capture.dimensions();
+    let scale = (THUMBNAIL_WIDTH as f64 / width.max(1) as f64).min(1.0);
+    let height = ((height as f64 * scale) as u32).max(1);
+    let width = ((width as f64 * scale) as u32).max(1);
+    let thumbnail = imageops::resize(capture, width, height, FilterType::Triangle);
+    image::Handle::from_rgba(width, height, thumbnail.into_raw())
+}
+
+/// How a recent game that isn't running is shown
+fn recent_label(target: &WindowTarget) -> String {
+    target
+        .exe
+        .clone()
+        .or_else(|| target.title.clone())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+/// Score of the query's characters found in order in a text, case-insensitively, `None` if
+/// some aren't. Consecutive characters and ones starting a word score higher.
+fn fuzzy_score(query: &str, haystack: &str) -> Option<u32> {
+    let haystack: Vec<char> = haystack.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut from = 0;
+    let mut last: Option<usize> = None;
+    for c in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let i = from + haystack[from..].iter().position(|&h| h == c)?;
+        score += 1;
+        if last.is_some_and(|last| last + 1 == i) {
+            score += 2;
+        }
+        if i == 0 || !haystack[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        last = Some(i);
+        from = i + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_needs_characters_in_order() {
+        assert!(fuzzy_score("abc", "a big cat").is_some());
+        assert_eq!(fuzzy_score("cba", "a big cat"), None);
+        assert_eq!(fuzzy_score("z", "a big cat"), None);
+        // Case and spaces in the query don't matter
+        assert_eq!(
+            fuzzy_score("Big Cat", "a big cat"),
+            fuzzy_score("bigcat", "a big cat")
+        );
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_words_and_runs() {
+        let word_start = fuzzy_score("sg", "steins gate").unwrap();
+        let inside = fuzzy_score("sg", "asgard").unwrap();
+        assert!(word_start > inside);
+
+        let run = fuzzy_score("gate", "steins gate").unwrap();
+        let scattered = fuzzy_score("gate", "great aster").unwrap();
+        assert!(run > scattered);
+
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+    }
+}
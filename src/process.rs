@@ -1,19 +1,168 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
+use serde::{Deserialize, Serialize};
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt as _};
 use xcap::{XCapResult, image::RgbaImage};
 
 type ProcessID = u32;
 
+/// Smaller windows are tooltips, menus or helpers, not something to record
+const MIN_WINDOW_SIZE: u32 = 32;
+/// Preferred size of the icons in `_NET_WM_ICON`, which usually holds several
+const ICON_SIZE: u32 = 32;
+/// How long to wait before looking for a closed game's window again, doubling up to the max
+const MIN_REATTACH_DELAY: Duration = Duration::from_secs(1);
+const MAX_REATTACH_DELAY: Duration = Duration::from_secs(8);
+
+/// What identifies a game's window when it's recreated (fullscreen toggle, Proton restart) or
+/// the app is restarted
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowTarget {
+    /// Only identifies the game while it keeps running, so it isn't saved: after a restart it may
+    /// belong to another program
+    #[serde(skip)]
+    pub pid: Option<u32>,
+    /// Executable file name, like `Game.exe` for Wine/Proton games
+    pub exe: Option<String>,
+    /// Matched case-insensitively as a part of the title, which some games change between scenes
+    pub title: Option<String>,
+}
+
+impl WindowTarget {
+    /// How well a window matches, `None` if it belongs to another program
+    pub fn score(&self, process: &Process) -> Option<u32> {
+        let (pid, exe) = (process.pid(), process.exe.as_deref());
+        if let (Some(target), Some(exe)) = (&self.exe, exe)
+            && target != exe
+        {
+            return None;
+        }
+        let same_pid = self.pid.is_some() && self.pid == pid;
+        let same_exe = self.exe.is_some() && self.exe.as_deref() == exe;
+        if !same_pid && !same_exe {
+            return None;
+        }
+        let same_title = match (&self.title, process.title()) {
+            (Some(target), Some(title)) => title.to_lowercase().contains(&target.to_lowercase()),
+            _ => false,
+        };
+        Some(4 * same_pid as u32 + 2 * same_exe as u32 + same_title as u32)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Process {
     id: ProcessID,
     name: String,
-    window: xcap::Window,
+    app_name: Option<String>,
+    exe: Option<String>,
+    /// Current window and PID, shared by the clones so a reattachment updates all of them
+    window: Arc<Mutex<(xcap::Window, Option<u32>)>>,
+    /// Limits how often every window is listed while the game is closed
+    reattach_backoff: Arc<Mutex<Backoff>>,
 }
 
 impl Process {
+    /// Capture the window, reattaching to the game's new window if it was recreated
     pub fn capture_image(&self) -> XCapResult<RgbaImage> {
-        self.window.capture_image()
+        self.capture_window().or_else(|e| {
+            let Some(window) = self.reattach() else {
+                return Err(e);
+            };
+            window.capture_image()
+        })
+    }
+
+    /// Capture the window without looking for another one if it's gone
+    pub fn capture_window(&self) -> XCapResult<RgbaImage> {
+        let window = self.window.lock().unwrap().0.clone();
+        window.capture_image()
+    }
+
+    /// Find the game's window again and track it, returning it if found
+    fn reattach(&self) -> Option<xcap::Window> {
+        let found = find_window(&self.target())?;
+        let (window, pid) = found.window.lock().unwrap().clone();
+        if window.id().ok() == self.window.lock().unwrap().0.id().ok() {
+            return None;
+        }
+        eprintln!("Reattached to the window of {}", self.name);
+        *self.window.lock().unwrap() = (window.clone(), pid);
+        Some(window)
+    }
+
+    /// PID of the game, looking for its window again if the process exited, as when the game was
+    /// restarted, so the audio follows the new process
+    pub fn live_pid(&self) -> Option<u32> {
+        match self.pid() {
+            Some(pid) if is_running(pid) => Some(pid),
+            _ => {
+                let mut backoff = self.reattach_backoff.lock().unwrap();
+                let now = Instant::now();
+                if !backoff.ready(now) {
+                    return None;
+                }
+                self.reattach();
+                let pid = self.pid().filter(|&pid| is_running(pid));
+                match pid {
+                    Some(_) => backoff.reset(),
+                    None => backoff.failed(now),
+                }
+                pid
+            }
+        }
+    }
+
+    /// What to look for to find this window again
+    pub fn target(&self) -> WindowTarget {
+        WindowTarget {
+            // The PID of an exited process may be reused by another one
+            pid: self.pid().filter(|&pid| is_running(pid)),
+            exe: self.exe.clone(),
+            title: self.title(),
+        }
+    }
+
+    /// X11 id of the window when the process was listed
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// PID of the process owning the window, if known
+    pub fn pid(&self) -> Option<u32> {
+        self.window.lock().unwrap().1
+    }
+
+    pub fn app_name(&self) -> Option<&str> {
+        self.app_name.as_deref()
+    }
+
+    pub fn exe(&self) -> Option<&str> {
+        self.exe.as_deref()
+    }
+
+    /// Name identifying the game across sessions, unlike the window which changes every launch
+    pub fn game(&self) -> &str {
+        self.app_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Current title of the window, which some games change between scenes
+    pub fn title(&self) -> Option<String> {
+        self.window.lock().unwrap().0.title().ok()
+    }
+
+    /// Current size of the window
+    pub fn size(&self) -> Option<(u32, u32)> {
+        let window = &self.window.lock().unwrap().0;
+        Some((window.width().ok()?, window.height().ok()?))
     }
 }
 
@@ -29,23 +178,286 @@ impl Display for Process {
     }
 }
 
+/// Windows that can be recorded, frontmost first. Minimized, tiny and own windows are left out.
 pub fn processes() -> Result<Vec<Process>, String> {
     let windows = xcap::Window::all().map_err(|e| e.to_string())?;
-    let processes = windows.into_iter().map(process_from_window).collect();
+    let processes = windows
+        .into_iter()
+        .filter(is_recordable)
+        .filter_map(process_from_window)
+        .collect();
     Ok(processes)
 }
 
-fn process_from_window(window: xcap::Window) -> Process {
-    let id = window.id().unwrap();
+fn is_recordable(window: &xcap::Window) -> bool {
+    let visible = !window.is_minimized().unwrap_or(false)
+        && window.width().unwrap_or(0) >= MIN_WINDOW_SIZE
+        && window.height().unwrap_or(0) >= MIN_WINDOW_SIZE;
+    visible && window.pid().ok() != Some(std::process::id())
+}
+
+fn process_from_window(window: xcap::Window) -> Option<Process> {
+    // A window without an id can't be told apart from the others, so it's skipped
+    let id = window.id().ok()?;
+    let pid = window.pid().ok().filter(|pid| *pid != 0);
+
+    let title = window.title().ok();
+    let app_name = window.app_name().ok();
+    let name = match (&title, &app_name) {
+        (Some(title), Some(app_name)) => format!("{} - {}", app_name, title),
+        (Some(title), None) => title.clone(),
+        (None, Some(app_name)) => app_name.clone(),
+        (None, None) => "Unknown".to_string(),
+    };
+
+    Some(Process {
+        id,
+        name,
+        app_name,
+        exe: pid.and_then(exe_name),
+        window: Arc::new(Mutex::new((window, pid))),
+        reattach_backoff: Default::default(),
+    })
+}
+
+/// Delay between attempts which doubles after each failure
+#[derive(Debug)]
+struct Backoff {
+    delay: Duration,
+    next: Option<Instant>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            delay: MIN_REATTACH_DELAY,
+            next: None,
+        }
+    }
+}
+
+impl Backoff {
+    fn ready(&self, now: Instant) -> bool {
+        self.next.is_none_or(|next| now >= next)
+    }
+
+    fn failed(&mut self, now: Instant) {
+        self.next = Some(now + self.delay);
+        self.delay = (self.delay * 2).min(MAX_REATTACH_DELAY);
+    }
+
+    fn reset(&mut self) {
+        *self = Backoff::default();
+    }
+}
+
+/// The window best matching a target, if the game is running
+pub fn find_window(target: &WindowTarget) -> Option<Process> {
+    let windows = xcap::Window::all().ok()?;
+    windows
+        .into_iter()
+        .filter(is_recordable)
+        .filter_map(process_from_window)
+        .filter_map(|p| Some((target.score(&p)?, p)))
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, p)| p)
+}
 
-    let title = window.title();
-    let app_name = window.app_name();
-    let name = match (title, app_name) {
-        (Ok(title), Ok(app_name)) => format!("{} - {}", app_name, title),
-        (Ok(title), Err(_)) => title,
-        (Err(_), Ok(app_name)) => app_name,
-        (Err(_), Err(_)) => "Unknown".to_string(),
+/// Icons of windows from their `_NET_WM_ICON` property, for the windows that have one
+pub fn window_icons(ids: impl IntoIterator<Item = u32>) -> HashMap<u32, RgbaImage> {
+    let mut icons = HashMap::new();
+    let Ok((conn, _)) = x11rb::connect(None) else {
+        return icons;
     };
+    let Some(atom) = conn
+        .intern_atom(true, b"_NET_WM_ICON")
+        .ok()
+        .and_then(|c| c.reply().ok())
+        .map(|r| r.atom)
+    else {
+        return icons;
+    };
+    for id in ids {
+        let reply = conn
+            .get_property(false, id, atom, AtomEnum::CARDINAL, 0, u32::MAX / 4)
+            .ok()
+            .and_then(|c| c.reply().ok());
+        let Some(data) = reply.as_ref().and_then(|r| r.value32()) else {
+            continue;
+        };
+        if let Some(icon) = best_icon(&data.collect::<Vec<_>>()) {
+            icons.insert(id, icon);
+        }
+    }
+    icons
+}
+
+/// The icon closest to `ICON_SIZE` among the width, height and ARGB pixels packed one after
+/// the other
+fn best_icon(mut data: &[u32]) -> Option<RgbaImage> {
+    let mut best: Option<(u32, u32, &[u32])> = None;
+    while let [width, height, rest @ ..] = data {
+        let len = *width as usize * *height as usize;
+        if len == 0 || rest.len() < len {
+            break;
+        }
+        let (pixels, next) = rest.split_at(len);
+        // The smallest icon at least as large as wanted, otherwise the largest
+        let better = best.is_none_or(|(w, _, _)| {
+            (w < ICON_SIZE && *width > w) || (*width >= ICON_SIZE && *width < w)
+        });
+        if better {
+            best = Some((*width, *height, pixels));
+        }
+        data = next;
+    }
+    let (width, height, pixels) = best?;
+    let raw = pixels
+        .iter()
+        .flat_map(|p| {
+            let [b, g, r, a] = p.to_le_bytes();
+            [r, g, b, a]
+        })
+        .collect();
+    RgbaImage::from_raw(width, height, raw)
+}
+
+/// File name of the program a process runs, Wine/Proton games show their Windows path there
+fn exe_name(pid: u32) -> Option<String> {
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let program = cmdline.split(|b| *b == 0).next()?;
+    let program = String::from_utf8_lossy(program);
+    let name = program.rsplit(['/', '\\']).next()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Look up an executable in `PATH`
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| {
+            candidate
+                .metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+fn is_running(pid: u32) -> bool {
+    Path::new(&format!("/proc/{}", pid)).exists()
+}
 
-    Process { id, name, window }
+/// Whether `pid` is `ancestor` or one of its descendants (e.g. Wine/Proton subprocesses). Only
+/// the parents of `pid` are read, rather than scanning every process.
+pub fn is_in_tree(pid: u32, ancestor: u32) -> bool {
+    let mut current = pid;
+    loop {
+        if current == ancestor {
+            return true;
+        }
+        match parent_pid(current) {
+            Some(parent) if parent != 0 && parent != current => current = parent,
+            _ => return false,
+        }
+    }
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces and parentheses, so parse after its closing parenthesis
+    let (_, rest) = stat.rsplit_once(')')?;
+    // Fields after the name: state, ppid, ...
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn child_processes_are_in_tree() {
+        let own = std::process::id();
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let in_tree = is_in_tree(child.id(), own);
+        let reversed = is_in_tree(own, child.id());
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(in_tree);
+        assert!(!reversed);
+        assert!(is_in_tree(own, own));
+        assert!(!is_in_tree(own, u32::MAX));
+    }
+
+    #[test]
+    fn reattach_attempts_back_off() {
+        let start = Instant::now();
+        let mut backoff = Backoff::default();
+        assert!(backoff.ready(start));
+        let mut now = start;
+        let mut delays = Vec::new();
+        for _ in 0..6 {
+            backoff.failed(now);
+            let next = backoff.next.unwrap();
+            assert!(!backoff.ready(next - Duration::from_millis(1)));
+            delays.push((next - now).as_secs());
+            now = next;
+        }
+        assert_eq!(delays, [1, 2, 4, 8, 8, 8]);
+        backoff.reset();
+        assert!(backoff.ready(now));
+    }
+
+    #[test]
+    fn saved_target_has_no_pid() {
+        let target = WindowTarget {
+            pid: Some(1234),
+            exe: Some("Game.exe".to_string()),
+            title: Some("Game".to_string()),
+        };
+        let saved = toml::to_string(&target).unwrap();
+        assert!(!saved.contains("1234"));
+        let loaded: WindowTarget = toml::from_str("pid = 1234\nexe = \"Game.exe\"").unwrap();
+        assert_eq!(loaded.pid, None);
+        assert_eq!(loaded.exe.as_deref(), Some("Game.exe"));
+    }
+
+    /// `_NET_WM_ICON` data of square icons filled with one ARGB color each
+    fn icons(sizes: &[(u32, u32)]) -> Vec<u32> {
+        sizes
+            .iter()
+            .flat_map(|&(size, argb)| {
+                [size, size]
+                    .into_iter()
+                    .chain(std::iter::repeat_n(argb, (size * size) as usize))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn best_icon_is_the_smallest_large_enough() {
+        let data = icons(&[(16, 1), (64, 2), (32, 3), (48, 4)]);
+        let icon = best_icon(&data).unwrap();
+        assert_eq!(icon.dimensions(), (32, 32));
+
+        let data = icons(&[(16, 1), (24, 2)]);
+        assert_eq!(best_icon(&data).unwrap().dimensions(), (24, 24));
+    }
+
+    #[test]
+    fn best_icon_converts_argb() {
+        let icon = best_icon(&icons(&[(1, 0x80112233)])).unwrap();
+        assert_eq!(icon.get_pixel(0, 0).0, [0x11, 0x22, 0x33, 0x80]);
+    }
+
+    #[test]
+    fn best_icon_stops_at_truncated_data() {
+        let mut data = icons(&[(16, 1)]);
+        data.extend([64, 64, 0]);
+        assert_eq!(best_icon(&data).unwrap().dimensions(), (16, 16));
+        assert!(best_icon(&[]).is_none());
+        assert!(best_icon(&[0, 0]).is_none());
+    }
 }
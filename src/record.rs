@@ -1,32 +1,160 @@
-use duct::{Handle, cmd, unix::HandleExt};
+use chrono::{DateTime, Local};
 use thiserror::Error;
-use xcap::{XCapError, image::ImageError};
+use xcap::{XCapError, image::RgbaImage};
 
-use crate::process::Process;
+use crate::{
+    animation::{self, AnimationGrabber},
+    capture::{
+        CaptureError, CaptureTarget, PcmFormat, PcmSource, PcmStream, peak, process_streams,
+        start_capture,
+    },
+    config::{
+        AnimationConfig, AudioConfig, BackgroundConfig, LoudnessConfig, ScreenshotConfig,
+        TrimConfig, VadConfig,
+    },
+    crop, dsp,
+    encode::{AudioFormat, EncodeError, encode},
+    metadata::{MetadataError, RecordKind, RecordMetadata},
+    process::Process,
+    screenshot::{self, FrameGrabber, ScreenshotFormat},
+    vad::{Segment, Segmenter},
+};
 use std::{
-    os::unix::fs::MetadataExt,
+    fmt,
+    fs::OpenOptions,
+    io::ErrorKind,
     path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc,
+    },
+    thread::JoinHandle,
     time::{Duration, SystemTime},
 };
 
+/// Audio kept from before a record, which its background is estimated from
+const PRE_ROLL: Duration = Duration::from_secs(1);
+
 pub struct Recorder {
     config: RecordConfig,
-    audio_path: PathBuf,
-    screenshot_path: PathBuf,
-
-    record_cmd: Handle,
+    stream: PcmStream,
+    pre_roll: Vec<f32>,
+    frames: FrameGrabber,
+    animation: Option<AnimationGrabber>,
+    /// Why the recording doesn't capture what was asked for
+    warning: Option<String>,
 }
 
 #[derive(Clone)]
 pub struct RecordConfig {
     pub process: Process,
     pub output_dir: PathBuf,
+    pub trim: TrimConfig,
+    pub loudness: LoudnessConfig,
+    pub background: BackgroundConfig,
+    pub audio: AudioConfig,
+    pub screenshot: ScreenshotConfig,
+    pub animation: AnimationConfig,
 }
 
+#[derive(Clone)]
 pub struct RecordedData {
     pub audio_path: PathBuf,
+    pub audio_format: AudioFormat,
     pub screenshot_path: PathBuf,
     pub duration: Duration,
+    pub metadata_path: PathBuf,
+    pub metadata: RecordMetadata,
+    /// Burst frames the screenshot may be swapped for, empty when a single one was taken
+    pub frames: Vec<RgbaImage>,
+    pub animation_path: Option<PathBuf>,
+    /// What couldn't be saved as asked, the record being kept anyway
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Debug for RecordedData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The burst frames are too big to be printed
+        f.debug_struct("RecordedData")
+            .field("audio_path", &self.audio_path)
+            .field("screenshot_path", &self.screenshot_path)
+            .field("metadata_path", &self.metadata_path)
+            .field("duration", &self.duration)
+            .field("frames", &self.frames.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RecordedData {
+    /// Screenshot and audio files of the record which exist
+    pub fn files(&self) -> Vec<&PathBuf> {
+        [&self.screenshot_path, &self.audio_path]
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    /// Save another burst frame as the screenshot, in the format the record was saved in
+    pub fn choose_frame(&self, index: usize, config: &ScreenshotConfig) -> Result<(), RecordError> {
+        let Some(frame) = self.frames.get(index) else {
+            return Ok(());
+        };
+        let format = self
+            .screenshot_path
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(ScreenshotFormat::from_extension)
+            .unwrap_or(config.format);
+        let config = ScreenshotConfig {
+            format,
+            ..config.clone()
+        };
+        screenshot::save(frame, &self.screenshot_path, &config)?;
+        Ok(())
+    }
+
+    /// Write the metadata, after changing it
+    pub fn save_metadata(&self) -> Result<(), RecordError> {
+        Ok(self.metadata.save(&self.metadata_path)?)
+    }
+}
+
+/// Filter on record metadata, unset fields match every record
+#[derive(Debug, Clone, Default)]
+pub struct RecordQuery {
+    /// Case-insensitive text searched in the app name, window title, sentence and tags
+    pub text: Option<String>,
+    /// Exact app name
+    pub app_name: Option<String>,
+    pub tag: Option<String>,
+    pub since: Option<DateTime<Local>>,
+    pub until: Option<DateTime<Local>>,
+}
+
+impl RecordQuery {
+    pub fn matches(&self, metadata: &RecordMetadata) -> bool {
+        let text_matches = |text: &str| {
+            let text = text.to_lowercase();
+            [
+                &metadata.app_name,
+                &metadata.window_title,
+                &metadata.sentence,
+            ]
+            .into_iter()
+            .flatten()
+            .chain(&metadata.tags)
+            .any(|field| field.to_lowercase().contains(&text))
+        };
+        self.text.as_deref().is_none_or(text_matches)
+            && self
+                .app_name
+                .as_ref()
+                .is_none_or(|a| metadata.app_name.as_ref() == Some(a))
+            && self.tag.as_ref().is_none_or(|t| metadata.tags.contains(t))
+            && self.since.is_none_or(|since| metadata.ended_at >= since)
+            && self.until.is_none_or(|until| metadata.started_at <= until)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -34,135 +162,716 @@ pub enum RecordError {
     #[error("Failed to capture screenshot: {0}")]
     CaptureScreenshot(#[from] XCapError),
     #[error("Failed to save screenshot: {0}")]
-    SaveScreenshot(#[from] ImageError),
+    SaveScreenshot(#[from] screenshot::SaveError),
+    #[error("Failed to capture audio: {0}")]
+    CaptureAudio(#[from] CaptureError),
+    #[error("`{0}` is not installed, install it or choose another audio format")]
+    MissingTool(&'static str),
+    #[error("Encoder `{tool}` failed: {stderr}")]
+    Encoder { tool: &'static str, stderr: String },
+    #[error("Failed to read record metadata: {0}")]
+    Metadata(#[from] MetadataError),
     #[error("IO error on recording: {0}")]
     IO(#[from] std::io::Error),
 }
 
+impl From<EncodeError> for RecordError {
+    fn from(e: EncodeError) -> Self {
+        match e {
+            EncodeError::MissingTool(tool) => RecordError::MissingTool(tool),
+            EncodeError::Failed { tool, stderr } => RecordError::Encoder { tool, stderr },
+            EncodeError::IO(e) => RecordError::IO(e),
+        }
+    }
+}
+
 impl Recorder {
-    // TODO: refactor
-    pub fn start(config: RecordConfig) -> Recorder {
-        // Generate the file paths
-        let [audio_path, screenshot_path] = {
-            let unix = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            let name_prefix = format!("{}", unix);
-
-            let audio_path = config.output_dir.join(format!("{}_audio.mp3", name_prefix));
-            let screenshot_path = config
-                .output_dir
-                .join(format!("{}_screenshot.png", name_prefix));
-            [audio_path, screenshot_path]
-        };
+    /// Start recording. `pre_roll` is the audio captured just before, if any, such as the end of
+    /// the replay buffer.
+    pub fn start(config: RecordConfig, pre_roll: Vec<f32>) -> Result<Recorder, RecordError> {
+        config.audio.format.check_tool()?;
+        if config.animation.enabled {
+            config.animation.format.check_tool()?;
+        }
 
         // Start the audio recording
-        let record_cmd = start_audio_record(&audio_path, None).unwrap();
+        let (target, warning) = capture_target(&config);
+        if let Some(warning) = &warning {
+            eprintln!("{}", warning);
+        }
+        let source = start_capture(&target, &config.audio)?;
+        let frames = FrameGrabber::start(&config.process, &config.screenshot)?;
+        let animation = config.animation.enabled.then(|| {
+            AnimationGrabber::start(&config.process, &config.animation, &config.screenshot)
+        });
 
-        Recorder {
+        Ok(Recorder {
+            stream: PcmStream::start(source, None),
+            pre_roll,
+            frames,
+            animation,
             config,
-            audio_path,
-            screenshot_path,
-            record_cmd,
+            warning,
+        })
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    /// Current audio level (0.0 to 1.0)
+    pub fn level(&self) -> f32 {
+        self.stream.level()
+    }
+
+    /// Stop and save the recording. The audio is saved even if the screenshot can't be taken, as
+    /// when the window has closed, or if the capture failed midway.
+    pub fn stop(self) -> Result<RecordedData, RecordError> {
+        let mut warnings = Vec::new();
+        // Capture the last image before encoding, which may take a moment
+        let (screenshot, frames) = match self.frames.finish(&self.config.process) {
+            Ok((screenshot, frames)) => (Some(screenshot), frames),
+            Err(e) => {
+                warnings.push(format!("No screenshot was saved: {}", e));
+                (None, Vec::new())
+            }
+        };
+        let animation = self.animation.map(|a| a.finish()).unwrap_or_default();
+        let format = self.stream.format();
+        let (samples, res) = self.stream.stop();
+        if let Err(e) = res {
+            if samples.is_empty() {
+                return Err(e.into());
+            }
+            warnings.push(format!("The audio capture stopped early: {}", e));
         }
+        save_record(
+            &self.config,
+            CapturedAudio {
+                samples,
+                pre_roll: self.pre_roll,
+                format,
+            },
+            screenshot,
+            frames,
+            animation,
+            RecordKind::Manual,
+            warnings,
+        )
+    }
+}
+
+/// Continuously buffers the last few seconds of a process's audio, so a voice line can be saved
+/// after it has been played.
+pub struct ReplayBuffer {
+    config: RecordConfig,
+    stream: PcmStream,
+    duration: Duration,
+    warning: Option<String>,
+}
+
+impl ReplayBuffer {
+    pub fn start(config: RecordConfig, duration: Duration) -> Result<ReplayBuffer, RecordError> {
+        config.audio.format.check_tool()?;
+        let (target, warning) = capture_target(&config);
+        if let Some(warning) = &warning {
+            eprintln!("{}", warning);
+        }
+        let source = start_capture(&target, &config.audio)?;
+
+        Ok(ReplayBuffer {
+            // Also keep what's just before the saved audio, to estimate the background from
+            stream: PcmStream::start(source, Some(duration + PRE_ROLL)),
+            duration,
+            config,
+            warning,
+        })
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
     }
 
-    pub fn stop(mut self) -> Result<RecordedData, RecordError> {
-        self.stop_audio()?;
-        let audio_duration = audio_duration(&self.audio_path);
+    /// The most recently captured audio, to be used as the pre-roll of a record starting now
+    pub fn pre_roll(&self) -> Vec<f32> {
+        let mut samples = self.stream.snapshot();
+        let len = self.stream.format().samples_in(PRE_ROLL);
+        samples.drain(..samples.len().saturating_sub(len));
+        samples
+    }
 
-        // Capture and save the last image screenshot
-        let screenshot = self.config.process.capture_image()?;
-        screenshot
-            .save(&self.screenshot_path)
-            .map_err(RecordError::SaveScreenshot)?;
+    /// Take the buffered audio, to be saved with [`Replay::save`]
+    pub fn take(&mut self) -> Result<Replay, RecordError> {
+        self.stream.check()?;
 
-        Ok(RecordedData {
-            audio_path: self.audio_path.clone(),
-            screenshot_path: self.screenshot_path.clone(),
-            duration: audio_duration,
+        let mut samples = self.stream.snapshot();
+        let len = self.stream.format().samples_in(self.duration);
+        let pre_roll = samples.drain(..samples.len().saturating_sub(len)).collect();
+        Ok(Replay {
+            config: self.config.clone(),
+            audio: CapturedAudio {
+                samples,
+                pre_roll,
+                format: self.stream.format(),
+            },
         })
     }
+}
+
+/// Audio taken from a [`ReplayBuffer`], not saved yet
+pub struct Replay {
+    config: RecordConfig,
+    audio: CapturedAudio,
+}
 
-    fn stop_audio(&mut self) -> Result<(), RecordError> {
-        // Stop the recording command to end the audio recording
-        if let Some(status) = self.record_cmd.try_wait()? {
-            eprintln!(
-                "Recording stopped before the recording should have been stopped: {:?}",
-                status
-            );
-        }
-
-        const SIGTERM: i32 = 15;
-        self.record_cmd.send_signal(SIGTERM)?;
-        self.record_cmd.wait()?;
-
-        let audio_duration = audio_duration(&self.audio_path);
-        println!("Captured {:?} of audio (before trim)", audio_duration);
-
-        // Trim the silence from the beginning and end of the audio
-        let tmp_trimmed_audio_path = self.audio_path.with_extension("tmp.mp3");
-        let res = cmd!(
-            "sox",
-            &self.audio_path,
-            &tmp_trimmed_audio_path,
-            "silence",
-            "1",
-            "0.1",
-            "1%",
-            "reverse",
-            "silence",
-            "1",
-            "0.1",
-            "1%",
-            "reverse"
+impl Replay {
+    /// Save the audio along with a screenshot of the process
+    pub fn save(self) -> Result<RecordedData, RecordError> {
+        let mut warnings = Vec::new();
+        let screenshot = screenshot_or_warning(&self.config.process, &mut warnings);
+        save_record(
+            &self.config,
+            self.audio,
+            screenshot,
+            Vec::new(),
+            Vec::new(),
+            RecordKind::Replay,
+            warnings,
         )
-        .run();
-        if let Err(e) = res {
-            eprintln!("sox failed: {:?}", e);
+    }
+}
+
+/// Records every voice line of a process automatically, detecting where each one starts and
+/// ends from the captured audio.
+pub struct AutoRecorder {
+    records: mpsc::Receiver<Result<RecordedData, RecordError>>,
+    stop: Arc<AtomicBool>,
+    level: Arc<AtomicU32>,
+    thread: Option<JoinHandle<Result<(), CaptureError>>>,
+    /// Saves the voice lines, so encoding them doesn't hold up the capture
+    saver: Option<JoinHandle<()>>,
+    warning: Option<String>,
+}
+
+impl AutoRecorder {
+    pub fn start(config: RecordConfig, vad: VadConfig) -> Result<AutoRecorder, RecordError> {
+        config.audio.format.check_tool()?;
+        let (target, warning) = capture_target(&config);
+        if let Some(warning) = &warning {
+            eprintln!("{}", warning);
         }
+        let mut source = start_capture(&target, &config.audio)?;
 
-        std::fs::rename(&tmp_trimmed_audio_path, &self.audio_path)?;
+        let (sender, records) = mpsc::channel();
+        let (segments, to_save) = mpsc::channel::<(Segment, Option<RgbaImage>, Vec<String>)>();
+        let format = source.format();
+        let saver = std::thread::spawn({
+            let config = config.clone();
+            move || {
+                for (segment, screenshot, warnings) in to_save {
+                    let res = save_record(
+                        &config,
+                        CapturedAudio {
+                            pre_roll: segment.samples[..segment.pre_roll].to_vec(),
+                            samples: segment.samples,
+                            format,
+                        },
+                        screenshot,
+                        Vec::new(),
+                        Vec::new(),
+                        RecordKind::Auto,
+                        warnings,
+                    );
+                    if sender.send(res).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
 
-        Ok(())
+        let stop = Arc::new(AtomicBool::new(false));
+        let level = Arc::new(AtomicU32::new(0));
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            let level = level.clone();
+            move || {
+                let mut segmenter = Segmenter::new(format, &vad);
+                // The screenshot is taken right away so it shows the end of the line, even when
+                // the previous one is still being saved
+                let send = |segment: Segment| {
+                    let mut warnings = Vec::new();
+                    let screenshot = screenshot_or_warning(&config.process, &mut warnings);
+                    segments.send((segment, screenshot, warnings)).is_ok()
+                };
+
+                while !stop.load(Ordering::Relaxed) {
+                    let Some(chunk) = source.read(Duration::from_millis(100))? else {
+                        level.store(0f32.to_bits(), Ordering::Relaxed);
+                        continue;
+                    };
+                    level.store(peak(&chunk).to_bits(), Ordering::Relaxed);
+
+                    for utterance in segmenter.push(&chunk) {
+                        if !send(utterance) {
+                            return Ok(());
+                        }
+                    }
+                }
+                if let Some(utterance) = segmenter.flush() {
+                    send(utterance);
+                }
+                Ok(())
+            }
+        });
+
+        Ok(AutoRecorder {
+            records,
+            stop,
+            level,
+            thread: Some(thread),
+            saver: Some(saver),
+            warning,
+        })
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    /// Peak level (0.0 to 1.0) of the most recently captured audio
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Records of the voice lines which ended since the last call
+    pub fn poll(&mut self) -> Vec<Result<RecordedData, RecordError>> {
+        let mut records: Vec<_> = self.records.try_iter().collect();
+        if let Some(thread) = self.thread.take_if(|thread| thread.is_finished()) {
+            match thread.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => records.push(Err(e.into())),
+                Err(_) => records.push(Err(CaptureError::Panicked.into())),
+            }
+        }
+        records
+    }
+
+    /// Whether the capture is still running
+    pub fn is_running(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Stop listening, saving the voice line in progress if there is one
+    pub fn stop(mut self) -> Vec<Result<RecordedData, RecordError>> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            match thread.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Auto recording failed: {}", e),
+                Err(_) => eprintln!("Auto recording thread panicked"),
+            }
+        }
+        // The capture thread is done, so the saver ends once the last lines are saved
+        if let Some(saver) = self.saver.take()
+            && saver.join().is_err()
+        {
+            eprintln!("Auto recording saver thread panicked");
+        }
+        self.records.try_iter().collect()
     }
 }
 
-impl Drop for Recorder {
+impl Drop for AutoRecorder {
     fn drop(&mut self) {
-        self.record_cmd.kill().unwrap();
+        self.stop.store(true, Ordering::Relaxed);
     }
 }
 
-fn audio_duration(audio_path: &Path) -> Duration {
-    // Hack to avoid processing empty audio files
-    if std::fs::metadata(audio_path).unwrap().size() < 500 {
-        return Duration::ZERO;
-    }
+/// Audio captured for a record
+struct CapturedAudio {
+    samples: Vec<f32>,
+    /// Audio captured just before, which the background is estimated from
+    pre_roll: Vec<f32>,
+    format: PcmFormat,
+}
 
-    match cmd!("soxi", "-D", audio_path).read() {
-        Ok(duration_str) => {
-            let duration = duration_str.trim().parse::<f64>().unwrap();
-            Duration::from_secs_f64(duration)
+/// Reserve a name for a record made at `unix` time, by creating its empty metadata file. Records
+/// saved at the same time from several threads then don't overwrite each other, whatever formats
+/// they end up saved in.
+fn reserve_name(output_dir: &Path, unix: u64) -> std::io::Result<String> {
+    // Several records can be made within a second in auto mode
+    let mut name_prefix = format!("{}", unix);
+    let mut n = 1;
+    loop {
+        let path = output_dir.join(format!("{}_meta.json", name_prefix));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(name_prefix),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                name_prefix = format!("{}-{}", unix, n);
+                n += 1;
+            }
+            Err(e) => return Err(e),
         }
+    }
+}
+
+/// Generate the audio, screenshot, metadata and animation paths for a new record, reserving its
+/// name
+fn record_paths(config: &RecordConfig) -> std::io::Result<[PathBuf; 4]> {
+    let output_dir = &config.output_dir;
+    let unix = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let name_prefix = reserve_name(output_dir, unix)?;
+
+    let metadata_path = output_dir.join(format!("{}_meta.json", name_prefix));
+
+    let audio_path = output_dir.join(format!(
+        "{}_audio.{}",
+        name_prefix,
+        config.audio.format.extension()
+    ));
+    let screenshot_path = output_dir.join(format!(
+        "{}_screenshot.{}",
+        name_prefix,
+        config.screenshot.format.extension()
+    ));
+    let animation_path = output_dir.join(format!(
+        "{}_animation.{}",
+        name_prefix,
+        config.animation.format.extension()
+    ));
+    Ok([audio_path, screenshot_path, metadata_path, animation_path])
+}
+
+/// Encode the audio in the configured format, or as WAV if that fails. Returns the format it
+/// was saved in.
+fn encode_audio(
+    samples: &[f32],
+    format: PcmFormat,
+    audio_path: &mut PathBuf,
+    config: &RecordConfig,
+    warnings: &mut Vec<String>,
+) -> Result<AudioFormat, RecordError> {
+    let audio_format = config.audio.format;
+    let Err(e) = encode(samples, format, audio_path, &config.audio) else {
+        return Ok(audio_format);
+    };
+    if audio_format == AudioFormat::Wav {
+        return Err(e.into());
+    }
+    // Keep the audio rather than losing the whole record to a broken encoder
+    let _ = std::fs::remove_file(&*audio_path);
+    audio_path.set_extension(AudioFormat::Wav.extension());
+    let wav = AudioConfig {
+        format: AudioFormat::Wav,
+        ..config.audio.clone()
+    };
+    encode(samples, format, audio_path, &wav)?;
+    warnings.push(format!(
+        "Saved the audio as WAV, encoding it as {} failed: {}",
+        audio_format,
+        RecordError::from(e)
+    ));
+    Ok(AudioFormat::Wav)
+}
+
+/// Capture a screenshot of the process, noting why in `warnings` if it can't be taken
+fn screenshot_or_warning(process: &Process, warnings: &mut Vec<String>) -> Option<RgbaImage> {
+    process
+        .capture_image()
+        .inspect_err(|e| warnings.push(format!("No screenshot was saved: {}", e)))
+        .ok()
+}
+
+/// Process and encode the captured audio, then save it along with the screenshot and animation.
+/// Earlier burst frames are kept with the record so the screenshot can be swapped for one of
+/// them.
+///
+/// The audio is saved first and is all that's needed for the record to be kept: it's saved as
+/// WAV if the configured encoder fails, and anything else failing is added to the `warnings`.
+fn save_record(
+    config: &RecordConfig,
+    audio: CapturedAudio,
+    screenshot: Option<RgbaImage>,
+    earlier_frames: Vec<RgbaImage>,
+    animation_frames: Vec<RgbaImage>,
+    kind: RecordKind,
+    mut warnings: Vec<String>,
+) -> Result<RecordedData, RecordError> {
+    let ended_at = Local::now();
+    let [
+        mut audio_path,
+        mut screenshot_path,
+        metadata_path,
+        animation_path,
+    ] = record_paths(config)?;
+
+    let CapturedAudio {
+        mut samples,
+        pre_roll,
+        format,
+    } = audio;
+    let captured = duration_of(&samples, format);
+    println!("Captured {:?} of audio (before trim)", captured);
+    dsp::process(
+        &mut samples,
+        &pre_roll,
+        format,
+        &config.background,
+        &config.trim,
+        &config.loudness,
+    );
+    let audio_format = match encode_audio(&samples, format, &mut audio_path, config, &mut warnings)
+    {
+        Ok(audio_format) => audio_format,
         Err(e) => {
-            panic!("soxi failed: {:?}", e);
+            // Free the reserved name, there's no record
+            let _ = std::fs::remove_file(&metadata_path);
+            return Err(e);
+        }
+    };
+
+    let game = config.process.game();
+    let mut frames: Vec<RgbaImage> = Vec::new();
+    if let Some(screenshot) = screenshot {
+        let screenshot = crop::apply(screenshot, game, &config.screenshot);
+        let res = screenshot::save(&screenshot, &screenshot_path, &config.screenshot);
+        match res {
+            Err(e) if config.screenshot.format != ScreenshotFormat::Png => {
+                // Like the audio, fall back to a format saved without external tools
+                let _ = std::fs::remove_file(&screenshot_path);
+                screenshot_path.set_extension(ScreenshotFormat::Png.extension());
+                let png = ScreenshotConfig {
+                    format: ScreenshotFormat::Png,
+                    ..config.screenshot.clone()
+                };
+                warnings.push(
+                    match screenshot::save(&screenshot, &screenshot_path, &png) {
+                        Ok(()) => format!(
+                            "Saved the screenshot as PNG, encoding it as {} failed: {}",
+                            config.screenshot.format, e
+                        ),
+                        Err(e) => format!("Failed to save screenshot: {}", e),
+                    },
+                );
+            }
+            Err(e) => warnings.push(format!("Failed to save screenshot: {}", e)),
+            Ok(()) => {}
+        }
+        if !earlier_frames.is_empty() {
+            frames = earlier_frames
+                .into_iter()
+                .map(|frame| crop::apply(frame, game, &config.screenshot))
+                .collect();
+            frames.push(screenshot);
         }
     }
+    // Like the metadata, a failed animation is only an extra missing
+    let animation_path = (!animation_frames.is_empty())
+        .then(|| animation::encode(animation_frames, &animation_path, &config.animation))
+        .and_then(|res| match res {
+            Ok(()) => Some(animation_path),
+            Err(e) => {
+                warnings.push(format!("Failed to save animation: {}", e));
+                None
+            }
+        });
+
+    let duration = duration_of(&samples, format);
+    let metadata = RecordMetadata {
+        kind,
+        audio_format,
+        app_name: config.process.app_name().map(str::to_string),
+        window_title: config.process.title(),
+        started_at: ended_at - captured,
+        ended_at,
+        captured_secs: captured.as_secs_f64(),
+        duration_secs: duration.as_secs_f64(),
+        sentence: None,
+        tags: Vec::new(),
+        anki_notes: Vec::new(),
+    };
+    // The record itself is saved, so missing metadata isn't worth failing it
+    if let Err(e) = metadata.save(&metadata_path) {
+        let _ = std::fs::remove_file(&metadata_path);
+        warnings.push(format!("Failed to save metadata: {}", e));
+    }
+
+    Ok(RecordedData {
+        audio_path,
+        audio_format,
+        screenshot_path,
+        duration,
+        metadata_path,
+        metadata,
+        frames,
+        animation_path,
+        warnings,
+    })
 }
 
-fn start_audio_record(audio_path: &Path, target_sink: Option<&str>) -> Result<Handle, RecordError> {
-    // based on https://github.com/JayXT/RecordAudioOutput/blob/main/record_audio_output_pw
+fn duration_of(samples: &[f32], format: PcmFormat) -> Duration {
+    let frames = samples.len() / format.channels.max(1) as usize;
+    Duration::from_secs_f64(frames as f64 / format.sample_rate as f64)
+}
 
-    let target_sink = target_sink.unwrap_or("auto");
-    let inner_expr = format!(
-        "pw-record --target \"{}\" -P '{{ stream.capture.sink=true }}' - | lame -r -s 48 -m s -V7 - \"{}\"",
-        target_sink,
-        audio_path.display()
-    );
-    // afaik, must wrap in shell context otherwise it won't record the correct audio
-    let expr = cmd("/usr/bin/env", ["bash", "-c", &inner_expr]);
-    let command = expr.unchecked().start()?;
-    Ok(command)
+/// Resolve the audio streams of the recorded process, falling back to all desktop audio with a
+/// warning if they can't be listed.
+fn capture_target(config: &RecordConfig) -> (CaptureTarget, Option<String>) {
+    if !config.audio.per_application {
+        return (CaptureTarget::Desktop, None);
+    }
+
+    let process = &config.process;
+    let Some(pid) = process.pid() else {
+        return (
+            CaptureTarget::Desktop,
+            Some(format!(
+                "PID of {} is unknown, recording all desktop audio",
+                process
+            )),
+        );
+    };
+    match process_streams(pid) {
+        Ok(nodes) if nodes.is_empty() => (
+            CaptureTarget::Process(process.clone()),
+            Some(format!(
+                "{} isn't playing audio yet, it's recorded once it does",
+                process
+            )),
+        ),
+        Ok(_) => (CaptureTarget::Process(process.clone()), None),
+        Err(e) => (
+            CaptureTarget::Desktop,
+            Some(format!(
+                "Failed to find audio stream of {} ({}), recording all desktop audio",
+                process, e
+            )),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(hour: u32, min: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2025, 3, 1, hour, min, 0).unwrap()
+    }
+
+    /// A line recorded from 12:00 to 12:01
+    fn metadata() -> RecordMetadata {
+        RecordMetadata {
+            app_name: Some("Game.exe".to_string()),
+            window_title: Some("Summer Pockets".to_string()),
+            sentence: Some("夏の日差しが眩しい".to_string()),
+            tags: vec!["kanji".to_string(), "chapter1".to_string()],
+            started_at: at(12, 0),
+            ended_at: at(12, 1),
+            ..Default::default()
+        }
+    }
+
+    fn text(text: &str) -> RecordQuery {
+        RecordQuery {
+            text: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert!(RecordQuery::default().matches(&metadata()));
+        assert!(RecordQuery::default().matches(&RecordMetadata::default()));
+    }
+
+    #[test]
+    fn text_is_searched_in_every_field() {
+        let metadata = metadata();
+        for found in ["game.EXE", "pockets", "日差し", "KANJI", "ter1"] {
+            assert!(text(found).matches(&metadata), "{}", found);
+        }
+        assert!(!text("winter").matches(&metadata));
+        assert!(!text("game").matches(&RecordMetadata::default()));
+    }
+
+    #[test]
+    fn app_name_and_tag_match_exactly() {
+        let metadata = metadata();
+        let app = |name: &str| RecordQuery {
+            app_name: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert!(app("Game.exe").matches(&metadata));
+        assert!(!app("game.exe").matches(&metadata));
+        assert!(!app("Game").matches(&metadata));
+
+        let tag = |tag: &str| RecordQuery {
+            tag: Some(tag.to_string()),
+            ..Default::default()
+        };
+        assert!(tag("kanji").matches(&metadata));
+        assert!(!tag("kan").matches(&metadata));
+        assert!(!tag("Kanji").matches(&metadata));
+    }
+
+    #[test]
+    fn time_range_matches_overlapping_records() {
+        let metadata = metadata();
+        let range = |since: Option<DateTime<Local>>, until: Option<DateTime<Local>>| RecordQuery {
+            since,
+            until,
+            ..Default::default()
+        };
+        // Overlapping either edge, containing or inside the record
+        assert!(range(Some(at(11, 0)), Some(at(12, 0))).matches(&metadata));
+        assert!(range(Some(at(12, 1)), Some(at(13, 0))).matches(&metadata));
+        assert!(range(Some(at(11, 0)), Some(at(13, 0))).matches(&metadata));
+        assert!(range(Some(at(12, 0)), None).matches(&metadata));
+        assert!(range(None, Some(at(12, 1))).matches(&metadata));
+        // Entirely before or after
+        assert!(!range(Some(at(12, 2)), None).matches(&metadata));
+        assert!(!range(None, Some(at(11, 59))).matches(&metadata));
+    }
+
+    #[test]
+    fn every_filter_must_match() {
+        let query = RecordQuery {
+            text: Some("pockets".to_string()),
+            tag: Some("kanji".to_string()),
+            app_name: Some("Other.exe".to_string()),
+            ..Default::default()
+        };
+        assert!(!query.matches(&metadata()));
+    }
+
+    #[test]
+    fn reserved_names_are_unique() {
+        let dir = std::env::temp_dir().join(format!("vn_record-names-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // A record of another format already took the first name
+        std::fs::write(dir.join("100_audio.wav"), b"").unwrap();
+        std::fs::write(dir.join("100_meta.json"), b"{}").unwrap();
+
+        let names = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| s.spawn(|| reserve_name(&dir, 100).unwrap()))
+                .collect();
+            let mut names: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+            names.sort();
+            names
+        });
+        let mut unique = names.clone();
+        unique.dedup();
+        assert_eq!(unique, names);
+        assert!(!names.contains(&"100".to_string()));
+        assert_eq!(std::fs::read(dir.join("100_meta.json")).unwrap(), b"{}");
+        assert!(dir.join(format!("{}_meta.json", names[0])).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,378 @@
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fmt::Display,
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use duct::cmd;
+use image::{
+    DynamicImage, ImageError, RgbaImage,
+    codecs::{avif::AvifEncoder, jpeg::JpegEncoder, png::PngEncoder},
+    imageops::{self, FilterType},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use xcap::XCapResult;
+
+use crate::{config::ScreenshotConfig, encode::EncodeError, process::Process};
+
+/// Encoder speed of AVIF, from 1 (slowest, smallest) to 10
+const AVIF_SPEED: u8 = 6;
+
+/// File format screenshots are saved in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenshotFormat {
+    #[default]
+    Png,
+    Jpeg,
+    /// Encoded by ffmpeg, the image crate only writes lossless WebP which is often larger than PNG
+    Webp,
+    Avif,
+}
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+}
+
+impl ScreenshotFormat {
+    pub const ALL: [ScreenshotFormat; 4] = [
+        ScreenshotFormat::Png,
+        ScreenshotFormat::Jpeg,
+        ScreenshotFormat::Webp,
+        ScreenshotFormat::Avif,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ScreenshotFormat::Png => "png",
+            ScreenshotFormat::Jpeg => "jpg",
+            ScreenshotFormat::Webp => "webp",
+            ScreenshotFormat::Avif => "avif",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<ScreenshotFormat> {
+        Self::ALL
+            .into_iter()
+            .find(|f| f.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+impl Display for ScreenshotFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ScreenshotFormat::Png => "PNG",
+            ScreenshotFormat::Jpeg => "JPEG",
+            ScreenshotFormat::Webp => "WebP",
+            ScreenshotFormat::Avif => "AVIF",
+        })
+    }
+}
+
+/// Encode a screenshot in the configured format, scaled down to fit the maximum dimensions
+pub fn save(
+    screenshot: &RgbaImage,
+    path: &Path,
+    config: &ScreenshotConfig,
+) -> Result<(), SaveError> {
+    let (width, height) = screenshot.dimensions();
+    let scale = [
+        config.max_width.map(|w| w as f64 / width as f64),
+        config.max_height.map(|h| h as f64 / height as f64),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(1.0, f64::min);
+    let image = if scale < 1.0 {
+        let width = ((width as f64 * scale).round() as u32).max(1);
+        let height = ((height as f64 * scale).round() as u32).max(1);
+        DynamicImage::ImageRgba8(imageops::resize(
+            screenshot,
+            width,
+            height,
+            FilterType::Lanczos3,
+        ))
+    } else {
+        DynamicImage::ImageRgba8(screenshot.clone())
+    };
+    // Windows are opaque, the alpha channel only takes space. JPEG can't have one at all.
+    let image = if config.strip_alpha || config.format == ScreenshotFormat::Jpeg {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    } else {
+        image
+    };
+
+    if config.format == ScreenshotFormat::Webp {
+        return Ok(encode_webp(&image, path, config.quality)?);
+    }
+    let writer = BufWriter::new(File::create(path).map_err(ImageError::IoError)?);
+    match config.format {
+        ScreenshotFormat::Png => image.write_with_encoder(PngEncoder::new(writer)),
+        ScreenshotFormat::Jpeg => {
+            image.write_with_encoder(JpegEncoder::new_with_quality(writer, config.quality))
+        }
+        ScreenshotFormat::Webp => unreachable!("encoded by ffmpeg"),
+        ScreenshotFormat::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            writer,
+            AVIF_SPEED,
+            config.quality,
+        )),
+    }?;
+    Ok(())
+}
+
+/// Encode a lossy WebP with ffmpeg's libwebp
+fn encode_webp(image: &DynamicImage, path: &Path, quality: u8) -> Result<(), EncodeError> {
+    let (pix_fmt, raw) = match image {
+        DynamicImage::ImageRgb8(image) => ("rgb24", image.as_raw().clone()),
+        image => ("rgba", image.to_rgba8().into_raw()),
+    };
+    let args: Vec<OsString> = [
+        "-hide_banner",
+        "-loglevel",
+        "error",
+        "-y",
+        "-f",
+        "rawvideo",
+        "-pix_fmt",
+        pix_fmt,
+        "-s",
+        &format!("{}x{}", image.width(), image.height()),
+        "-i",
+        "-",
+        "-frames:v",
+        "1",
+        "-c:v",
+        "libwebp",
+        "-lossless",
+        "0",
+        "-quality",
+        &quality.to_string(),
+    ]
+    .into_iter()
+    .map(OsString::from)
+    .chain([path.into()])
+    .collect();
+
+    let tool = "ffmpeg";
+    let output = cmd(tool, args)
+        .stdin_bytes(raw)
+        .stdout_null()
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => EncodeError::MissingTool(tool),
+            _ => EncodeError::IO(e),
+        })?;
+
+    if !output.status.success() {
+        return Err(EncodeError::Failed {
+            tool,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// When the screenshot of a manual record is taken. Replays and auto records always take it
+/// when they're saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenshotTiming {
+    /// When the recording starts, before the game moves on
+    Start,
+    /// When the recording stops
+    #[default]
+    Stop,
+    /// A fixed time after the recording starts
+    Offset,
+    /// Several frames during the recording, to choose from afterwards
+    Burst,
+}
+
+impl ScreenshotTiming {
+    pub const ALL: [ScreenshotTiming; 4] = [
+        ScreenshotTiming::Start,
+        ScreenshotTiming::Stop,
+        ScreenshotTiming::Offset,
+        ScreenshotTiming::Burst,
+    ];
+}
+
+impl Display for ScreenshotTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ScreenshotTiming::Start => "At start",
+            ScreenshotTiming::Stop => "At stop",
+            ScreenshotTiming::Offset => "After an offset",
+            ScreenshotTiming::Burst => "Burst",
+        })
+    }
+}
+
+/// Takes the screenshots of a recording according to the timing policy
+pub struct FrameGrabber {
+    frames: Arc<Mutex<VecDeque<RgbaImage>>>,
+    /// The latest frame is taken when finishing, as for bursts
+    final_frame: bool,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FrameGrabber {
+    pub fn start(process: &Process, config: &ScreenshotConfig) -> XCapResult<FrameGrabber> {
+        let frames = Arc::new(Mutex::new(VecDeque::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (delay, interval, count) = match config.timing {
+            ScreenshotTiming::Start => {
+                frames.lock().unwrap().push_back(process.capture_image()?);
+                return Ok(FrameGrabber {
+                    frames,
+                    final_frame: false,
+                    stop,
+                    thread: None,
+                });
+            }
+            ScreenshotTiming::Stop => {
+                return Ok(FrameGrabber {
+                    frames,
+                    final_frame: false,
+                    stop,
+                    thread: None,
+                });
+            }
+            ScreenshotTiming::Offset => (
+                Duration::from_millis(config.offset_ms.into()),
+                Duration::ZERO,
+                1,
+            ),
+            // The last frame of a burst is taken when it finishes
+            ScreenshotTiming::Burst => (
+                Duration::ZERO,
+                Duration::from_millis(config.burst_interval_ms.into()),
+                (config.burst_count as usize).saturating_sub(1).max(1),
+            ),
+        };
+        let final_frame = config.timing == ScreenshotTiming::Burst;
+
+        let thread = std::thread::spawn({
+            let process = process.clone();
+            let frames = frames.clone();
+            let stop = stop.clone();
+            move || {
+                let mut next = Instant::now() + delay;
+                let mut interval = interval;
+                let mut taken = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    if Instant::now() < next {
+                        std::thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                    match process.capture_image() {
+                        Ok(frame) => {
+                            // A full burst keeps every other frame and takes them half as
+                            // often, so the frames stay spread over the whole recording
+                            let mut frames = frames.lock().unwrap();
+                            frames.push_back(frame);
+                            if frames.len() > count {
+                                thin_out(&mut frames);
+                                interval *= 2;
+                            }
+                        }
+                        Err(e) => eprintln!("Failed to capture a frame: {}", e),
+                    }
+                    taken += 1;
+                    if interval.is_zero() && taken >= count {
+                        return;
+                    }
+                    next += interval;
+                }
+            }
+        });
+
+        Ok(FrameGrabber {
+            frames,
+            final_frame,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Stop grabbing and return the latest frame along with the earlier ones of a burst. The
+    /// frame is taken now if none was, as when the recording is shorter than the offset.
+    pub fn finish(mut self, process: &Process) -> XCapResult<(RgbaImage, Vec<RgbaImage>)> {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let mut frames: Vec<RgbaImage> = self.frames.lock().unwrap().drain(..).collect();
+        if self.final_frame {
+            match process.capture_image() {
+                Ok(frame) => return Ok((frame, frames)),
+                Err(e) => eprintln!("Failed to capture the last frame: {}", e),
+            }
+        }
+        let latest = match frames.pop() {
+            Some(frame) => frame,
+            None => process.capture_image()?,
+        };
+        Ok((latest, frames))
+    }
+}
+
+/// Drop every other frame, keeping the first
+fn thin_out<T>(frames: &mut VecDeque<T>) {
+    let mut index = 0;
+    frames.retain(|_| {
+        index += 1;
+        index % 2 == 1
+    });
+}
+
+impl Drop for FrameGrabber {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thinning_spreads_a_burst_over_the_recording() {
+        // Frames numbered by the interval they were taken at, the interval doubling when full
+        let count = 4;
+        let (mut frames, mut interval) = (VecDeque::new(), 1);
+        let mut time = 0;
+        while time <= 40 {
+            frames.push_back(time);
+            if frames.len() > count {
+                thin_out(&mut frames);
+                interval *= 2;
+            }
+            time += interval;
+        }
+        assert_eq!(frames, [0, 16, 32]);
+
+        let mut odd = VecDeque::from([1, 2, 3]);
+        thin_out(&mut odd);
+        assert_eq!(odd, [1, 3]);
+    }
+}
@@ -0,0 +1,836 @@
+use std::{collections::BTreeMap, path::PathBuf, str::FromStr};
+
+use global_hotkey::hotkey::HotKey;
+use iced::{
+    Alignment::Center,
+    Element,
+    Length::Fill,
+    keyboard::{self, key},
+    widget::{Column, Row, button, checkbox, pick_list, scrollable, text, text_input},
+};
+
+use crate::{
+    Message,
+    animation::AnimationFormat,
+    capture::CaptureBackend,
+    config::{Config, ConfigError},
+    encode::AudioFormat,
+    hotkey::{GHKMessage, find_conflicts, hotkey_from_key_press},
+    screenshot::{ScreenshotFormat, ScreenshotTiming},
+};
+
+/// Editable (unparsed) state of the settings page
+#[derive(Debug, Clone)]
+pub struct SettingsForm {
+    output_dir: String,
+    trim_enabled: bool,
+    trim_threshold_percent: String,
+    trim_min_duration_secs: String,
+    trim_fade_ms: String,
+    loudness_enabled: bool,
+    loudness_target_lufs: String,
+    background_enabled: bool,
+    background_strength: String,
+    background_floor: String,
+    capture_backend: CaptureBackend,
+    per_application: bool,
+    audio_format: AudioFormat,
+    mp3_vbr_quality: String,
+    opus_bitrate_kbps: String,
+    vorbis_quality: String,
+    flac_compression: String,
+    mono: bool,
+    sample_rate: String,
+    replay_enabled: bool,
+    replay_duration_secs: String,
+    vad_threshold_db: String,
+    vad_hangover_ms: String,
+    vad_min_utterance_ms: String,
+    vad_pre_roll_ms: String,
+    vad_max_utterance_secs: String,
+    toasts: bool,
+    desktop_notifications: bool,
+    sounds: bool,
+    sound_volume: String,
+    anki_url: String,
+    anki_audio_field: String,
+    anki_picture_field: String,
+    anki_animation_field: String,
+    anki_sentence_field: String,
+    anki_open_browser: bool,
+    text_hook_enabled: bool,
+    text_hook_poll_ms: String,
+    screenshot_format: ScreenshotFormat,
+    screenshot_max_width: String,
+    screenshot_max_height: String,
+    screenshot_quality: String,
+    strip_alpha: bool,
+    screenshot_timing: ScreenshotTiming,
+    screenshot_offset_ms: String,
+    burst_count: String,
+    burst_interval_ms: String,
+    crop_black_bars: bool,
+    black_threshold: String,
+    animation_enabled: bool,
+    animation_format: AnimationFormat,
+    animation_fps: String,
+    animation_max_width: String,
+    animation_max_secs: String,
+    animation_max_size_kb: String,
+    hotkeys: BTreeMap<GHKMessage, HotKey>,
+    /// The hotkey currently waiting for a key press
+    capturing: Option<GHKMessage>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SettingEdit {
+    OutputDir(String),
+    TrimEnabled(bool),
+    TrimThresholdPercent(String),
+    TrimMinDurationSecs(String),
+    TrimFadeMs(String),
+    LoudnessEnabled(bool),
+    LoudnessTargetLufs(String),
+    BackgroundEnabled(bool),
+    BackgroundStrength(String),
+    BackgroundFloor(String),
+    CaptureBackend(CaptureBackend),
+    PerApplication(bool),
+    AudioFormat(AudioFormat),
+    Mp3VbrQuality(String),
+    OpusBitrateKbps(String),
+    VorbisQuality(String),
+    FlacCompression(String),
+    Mono(bool),
+    SampleRate(String),
+    ReplayEnabled(bool),
+    ReplayDurationSecs(String),
+    VadThresholdDb(String),
+    VadHangoverMs(String),
+    VadMinUtteranceMs(String),
+    VadPreRollMs(String),
+    VadMaxUtteranceSecs(String),
+    Toasts(bool),
+    DesktopNotifications(bool),
+    Sounds(bool),
+    SoundVolume(String),
+    AnkiUrl(String),
+    AnkiAudioField(String),
+    AnkiPictureField(String),
+    AnkiAnimationField(String),
+    AnkiSentenceField(String),
+    AnkiOpenBrowser(bool),
+    TextHookEnabled(bool),
+    TextHookPollMs(String),
+    ScreenshotFormat(ScreenshotFormat),
+    ScreenshotMaxWidth(String),
+    ScreenshotMaxHeight(String),
+    ScreenshotQuality(String),
+    StripAlpha(bool),
+    ScreenshotTiming(ScreenshotTiming),
+    ScreenshotOffsetMs(String),
+    BurstCount(String),
+    BurstIntervalMs(String),
+    CropBlackBars(bool),
+    BlackThreshold(String),
+    AnimationEnabled(bool),
+    AnimationFormat(AnimationFormat),
+    AnimationFps(String),
+    AnimationMaxWidth(String),
+    AnimationMaxSecs(String),
+    AnimationMaxSizeKb(String),
+    CaptureHotkey(Option<GHKMessage>),
+    UnbindHotkey(GHKMessage),
+}
+
+impl SettingsForm {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            output_dir: config.output_dir.display().to_string(),
+            trim_enabled: config.trim.enabled,
+            trim_threshold_percent: config.trim.threshold_percent.to_string(),
+            trim_min_duration_secs: config.trim.min_duration_secs.to_string(),
+            trim_fade_ms: config.trim.fade_ms.to_string(),
+            loudness_enabled: config.loudness.enabled,
+            loudness_target_lufs: config.loudness.target_lufs.to_string(),
+            background_enabled: config.background.enabled,
+            background_strength: config.background.strength.to_string(),
+            background_floor: config.background.floor.to_string(),
+            capture_backend: config.audio.backend,
+            per_application: config.audio.per_application,
+            audio_format: config.audio.format,
+            mp3_vbr_quality: config.audio.mp3_vbr_quality.to_string(),
+            opus_bitrate_kbps: config.audio.opus_bitrate_kbps.to_string(),
+            vorbis_quality: config.audio.vorbis_quality.to_string(),
+            flac_compression: config.audio.flac_compression.to_string(),
+            mono: config.audio.mono,
+            sample_rate: config
+                .audio
+                .sample_rate
+                .map(|r| r.to_string())
+                .unwrap_or_default(),
+            replay_enabled: config.replay.enabled,
+            replay_duration_secs: config.replay.duration_secs.to_string(),
+            vad_threshold_db: config.vad.threshold_db.to_string(),
+            vad_hangover_ms: config.vad.hangover_ms.to_string(),
+            vad_min_utterance_ms: config.vad.min_utterance_ms.to_string(),
+            vad_pre_roll_ms: config.vad.pre_roll_ms.to_string(),
+            vad_max_utterance_secs: config.vad.max_utterance_secs.to_string(),
+            toasts: config.feedback.toasts,
+            desktop_notifications: config.feedback.desktop_notifications,
+            sounds: config.feedback.sounds,
+            sound_volume: config.feedback.sound_volume.to_string(),
+            anki_url: config.anki.url.clone(),
+            anki_audio_field: config.anki.audio_field.clone(),
+            anki_picture_field: config.anki.picture_field.clone(),
+            anki_animation_field: config.anki.animation_field.clone(),
+            anki_sentence_field: config.anki.sentence_field.clone(),
+            anki_open_browser: config.anki.open_browser,
+            text_hook_enabled: config.text_hook.enabled,
+            text_hook_poll_ms: config.text_hook.poll_ms.to_string(),
+            screenshot_format: config.screenshot.format,
+            screenshot_max_width: config
+                .screenshot
+                .max_width
+                .map(|w| w.to_string())
+                .unwrap_or_default(),
+            screenshot_max_height: config
+                .screenshot
+                .max_height
+                .map(|h| h.to_string())
+                .unwrap_or_default(),
+            screenshot_quality: config.screenshot.quality.to_string(),
+            strip_alpha: config.screenshot.strip_alpha,
+            screenshot_timing: config.screenshot.timing,
+            screenshot_offset_ms: config.screenshot.offset_ms.to_string(),
+            burst_count: config.screenshot.burst_count.to_string(),
+            burst_interval_ms: config.screenshot.burst_interval_ms.to_string(),
+            crop_black_bars: config.screenshot.crop_black_bars,
+            black_threshold: config.screenshot.black_threshold.to_string(),
+            animation_enabled: config.animation.enabled,
+            animation_format: config.animation.format,
+            animation_fps: config.animation.fps.to_string(),
+            animation_max_width: config.animation.max_width.to_string(),
+            animation_max_secs: config.animation.max_secs.to_string(),
+            animation_max_size_kb: config.animation.max_size_kb.to_string(),
+            hotkeys: config.hotkeys.clone(),
+            capturing: None,
+        }
+    }
+
+    pub fn edit(&mut self, edit: SettingEdit) {
+        match edit {
+            SettingEdit::OutputDir(v) => self.output_dir = v,
+            SettingEdit::TrimEnabled(v) => self.trim_enabled = v,
+            SettingEdit::TrimThresholdPercent(v) => self.trim_threshold_percent = v,
+            SettingEdit::TrimMinDurationSecs(v) => self.trim_min_duration_secs = v,
+            SettingEdit::TrimFadeMs(v) => self.trim_fade_ms = v,
+            SettingEdit::LoudnessEnabled(v) => self.loudness_enabled = v,
+            SettingEdit::LoudnessTargetLufs(v) => self.loudness_target_lufs = v,
+            SettingEdit::BackgroundEnabled(v) => self.background_enabled = v,
+            SettingEdit::BackgroundStrength(v) => self.background_strength = v,
+            SettingEdit::BackgroundFloor(v) => self.background_floor = v,
+            SettingEdit::CaptureBackend(v) => self.capture_backend = v,
+            SettingEdit::PerApplication(v) => self.per_application = v,
+            SettingEdit::AudioFormat(v) => self.audio_format = v,
+            SettingEdit::Mp3VbrQuality(v) => self.mp3_vbr_quality = v,
+            SettingEdit::OpusBitrateKbps(v) => self.opus_bitrate_kbps = v,
+            SettingEdit::VorbisQuality(v) => self.vorbis_quality = v,
+            SettingEdit::FlacCompression(v) => self.flac_compression = v,
+            SettingEdit::Mono(v) => self.mono = v,
+            SettingEdit::SampleRate(v) => self.sample_rate = v,
+            SettingEdit::ReplayEnabled(v) => self.replay_enabled = v,
+            SettingEdit::ReplayDurationSecs(v) => self.replay_duration_secs = v,
+            SettingEdit::VadThresholdDb(v) => self.vad_threshold_db = v,
+            SettingEdit::VadHangoverMs(v) => self.vad_hangover_ms = v,
+            SettingEdit::VadMinUtteranceMs(v) => self.vad_min_utterance_ms = v,
+            SettingEdit::VadPreRollMs(v) => self.vad_pre_roll_ms = v,
+            SettingEdit::VadMaxUtteranceSecs(v) => self.vad_max_utterance_secs = v,
+            SettingEdit::Toasts(v) => self.toasts = v,
+            SettingEdit::DesktopNotifications(v) => self.desktop_notifications = v,
+            SettingEdit::Sounds(v) => self.sounds = v,
+            SettingEdit::SoundVolume(v) => self.sound_volume = v,
+            SettingEdit::AnkiUrl(v) => self.anki_url = v,
+            SettingEdit::AnkiAudioField(v) => self.anki_audio_field = v,
+            SettingEdit::AnkiPictureField(v) => self.anki_picture_field = v,
+            SettingEdit::AnkiAnimationField(v) => self.anki_animation_field = v,
+            SettingEdit::AnkiSentenceField(v) => self.anki_sentence_field = v,
+            SettingEdit::AnkiOpenBrowser(v) => self.anki_open_browser = v,
+            SettingEdit::TextHookEnabled(v) => self.text_hook_enabled = v,
+            SettingEdit::TextHookPollMs(v) => self.text_hook_poll_ms = v,
+            SettingEdit::ScreenshotFormat(v) => self.screenshot_format = v,
+            SettingEdit::ScreenshotMaxWidth(v) => self.screenshot_max_width = v,
+            SettingEdit::ScreenshotMaxHeight(v) => self.screenshot_max_height = v,
+            SettingEdit::ScreenshotQuality(v) => self.screenshot_quality = v,
+            SettingEdit::StripAlpha(v) => self.strip_alpha = v,
+            SettingEdit::ScreenshotTiming(v) => self.screenshot_timing = v,
+            SettingEdit::ScreenshotOffsetMs(v) => self.screenshot_offset_ms = v,
+            SettingEdit::BurstCount(v) => self.burst_count = v,
+            SettingEdit::BurstIntervalMs(v) => self.burst_interval_ms = v,
+            SettingEdit::CropBlackBars(v) => self.crop_black_bars = v,
+            SettingEdit::BlackThreshold(v) => self.black_threshold = v,
+            SettingEdit::AnimationEnabled(v) => self.animation_enabled = v,
+            SettingEdit::AnimationFormat(v) => self.animation_format = v,
+            SettingEdit::AnimationFps(v) => self.animation_fps = v,
+            SettingEdit::AnimationMaxWidth(v) => self.animation_max_width = v,
+            SettingEdit::AnimationMaxSecs(v) => self.animation_max_secs = v,
+            SettingEdit::AnimationMaxSizeKb(v) => self.animation_max_size_kb = v,
+            SettingEdit::CaptureHotkey(msg) => self.capturing = msg,
+            SettingEdit::UnbindHotkey(msg) => {
+                self.hotkeys.remove(&msg);
+                self.capturing = None;
+            }
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capturing.is_some()
+    }
+
+    /// Feed a key press to the hotkey currently being captured, Escape cancels the capture.
+    pub fn capture_key(&mut self, event: keyboard::Event) {
+        let Some(msg) = self.capturing else {
+            return;
+        };
+        let keyboard::Event::KeyPressed {
+            key,
+            physical_key,
+            modifiers,
+            ..
+        } = event
+        else {
+            return;
+        };
+
+        if key == keyboard::Key::Named(key::Named::Escape) && modifiers.is_empty() {
+            self.capturing = None;
+        } else if let Some(hotkey) = hotkey_from_key_press(&physical_key, modifiers) {
+            self.hotkeys.insert(msg, hotkey);
+            self.capturing = None;
+        }
+    }
+
+    /// Parse the form on top of `base`, returning a validated config.
+    pub fn to_config(&self, base: &Config) -> Result<Config, ConfigError> {
+        fn parse<T: FromStr>(field: &'static str, value: &str) -> Result<T, ConfigError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value
+                .trim()
+                .parse()
+                .map_err(|e: T::Err| ConfigError::Invalid {
+                    field,
+                    reason: format!("{:?}: {}", value, e),
+                })
+        }
+
+        let mut config = base.clone();
+        config.output_dir = PathBuf::from(self.output_dir.trim());
+        config.trim.enabled = self.trim_enabled;
+        config.trim.threshold_percent =
+            parse("trim.threshold_percent", &self.trim_threshold_percent)?;
+        config.trim.min_duration_secs =
+            parse("trim.min_duration_secs", &self.trim_min_duration_secs)?;
+        config.trim.fade_ms = parse("trim.fade_ms", &self.trim_fade_ms)?;
+        config.loudness.enabled = self.loudness_enabled;
+        config.loudness.target_lufs = parse("loudness.target_lufs", &self.loudness_target_lufs)?;
+        config.background.enabled = self.background_enabled;
+        config.background.strength = parse("background.strength", &self.background_strength)?;
+        config.background.floor = parse("background.floor", &self.background_floor)?;
+        config.audio.backend = self.capture_backend;
+        config.audio.per_application = self.per_application;
+        config.audio.format = self.audio_format;
+        config.audio.mp3_vbr_quality = parse("audio.mp3_vbr_quality", &self.mp3_vbr_quality)?;
+        config.audio.opus_bitrate_kbps = parse("audio.opus_bitrate_kbps", &self.opus_bitrate_kbps)?;
+        config.audio.vorbis_quality = parse("audio.vorbis_quality", &self.vorbis_quality)?;
+        config.audio.flac_compression = parse("audio.flac_compression", &self.flac_compression)?;
+        config.audio.mono = self.mono;
+        config.audio.sample_rate = match self.sample_rate.trim() {
+            "" => None,
+            rate => Some(parse("audio.sample_rate", rate)?),
+        };
+        config.replay.enabled = self.replay_enabled;
+        config.replay.duration_secs = parse("replay.duration_secs", &self.replay_duration_secs)?;
+        config.vad.threshold_db = parse("vad.threshold_db", &self.vad_threshold_db)?;
+        config.vad.hangover_ms = parse("vad.hangover_ms", &self.vad_hangover_ms)?;
+        config.vad.min_utterance_ms = parse("vad.min_utterance_ms", &self.vad_min_utterance_ms)?;
+        config.vad.pre_roll_ms = parse("vad.pre_roll_ms", &self.vad_pre_roll_ms)?;
+        config.vad.max_utterance_secs =
+            parse("vad.max_utterance_secs", &self.vad_max_utterance_secs)?;
+        config.feedback.toasts = self.toasts;
+        config.feedback.desktop_notifications = self.desktop_notifications;
+        config.feedback.sounds = self.sounds;
+        config.feedback.sound_volume = parse("feedback.sound_volume", &self.sound_volume)?;
+        config.anki.url = self.anki_url.trim().to_string();
+        config.anki.audio_field = self.anki_audio_field.trim().to_string();
+        config.anki.picture_field = self.anki_picture_field.trim().to_string();
+        config.anki.animation_field = self.anki_animation_field.trim().to_string();
+        config.anki.sentence_field = self.anki_sentence_field.trim().to_string();
+        config.anki.open_browser = self.anki_open_browser;
+        config.text_hook.enabled = self.text_hook_enabled;
+        config.text_hook.poll_ms = parse("text_hook.poll_ms", &self.text_hook_poll_ms)?;
+        config.screenshot.format = self.screenshot_format;
+        config.screenshot.max_width = match self.screenshot_max_width.trim() {
+            "" => None,
+            width => Some(parse("screenshot.max_width", width)?),
+        };
+        config.screenshot.max_height = match self.screenshot_max_height.trim() {
+            "" => None,
+            height => Some(parse("screenshot.max_height", height)?),
+        };
+        config.screenshot.quality = parse("screenshot.quality", &self.screenshot_quality)?;
+        config.screenshot.strip_alpha = self.strip_alpha;
+        config.screenshot.timing = self.screenshot_timing;
+        config.screenshot.offset_ms = parse("screenshot.offset_ms", &self.screenshot_offset_ms)?;
+        config.screenshot.burst_count = parse("screenshot.burst_count", &self.burst_count)?;
+        config.screenshot.burst_interval_ms =
+            parse("screenshot.burst_interval_ms", &self.burst_interval_ms)?;
+        config.screenshot.crop_black_bars = self.crop_black_bars;
+        config.screenshot.black_threshold =
+            parse("screenshot.black_threshold", &self.black_threshold)?;
+        config.animation.enabled = self.animation_enabled;
+        config.animation.format = self.animation_format;
+        config.animation.fps = parse("animation.fps", &self.animation_fps)?;
+        config.animation.max_width = parse("animation.max_width", &self.animation_max_width)?;
+        config.animation.max_secs = parse("animation.max_secs", &self.animation_max_secs)?;
+        config.animation.max_size_kb = parse("animation.max_size_kb", &self.animation_max_size_kb)?;
+        config.hotkeys = self.hotkeys.clone();
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn view<'a>(
+        &'a self,
+        hotkey_errors: &'a BTreeMap<GHKMessage, String>,
+    ) -> Element<'a, Message> {
+        fn field<'a>(
+            label: &'a str,
+            value: &'a str,
+            on_input: impl Fn(String) -> SettingEdit + 'a,
+        ) -> Element<'a, Message> {
+            Row::new()
+                .push(text(label).size(14).width(200))
+                .push(
+                    text_input(label, value)
+                        .size(14)
+                        .on_input(move |v| Message::SettingEdited(on_input(v))),
+                )
+                .align_y(Center)
+                .spacing(10)
+                .into()
+        }
+
+        let mut c = Column::new()
+            .spacing(8)
+            .push(text("General").size(20))
+            .push(field(
+                "Output Folder",
+                &self.output_dir,
+                SettingEdit::OutputDir,
+            ))
+            .push(text("Background Suppression").size(20))
+            .push(
+                checkbox(self.background_enabled)
+                    .label("Suppress background music under voice")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::BackgroundEnabled(v))),
+            )
+            .push(field(
+                "Suppression strength",
+                &self.background_strength,
+                SettingEdit::BackgroundStrength,
+            ))
+            .push(field(
+                "Kept signal (0-1)",
+                &self.background_floor,
+                SettingEdit::BackgroundFloor,
+            ))
+            .push(text("Trimming").size(20))
+            .push(
+                checkbox(self.trim_enabled)
+                    .label("Trim silence")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::TrimEnabled(v))),
+            )
+            .push(field(
+                "Silence threshold (%)",
+                &self.trim_threshold_percent,
+                SettingEdit::TrimThresholdPercent,
+            ))
+            .push(field(
+                "Minimum duration (s)",
+                &self.trim_min_duration_secs,
+                SettingEdit::TrimMinDurationSecs,
+            ))
+            .push(field(
+                "Fade in/out (ms)",
+                &self.trim_fade_ms,
+                SettingEdit::TrimFadeMs,
+            ))
+            .push(text("Loudness").size(20))
+            .push(
+                checkbox(self.loudness_enabled)
+                    .label("Normalize loudness (EBU R128)")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::LoudnessEnabled(v))),
+            )
+            .push(field(
+                "Target loudness (LUFS)",
+                &self.loudness_target_lufs,
+                SettingEdit::LoudnessTargetLufs,
+            ))
+            .push(text("Audio").size(20))
+            .push(
+                Row::new()
+                    .push(text("Capture backend").size(14).width(200))
+                    .push(
+                        pick_list(CaptureBackend::ALL, Some(self.capture_backend), |v| {
+                            Message::SettingEdited(SettingEdit::CaptureBackend(v))
+                        })
+                        .text_size(14),
+                    )
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .push(
+                checkbox(self.per_application)
+                    .label("Record only the selected application")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::PerApplication(v))),
+            )
+            .push(
+                Row::new()
+                    .push(text("Audio format").size(14).width(200))
+                    .push(
+                        pick_list(AudioFormat::ALL, Some(self.audio_format), |v| {
+                            Message::SettingEdited(SettingEdit::AudioFormat(v))
+                        })
+                        .text_size(14),
+                    )
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .push(match self.audio_format {
+                AudioFormat::Mp3 => Some(field(
+                    "MP3 VBR quality (0-9)",
+                    &self.mp3_vbr_quality,
+                    SettingEdit::Mp3VbrQuality,
+                )),
+                AudioFormat::Opus => Some(field(
+                    "Opus bitrate (kbps)",
+                    &self.opus_bitrate_kbps,
+                    SettingEdit::OpusBitrateKbps,
+                )),
+                AudioFormat::Vorbis => Some(field(
+                    "Vorbis quality (-1-10)",
+                    &self.vorbis_quality,
+                    SettingEdit::VorbisQuality,
+                )),
+                AudioFormat::Flac => Some(field(
+                    "FLAC compression (0-8)",
+                    &self.flac_compression,
+                    SettingEdit::FlacCompression,
+                )),
+                AudioFormat::Wav => None,
+            })
+            .push(
+                checkbox(self.mono)
+                    .label("Downmix to mono")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::Mono(v))),
+            )
+            .push(field(
+                "Sample rate (Hz, empty to keep)",
+                &self.sample_rate,
+                SettingEdit::SampleRate,
+            ))
+            .push(text("Replay").size(20))
+            .push(
+                checkbox(self.replay_enabled)
+                    .label("Keep recent audio of the selected application")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::ReplayEnabled(v))),
+            )
+            .push(field(
+                "Replay length (s)",
+                &self.replay_duration_secs,
+                SettingEdit::ReplayDurationSecs,
+            ))
+            .push(text("Auto Recording").size(20))
+            .push(field(
+                "Voice threshold (dBFS)",
+                &self.vad_threshold_db,
+                SettingEdit::VadThresholdDb,
+            ))
+            .push(field(
+                "Pause before line ends (ms)",
+                &self.vad_hangover_ms,
+                SettingEdit::VadHangoverMs,
+            ))
+            .push(field(
+                "Minimum line length (ms)",
+                &self.vad_min_utterance_ms,
+                SettingEdit::VadMinUtteranceMs,
+            ))
+            .push(field(
+                "Audio kept before a line (ms)",
+                &self.vad_pre_roll_ms,
+                SettingEdit::VadPreRollMs,
+            ))
+            .push(field(
+                "Maximum line length (s)",
+                &self.vad_max_utterance_secs,
+                SettingEdit::VadMaxUtteranceSecs,
+            ))
+            .push(text("Feedback").size(20))
+            .push(
+                checkbox(self.toasts)
+                    .label("Show success notifications in the app (errors are always shown)")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::Toasts(v))),
+            )
+            .push(
+                checkbox(self.desktop_notifications)
+                    .label("Show desktop notifications")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::DesktopNotifications(v))),
+            )
+            .push(
+                checkbox(self.sounds)
+                    .label("Play sounds")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::Sounds(v))),
+            )
+            .push(field(
+                "Sound volume (0-1)",
+                &self.sound_volume,
+                SettingEdit::SoundVolume,
+            ))
+            .push(text("Text Hook").size(20))
+            .push(
+                checkbox(self.text_hook_enabled)
+                    .label("Attach the sentence copied to the clipboard to records")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::TextHookEnabled(v))),
+            )
+            .push(field(
+                "Clipboard check interval (ms)",
+                &self.text_hook_poll_ms,
+                SettingEdit::TextHookPollMs,
+            ))
+            .push(text("Screenshots").size(20))
+            .push(
+                Row::new()
+                    .push(text("Screenshot format").size(14).width(200))
+                    .push(
+                        pick_list(ScreenshotFormat::ALL, Some(self.screenshot_format), |v| {
+                            Message::SettingEdited(SettingEdit::ScreenshotFormat(v))
+                        })
+                        .text_size(14),
+                    )
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .push(
+                matches!(
+                    self.screenshot_format,
+                    ScreenshotFormat::Jpeg | ScreenshotFormat::Webp | ScreenshotFormat::Avif
+                )
+                .then(|| {
+                    field(
+                        "Quality (1-100)",
+                        &self.screenshot_quality,
+                        SettingEdit::ScreenshotQuality,
+                    )
+                }),
+            )
+            .push(field(
+                "Max width (px, empty for any)",
+                &self.screenshot_max_width,
+                SettingEdit::ScreenshotMaxWidth,
+            ))
+            .push(field(
+                "Max height (px, empty for any)",
+                &self.screenshot_max_height,
+                SettingEdit::ScreenshotMaxHeight,
+            ))
+            .push(
+                checkbox(self.strip_alpha)
+                    .label("Drop the alpha channel")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::StripAlpha(v))),
+            )
+            .push(
+                Row::new()
+                    .push(text("Timing").size(14).width(200))
+                    .push(
+                        pick_list(ScreenshotTiming::ALL, Some(self.screenshot_timing), |v| {
+                            Message::SettingEdited(SettingEdit::ScreenshotTiming(v))
+                        })
+                        .text_size(14),
+                    )
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .push(match self.screenshot_timing {
+                ScreenshotTiming::Offset => Some(field(
+                    "Offset from start (ms)",
+                    &self.screenshot_offset_ms,
+                    SettingEdit::ScreenshotOffsetMs,
+                )),
+                ScreenshotTiming::Burst => Some(field(
+                    "Frames kept (2-10)",
+                    &self.burst_count,
+                    SettingEdit::BurstCount,
+                )),
+                ScreenshotTiming::Start | ScreenshotTiming::Stop => None,
+            })
+            .push(
+                (self.screenshot_timing == ScreenshotTiming::Burst).then(|| {
+                    field(
+                        "Frame interval (ms)",
+                        &self.burst_interval_ms,
+                        SettingEdit::BurstIntervalMs,
+                    )
+                }),
+            )
+            .push(
+                checkbox(self.crop_black_bars)
+                    .label("Cut black bars around the picture")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::CropBlackBars(v))),
+            )
+            .push(field(
+                "Black level (0-255)",
+                &self.black_threshold,
+                SettingEdit::BlackThreshold,
+            ))
+            .push(text("Animation").size(20))
+            .push(
+                checkbox(self.animation_enabled)
+                    .label("Save an animation of the window with records (needs ffmpeg)")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::AnimationEnabled(v))),
+            )
+            .push(
+                Row::new()
+                    .push(text("Animation format").size(14).width(200))
+                    .push(
+                        pick_list(AnimationFormat::ALL, Some(self.animation_format), |v| {
+                            Message::SettingEdited(SettingEdit::AnimationFormat(v))
+                        })
+                        .text_size(14),
+                    )
+                    .align_y(Center)
+                    .spacing(10),
+            )
+            .push(field(
+                "Frame rate (1-30)",
+                &self.animation_fps,
+                SettingEdit::AnimationFps,
+            ))
+            .push(field(
+                "Max width (px)",
+                &self.animation_max_width,
+                SettingEdit::AnimationMaxWidth,
+            ))
+            .push(field(
+                "Max duration (s)",
+                &self.animation_max_secs,
+                SettingEdit::AnimationMaxSecs,
+            ))
+            .push(field(
+                "Max file size (KiB)",
+                &self.animation_max_size_kb,
+                SettingEdit::AnimationMaxSizeKb,
+            ))
+            .push(text("Anki").size(20))
+            .push(field(
+                "AnkiConnect URL",
+                &self.anki_url,
+                SettingEdit::AnkiUrl,
+            ))
+            .push(field(
+                "Audio field",
+                &self.anki_audio_field,
+                SettingEdit::AnkiAudioField,
+            ))
+            .push(field(
+                "Picture field",
+                &self.anki_picture_field,
+                SettingEdit::AnkiPictureField,
+            ))
+            .push(field(
+                "Animation field",
+                &self.anki_animation_field,
+                SettingEdit::AnkiAnimationField,
+            ))
+            .push(field(
+                "Sentence field",
+                &self.anki_sentence_field,
+                SettingEdit::AnkiSentenceField,
+            ))
+            .push(
+                checkbox(self.anki_open_browser)
+                    .label("Show the note in Anki's browser")
+                    .text_size(14)
+                    .on_toggle(|v| Message::SettingEdited(SettingEdit::AnkiOpenBrowser(v))),
+            )
+            .push(text("Hotkeys").size(20));
+
+        let conflicts = find_conflicts(&self.hotkeys);
+        for msg in GHKMessage::ALL {
+            let key = self.hotkeys.get(&msg);
+            let capture_button = if self.capturing == Some(msg) {
+                button(text("Press a key combination (Esc to cancel)").size(14))
+                    .on_press(Message::SettingEdited(SettingEdit::CaptureHotkey(None)))
+            } else {
+                let label = key.map_or_else(|| "Unbound".to_string(), HotKey::to_string);
+                button(text(label).size(14))
+                    .on_press(Message::SettingEdited(SettingEdit::CaptureHotkey(Some(
+                        msg,
+                    ))))
+                    .style(button::secondary)
+            };
+            let unbind_button = key.map(|_| {
+                button(text("Unbind").size(14))
+                    .on_press(Message::SettingEdited(SettingEdit::UnbindHotkey(msg)))
+                    .style(button::text)
+            });
+
+            let error = if conflicts.iter().any(|(_, msgs)| msgs.contains(&msg)) {
+                Some(String::from("Bound to multiple actions"))
+            } else {
+                hotkey_errors.get(&msg).cloned()
+            };
+
+            c = c.push(
+                Row::new()
+                    .push(text(msg.label()).size(14).width(200))
+                    .push(capture_button)
+                    .push(unbind_button)
+                    .push(error.map(|e| text(e).size(12).style(text::danger)))
+                    .align_y(Center)
+                    .spacing(10),
+            );
+        }
+
+        // The buttons stay reachable below the scrolled fields
+        Column::new()
+            .push(scrollable(c).height(Fill))
+            .push(
+                Row::new()
+                    .spacing(10)
+                    .push(button("Save").on_press(Message::SaveSettings))
+                    .push(
+                        button("Revert")
+                            .on_press(Message::RevertSettings)
+                            .style(button::secondary),
+                    ),
+            )
+            .spacing(10)
+            .into()
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+
+use crate::{
+    clipboard,
+    metadata::{RecordKind, RecordMetadata},
+};
+
+/// Number of sentences kept in the timeline
+const TIMELINE_LEN: usize = 200;
+/// How long after an auto record starts its sentence may still arrive, as text hookers copy the
+/// line about when the voice starts
+const AUTO_GRACE: Duration = Duration::from_secs(1);
+/// Longest wait between clipboard reads while they fail
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+/// How often a waiting watcher checks whether it should stop
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A line of text received from the text hooker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sentence {
+    pub text: String,
+    pub received_at: DateTime<Local>,
+}
+
+/// Watches the clipboard for the sentences a text hooker (Agent, Textractor, ...) copies, keeping
+/// a timeline of them so each record can be paired with the line shown while it was captured.
+pub struct TextHook {
+    timeline: Arc<Mutex<VecDeque<Sentence>>>,
+    /// Why the clipboard can't be read currently, reads being retried
+    error: Arc<Mutex<Option<String>>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TextHook {
+    pub fn start(poll_interval: Duration) -> std::io::Result<TextHook> {
+        let timeline = Arc::new(Mutex::new(VecDeque::new()));
+        let error = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("text-hook".into())
+            .spawn({
+                let timeline = timeline.clone();
+                let error = error.clone();
+                let stop = stop.clone();
+                move || {
+                    watch(
+                        &timeline,
+                        &error,
+                        &stop,
+                        poll_interval,
+                        clipboard::read_text,
+                    )
+                }
+            })?;
+
+        Ok(TextHook {
+            timeline,
+            error,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Why the clipboard can't be read, `None` while it's watched fine
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().unwrap().clone()
+    }
+
+    /// The most recently received sentence
+    pub fn current(&self) -> Option<Sentence> {
+        self.timeline.lock().unwrap().back().cloned()
+    }
+
+    /// The sentence current at `time`
+    pub fn sentence_at(&self, time: DateTime<Local>) -> Option<Sentence> {
+        let timeline = self.timeline.lock().unwrap();
+        timeline
+            .iter()
+            .rev()
+            .find(|s| s.received_at <= time)
+            .cloned()
+    }
+
+    /// The sentence a record is about. Manual records and replays are saved after the line was
+    /// heard, so it's the one current when the capture ended. Auto records may end after the
+    /// next line was shown, so it's the one current when the voice started.
+    pub fn sentence_for(&self, metadata: &RecordMetadata) -> Option<Sentence> {
+        let time = match metadata.kind {
+            RecordKind::Manual | RecordKind::Replay => metadata.ended_at,
+            RecordKind::Auto => (metadata.started_at + AUTO_GRACE).min(metadata.ended_at),
+        };
+        self.sentence_at(time)
+    }
+}
+
+impl Drop for TextHook {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Poll the clipboard text, adding every new one to the timeline. Failed reads are retried less
+/// and less often, until the clipboard can be read again.
+fn watch<E: std::fmt::Display>(
+    timeline: &Mutex<VecDeque<Sentence>>,
+    error: &Mutex<Option<String>>,
+    stop: &AtomicBool,
+    poll_interval: Duration,
+    mut read_text: impl FnMut() -> Result<Option<String>, E>,
+) {
+    let mut last = None;
+    let mut wait = poll_interval;
+    while !stop.load(Ordering::Relaxed) {
+        match read_text() {
+            Ok(text) => {
+                if error.lock().unwrap().take().is_some() {
+                    eprintln!("Watching the clipboard again");
+                }
+                wait = poll_interval;
+                if let Some(text) = text
+                    && last.as_ref() != Some(&text)
+                {
+                    last = Some(text.clone());
+                    let mut timeline = timeline.lock().unwrap();
+                    timeline.push_back(Sentence {
+                        text,
+                        received_at: Local::now(),
+                    });
+                    if timeline.len() > TIMELINE_LEN {
+                        timeline.pop_front();
+                    }
+                }
+            }
+            Err(e) => {
+                let e = e.to_string();
+                let mut error = error.lock().unwrap();
+                if error.as_ref() != Some(&e) {
+                    eprintln!("Failed to read the clipboard, retrying: {}", e);
+                    *error = Some(e);
+                }
+                wait = (wait * 2).min(MAX_BACKOFF.max(poll_interval));
+            }
+        }
+
+        // Stopping doesn't wait for a long backoff to end
+        let until = Instant::now() + wait;
+        while !stop.load(Ordering::Relaxed) && Instant::now() < until {
+            std::thread::sleep((until - Instant::now()).min(STOP_CHECK_INTERVAL));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_retries_after_errors() {
+        let timeline = Arc::new(Mutex::new(VecDeque::new()));
+        let error = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut reads = VecDeque::from([
+            Err("no clipboard"),
+            Err("no clipboard"),
+            Ok(Some("line".to_string())),
+        ]);
+        let thread = std::thread::spawn({
+            let (timeline, error, stop) = (timeline.clone(), error.clone(), stop.clone());
+            move || {
+                let read_text = move || reads.pop_front().unwrap_or(Ok(None));
+                watch(
+                    &timeline,
+                    &error,
+                    &stop,
+                    Duration::from_millis(1),
+                    read_text,
+                )
+            }
+        });
+
+        let started = Instant::now();
+        while timeline.lock().unwrap().is_empty() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        stop.store(true, Ordering::Relaxed);
+        thread.join().unwrap();
+
+        let timeline = timeline.lock().unwrap();
+        assert_eq!(
+            timeline.iter().map(|s| &s.text).collect::<Vec<_>>(),
+            ["line"]
+        );
+        assert_eq!(*error.lock().unwrap(), None);
+    }
+}
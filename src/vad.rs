@@ -0,0 +1,247 @@
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{capture::PcmFormat, config::VadConfig, dsp::rms};
+
+/// Length of the frames the energy is measured on
+const FRAME: Duration = Duration::from_millis(20);
+
+/// Splits a continuous PCM stream into utterances using an energy detector with hangover.
+///
+/// A frame is voiced when its RMS level is above the threshold. An utterance starts at the first
+/// voiced frame (including some pre-roll before it) and ends once no voiced frame has been seen
+/// for the hangover duration, so short pauses between words don't split a line.
+pub struct Segmenter {
+    threshold: f32,
+    frame_len: usize,
+    hangover_frames: usize,
+    min_voiced_frames: usize,
+    max_len: usize,
+    pre_roll_len: usize,
+
+    /// Samples not yet making up a whole frame
+    pending: Vec<f32>,
+    /// Most recent unvoiced audio, prepended to the next utterance
+    pre_roll: VecDeque<f32>,
+    utterance: Option<Utterance>,
+}
+
+/// A detected voice line
+pub struct Segment {
+    pub samples: Vec<f32>,
+    /// Length of the unvoiced audio before the line at the start of `samples`, which only has
+    /// the background in it
+    pub pre_roll: usize,
+}
+
+struct Utterance {
+    samples: Vec<f32>,
+    pre_roll: usize,
+    voiced_frames: usize,
+    silent_frames: usize,
+}
+
+impl Segmenter {
+    pub fn new(format: PcmFormat, config: &VadConfig) -> Self {
+        let frame_len = format.samples_in(FRAME).max(1);
+        let frames_in = |ms: u32| (ms as usize).div_ceil(FRAME.as_millis() as usize);
+        Segmenter {
+            threshold: 10f32.powf(config.threshold_db / 20.0),
+            frame_len,
+            hangover_frames: frames_in(config.hangover_ms).max(1),
+            min_voiced_frames: frames_in(config.min_utterance_ms),
+            max_len: format.samples_in(Duration::from_secs(config.max_utterance_secs as u64)),
+            pre_roll_len: format.samples_in(Duration::from_millis(config.pre_roll_ms as u64)),
+            pending: Vec::new(),
+            pre_roll: VecDeque::new(),
+            utterance: None,
+        }
+    }
+
+    /// Feed captured samples, returning the utterances which ended within them.
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Segment> {
+        self.pending.extend_from_slice(samples);
+        let pending = std::mem::take(&mut self.pending);
+
+        let mut finished = Vec::new();
+        let mut frames = pending.chunks_exact(self.frame_len);
+        for frame in frames.by_ref() {
+            finished.extend(self.push_frame(frame));
+        }
+        self.pending = frames.remainder().to_vec();
+        finished
+    }
+
+    /// End the current utterance, if any, e.g. when the capture stops.
+    pub fn flush(&mut self) -> Option<Segment> {
+        let utterance = self.utterance.take()?;
+        self.finish(utterance)
+    }
+
+    fn push_frame(&mut self, frame: &[f32]) -> Option<Segment> {
+        let voiced = rms(frame) >= self.threshold;
+
+        let Some(utterance) = &mut self.utterance else {
+            if voiced {
+                let mut samples: Vec<f32> = self.pre_roll.drain(..).collect();
+                let pre_roll = samples.len();
+                samples.extend_from_slice(frame);
+                self.utterance = Some(Utterance {
+                    samples,
+                    pre_roll,
+                    voiced_frames: 1,
+                    silent_frames: 0,
+                });
+            } else {
+                self.pre_roll.extend(frame);
+                let excess = self.pre_roll.len().saturating_sub(self.pre_roll_len);
+                self.pre_roll.drain(..excess);
+            }
+            return None;
+        };
+
+        utterance.samples.extend_from_slice(frame);
+        if voiced {
+            utterance.voiced_frames += 1;
+            utterance.silent_frames = 0;
+        } else {
+            utterance.silent_frames += 1;
+        }
+
+        if utterance.silent_frames >= self.hangover_frames
+            || utterance.samples.len() >= self.max_len
+        {
+            let utterance = self.utterance.take()?;
+            return self.finish(utterance);
+        }
+        None
+    }
+
+    /// Drop the trailing hangover silence, and utterances too short to be a voice line
+    fn finish(&self, mut utterance: Utterance) -> Option<Segment> {
+        if utterance.voiced_frames < self.min_voiced_frames {
+            return None;
+        }
+        let silent_len = utterance.silent_frames * self.frame_len;
+        let keep = silent_len.min(self.pre_roll_len);
+        let len = utterance.samples.len() - silent_len + keep;
+        utterance.samples.truncate(len);
+        Some(Segment {
+            samples: utterance.samples,
+            pre_roll: utterance.pre_roll,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One sample per millisecond, so lengths below are in ms
+    const MONO_1K: PcmFormat = PcmFormat {
+        sample_rate: 1000,
+        channels: 1,
+    };
+
+    fn config() -> VadConfig {
+        VadConfig {
+            threshold_db: -40.0,
+            hangover_ms: 100,
+            min_utterance_ms: 60,
+            pre_roll_ms: 40,
+            max_utterance_secs: 1,
+        }
+    }
+
+    /// Square wave, whose RMS level is `amplitude`
+    fn tone(amplitude: f32, ms: usize) -> Vec<f32> {
+        (0..ms)
+            .map(|i| if i % 2 == 0 { amplitude } else { -amplitude })
+            .collect()
+    }
+
+    fn silence(ms: usize) -> Vec<f32> {
+        vec![0.0; ms]
+    }
+
+    /// Feed the parts in odd-sized chunks, then flush
+    fn segment(config: &VadConfig, parts: &[Vec<f32>]) -> Vec<Segment> {
+        let mut segmenter = Segmenter::new(MONO_1K, config);
+        let samples = parts.concat();
+        let mut segments: Vec<_> = samples
+            .chunks(7)
+            .flat_map(|chunk| segmenter.push(chunk))
+            .collect();
+        segments.extend(segmenter.flush());
+        segments
+    }
+
+    #[test]
+    fn quiet_audio_is_not_voice() {
+        // -50 dBFS, under the threshold
+        let segments = segment(&config(), &[tone(0.003, 500), silence(200)]);
+        assert!(segments.is_empty());
+    }
+
+    #[test]
+    fn short_pauses_dont_split_a_line() {
+        let segments = segment(
+            &config(),
+            &[
+                silence(100),
+                tone(0.5, 200),
+                silence(60),
+                tone(0.5, 200),
+                silence(200),
+            ],
+        );
+        assert_eq!(segments.len(), 1);
+        // Pre-roll, both parts and the pause, and only the pre-roll length of the trailing silence
+        assert_eq!(segments[0].samples.len(), 40 + 460 + 40);
+    }
+
+    #[test]
+    fn long_pauses_split_lines() {
+        let segments = segment(
+            &config(),
+            &[tone(0.5, 200), silence(200), tone(0.5, 200), silence(200)],
+        );
+        assert_eq!(segments.len(), 2);
+    }
+
+    #[test]
+    fn short_utterances_are_dropped() {
+        let segments = segment(
+            &config(),
+            &[silence(100), tone(0.5, 40), silence(200), tone(0.5, 100)],
+        );
+        // Only the last one, ended by the flush
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].samples.len(), 40 + 100);
+    }
+
+    #[test]
+    fn long_utterances_are_split() {
+        let segments = segment(&config(), &[tone(0.5, 2500)]);
+        let lens: Vec<_> = segments.iter().map(|s| s.samples.len()).collect();
+        assert_eq!(lens, [1000, 1000, 500]);
+    }
+
+    #[test]
+    fn pre_roll_is_kept() {
+        let segments = segment(&config(), &[silence(300), tone(0.5, 200), silence(300)]);
+        assert_eq!(segments.len(), 1);
+        let segment = &segments[0];
+        assert_eq!(segment.pre_roll, 40);
+        assert!(segment.samples[..40].iter().all(|&s| s == 0.0));
+        assert_eq!(segment.samples[40], 0.5);
+    }
+
+    #[test]
+    fn trailing_silence_is_cut() {
+        let segments = segment(&config(), &[tone(0.5, 200), silence(500)]);
+        assert_eq!(segments.len(), 1);
+        let samples = &segments[0].samples;
+        assert_eq!(samples.len(), 200 + 40);
+        assert!(samples[200..].iter().all(|&s| s == 0.0));
+    }
+}